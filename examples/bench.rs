@@ -1,12 +1,9 @@
-use std::{fs::File, io::Write, process::Command};
-
 use evalexpr::{build_operator_tree, context_map};
-use libloading::{Library, Symbol};
-use math_expr::Ast;
-use ocl::ProQue;
+use math_expr::{
+    Ast, BatchEvaluation, BytecodeExpression, CompiledExpression, JitExpression, WasmExpression,
+};
 use rayon::prelude::*;
 use serde::{Serialize, Serializer};
-use wasmer::{imports, Instance, Module, Store, Value};
 
 trait BenchComputation {
     fn new() -> Self;
@@ -25,6 +22,10 @@ struct OutputRow {
     #[serde(serialize_with = "serialize_f64")]
     dylib: f64,
     #[serde(serialize_with = "serialize_f64")]
+    jit: f64,
+    #[serde(serialize_with = "serialize_f64")]
+    vm: f64,
+    #[serde(serialize_with = "serialize_f64")]
     native: f64,
 }
 
@@ -58,6 +59,8 @@ fn main() {
     let mut evalexpr_expression = EvalexprExpression::new();
     let mut wasmer_expression = WasmerExpression::new();
     let mut dylib_expression = DylibExpression::new();
+    let mut jit_expression = CraneliftExpression::new();
+    let mut vm_expression = VmExpression::new();
     let mut native_expression = NativeExpression::new();
 
     for mult in [1, 16, 32, 64] {
@@ -73,6 +76,8 @@ fn main() {
             time_it(|| evalexpr_expression.compute(&numbers_a, &numbers_b));
         let (wasmer, wasmer_result) = time_it(|| wasmer_expression.compute(&numbers_a, &numbers_b));
         let (dylib, dylib_result) = time_it(|| dylib_expression.compute(&numbers_a, &numbers_b));
+        let (jit, jit_result) = time_it(|| jit_expression.compute(&numbers_a, &numbers_b));
+        let (vm, vm_result) = time_it(|| vm_expression.compute(&numbers_a, &numbers_b));
         let (native, native_result) = time_it(|| native_expression.compute(&numbers_a, &numbers_b));
 
         csv.serialize(OutputRow {
@@ -81,6 +86,8 @@ fn main() {
             evalexpr,
             wasmer,
             dylib,
+            jit,
+            vm,
             native,
         })
         .unwrap();
@@ -89,6 +96,8 @@ fn main() {
         assert_eq!(evalexpr_result, opencl_result);
         assert_eq!(evalexpr_result, wasmer_result);
         assert_eq!(evalexpr_result, dylib_result);
+        assert_eq!(evalexpr_result, jit_result);
+        assert_eq!(evalexpr_result, vm_result);
         assert_eq!(evalexpr_result, native_result);
     }
 }
@@ -104,60 +113,38 @@ fn time_it(f: impl FnOnce() -> Vec<f64>) -> (f64, Vec<f64>) {
     (secs, result)
 }
 
+fn evaluate_batch(
+    expression: &impl BatchEvaluation,
+    numbers_a: &[f64],
+    numbers_b: &[f64],
+) -> Vec<f64> {
+    let mut output = vec![0.0; numbers_a.len()];
+    expression
+        .evaluate_batch(&[numbers_a, numbers_b], &mut output)
+        .unwrap();
+    output
+}
+
 struct OpenClExpression {
-    pro_que: ProQue,
+    expression: math_expr::OpenClExpression,
 }
 
 impl BenchComputation for OpenClExpression {
     fn new() -> Self {
-        let src = r#"
-            __kernel void ndvi(__global double* out, __global const double* a, __global const double* b) {
-                size_t i = get_global_id(0);
-                out[i] = (a[i] - b[i]) / (a[i] + b[i]);
-            }
-        "#;
+        let ast = Ast::new(
+            "ndvi".to_string(),
+            &["a".to_string(), "b".to_string()],
+            "(a - b) / (a + b)",
+        )
+        .unwrap();
 
-        Self {
-            pro_que: ProQue::builder().src(src).build().unwrap(),
-        }
+        let expression = math_expr::OpenClExpression::new(&ast).unwrap();
+
+        Self { expression }
     }
 
     fn compute(&mut self, numbers_a: &[f64], numbers_b: &[f64]) -> Vec<f64> {
-        assert_eq!(numbers_a.len(), numbers_b.len());
-
-        self.pro_que.set_dims(numbers_a.len());
-
-        let buffer = self.pro_que.create_buffer::<f64>().unwrap();
-        let input_a = self
-            .pro_que
-            .buffer_builder::<f64>()
-            .copy_host_slice(numbers_a)
-            .build()
-            .unwrap();
-        let input_b = self
-            .pro_que
-            .buffer_builder::<f64>()
-            .copy_host_slice(numbers_b)
-            .build()
-            .unwrap();
-
-        let kernel = self
-            .pro_que
-            .kernel_builder("ndvi")
-            .arg(&buffer)
-            .arg(input_a)
-            .arg(input_b)
-            .build()
-            .unwrap();
-
-        unsafe {
-            kernel.enq().unwrap();
-        }
-
-        let mut output = vec![0.0; numbers_a.len()];
-        buffer.read(&mut output).enq().unwrap();
-
-        output
+        evaluate_batch(&self.expression, numbers_a, numbers_b)
     }
 }
 
@@ -194,115 +181,94 @@ impl BenchComputation for EvalexprExpression {
 }
 
 struct WasmerExpression {
-    instance: Instance,
+    expression: WasmExpression,
 }
 
 impl BenchComputation for WasmerExpression {
     fn new() -> Self {
-        let module_wat = r#"
-        (module
-            (func $ndvi
-                (export "ndvi")
-                (param $p0 f64) (param $p1 f64)
-                (result f64)
-                local.get $p0
-                local.get $p1
-                f64.sub
-                local.get $p0
-                local.get $p1
-                f64.add
-                f64.div
-            )
+        let ast = Ast::new(
+            "ndvi".to_string(),
+            &["a".to_string(), "b".to_string()],
+            "(a - b) / (a + b)",
         )
-        "#;
-
-        let store = Store::default();
-        let module = Module::new(&store, &module_wat).unwrap();
-        // The module doesn't import anything, so we create an empty import object.
-        let import_object = imports! {};
-        let instance = Instance::new(&module, &import_object).unwrap();
+        .unwrap();
 
-        // TODO: native function
+        let expression = WasmExpression::new(&ast).unwrap();
 
-        Self { instance }
+        Self { expression }
     }
 
     fn compute(&mut self, numbers_a: &[f64], numbers_b: &[f64]) -> Vec<f64> {
-        assert_eq!(numbers_a.len(), numbers_b.len());
-
-        let ndvi = self.instance.exports.get_function("ndvi").unwrap();
-
-        numbers_a
-            .par_iter()
-            .zip_eq(numbers_b.par_iter())
-            .map(|(&a, &b)| -> f64 {
-                let result = ndvi.call(&[Value::F64(a), Value::F64(b)]).unwrap();
-
-                result[0].unwrap_f64()
-            })
-            .collect()
+        evaluate_batch(&self.expression, numbers_a, numbers_b)
     }
 }
 
 struct DylibExpression {
-    lib: Library,
+    expression: CompiledExpression,
 }
 
 impl BenchComputation for DylibExpression {
     fn new() -> Self {
-        let input_filename = "tmp/ndvi.rs";
-        let library_filename = "tmp/libndvi.so";
+        let ast = Ast::new(
+            "ndvi".to_string(),
+            &["a".to_string(), "b".to_string()],
+            "(a - b) / (a + b)",
+        )
+        .unwrap();
+
+        let expression = CompiledExpression::new(&ast).unwrap();
+
+        Self { expression }
+    }
+
+    fn compute(&mut self, numbers_a: &[f64], numbers_b: &[f64]) -> Vec<f64> {
+        evaluate_batch(&self.expression, numbers_a, numbers_b)
+    }
+}
 
+struct CraneliftExpression {
+    expression: JitExpression,
+}
+
+impl BenchComputation for CraneliftExpression {
+    fn new() -> Self {
         let ast = Ast::new(
             "ndvi".to_string(),
             &["a".to_string(), "b".to_string()],
             "(a - b) / (a + b)",
-        );
-
-        let mut file = File::create(input_filename).unwrap();
-        file.write_all(ast.code().as_bytes()).unwrap();
-        // file.write_all(
-        //     br#"
-        //     #[no_mangle]
-        //     pub extern "C" fn ndvi(a: f64, b: f64) -> f64 {
-        //         (a - b) / (a + b)
-        //     }
-        // "#,
-        // )
-        // .unwrap();
-
-        let mut compile_file = Command::new("rustc");
-        compile_file
-            .args(&[
-                "--crate-type",
-                "cdylib",
-                "-C",
-                "opt-level=3",
-                "--out-dir",
-                "tmp",
-                input_filename,
-            ])
-            .status()
-            .expect("process failed to execute");
-
-        let lib = unsafe { Library::new(library_filename) }.unwrap();
-
-        Self { lib }
+        )
+        .unwrap();
+
+        let expression = JitExpression::new(&ast).unwrap();
+
+        Self { expression }
     }
 
     fn compute(&mut self, numbers_a: &[f64], numbers_b: &[f64]) -> Vec<f64> {
-        assert_eq!(numbers_a.len(), numbers_b.len());
+        evaluate_batch(&self.expression, numbers_a, numbers_b)
+    }
+}
 
-        let ndvi = unsafe {
-            let func: Symbol<fn(f64, f64) -> f64> = self.lib.get(b"ndvi").unwrap();
-            func
-        };
+struct VmExpression {
+    expression: BytecodeExpression,
+}
 
-        numbers_a
-            .par_iter()
-            .zip_eq(numbers_b.par_iter())
-            .map(|(&a, &b)| ndvi(a, b))
-            .collect()
+impl BenchComputation for VmExpression {
+    fn new() -> Self {
+        let ast = Ast::new(
+            "ndvi".to_string(),
+            &["a".to_string(), "b".to_string()],
+            "(a - b) / (a + b)",
+        )
+        .unwrap();
+
+        let expression = BytecodeExpression::new(&ast).unwrap();
+
+        Self { expression }
+    }
+
+    fn compute(&mut self, numbers_a: &[f64], numbers_b: &[f64]) -> Vec<f64> {
+        evaluate_batch(&self.expression, numbers_a, numbers_b)
     }
 }
 
@@ -0,0 +1,161 @@
+use rayon::prelude::*;
+
+use crate::eval::EvalError;
+use crate::{
+    Ast, BytecodeExpression, CompiledExpression, JitExpression, OpenClExpression, WasmExpression,
+};
+
+/// Number of elements that are evaluated as one parallel task by [`BatchEvaluation::evaluate_batch`]
+pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+/// Evaluation of an expression over columnar inputs, i.e. one slice per parameter.
+///
+/// The output is split into chunks that are evaluated in parallel with rayon.
+pub trait BatchEvaluation: Sync {
+    /// Number of parameters, and thus input slices, of the expression
+    fn arity(&self) -> usize;
+
+    /// Evaluates a single chunk on the current thread.
+    ///
+    /// The caller checked that there is one input per parameter and that all inputs are as long as `output`.
+    fn evaluate_chunk(&self, inputs: &[&[f64]], output: &mut [f64]) -> Result<(), EvalError>;
+
+    /// Evaluates the expression for every element with [`DEFAULT_CHUNK_SIZE`] elements per task
+    fn evaluate_batch(&self, inputs: &[&[f64]], output: &mut [f64]) -> Result<(), EvalError> {
+        self.evaluate_batch_with_chunk_size(inputs, output, DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Evaluates the expression for every element with `chunk_size` elements per task
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    fn evaluate_batch_with_chunk_size(
+        &self,
+        inputs: &[&[f64]],
+        output: &mut [f64],
+        chunk_size: usize,
+    ) -> Result<(), EvalError> {
+        assert!(chunk_size > 0, "chunk size must be positive");
+
+        check_inputs(self.arity(), inputs, output.len())?;
+
+        output
+            .par_chunks_mut(chunk_size)
+            .enumerate()
+            .try_for_each(|(chunk, output)| {
+                let start = chunk * chunk_size;
+                let inputs = inputs
+                    .iter()
+                    .map(|input| &input[start..start + output.len()])
+                    .collect::<Vec<_>>();
+
+                self.evaluate_chunk(&inputs, output)
+            })
+    }
+}
+
+/// Checks that there is one input per parameter and that every input has `length` elements
+pub(crate) fn check_inputs(
+    arity: usize,
+    inputs: &[&[f64]],
+    length: usize,
+) -> Result<(), EvalError> {
+    if inputs.len() != arity {
+        return Err(EvalError::WrongParameterCount {
+            expected: arity,
+            actual: inputs.len(),
+        });
+    }
+
+    match inputs.iter().find(|input| input.len() != length) {
+        Some(input) => Err(EvalError::WrongInputLength {
+            expected: length,
+            actual: input.len(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Calls `call` with the parameters of every element, for backends that evaluate one element at a time
+fn evaluate_elements(
+    inputs: &[&[f64]],
+    output: &mut [f64],
+    call: impl Fn(&[f64]) -> Result<f64, EvalError>,
+) -> Result<(), EvalError> {
+    let mut parameters = vec![0.; inputs.len()];
+
+    for (i, output) in output.iter_mut().enumerate() {
+        for (parameter, input) in parameters.iter_mut().zip(inputs) {
+            *parameter = input[i];
+        }
+        *output = call(&parameters)?;
+    }
+
+    Ok(())
+}
+
+impl BatchEvaluation for Ast {
+    fn arity(&self) -> usize {
+        self.parameters().len()
+    }
+
+    fn evaluate_chunk(&self, inputs: &[&[f64]], output: &mut [f64]) -> Result<(), EvalError> {
+        evaluate_elements(inputs, output, |parameters| self.evaluate(parameters))
+    }
+}
+
+impl BatchEvaluation for BytecodeExpression {
+    fn arity(&self) -> usize {
+        self.arity()
+    }
+
+    fn evaluate_chunk(&self, inputs: &[&[f64]], output: &mut [f64]) -> Result<(), EvalError> {
+        self.evaluate_slices(inputs, output)
+    }
+}
+
+impl BatchEvaluation for CompiledExpression {
+    fn arity(&self) -> usize {
+        self.arity()
+    }
+
+    fn evaluate_chunk(&self, inputs: &[&[f64]], output: &mut [f64]) -> Result<(), EvalError> {
+        evaluate_elements(inputs, output, |parameters| self.call(parameters))
+    }
+}
+
+impl BatchEvaluation for JitExpression {
+    fn arity(&self) -> usize {
+        self.arity()
+    }
+
+    fn evaluate_chunk(&self, inputs: &[&[f64]], output: &mut [f64]) -> Result<(), EvalError> {
+        evaluate_elements(inputs, output, |parameters| self.call(parameters))
+    }
+}
+
+impl BatchEvaluation for WasmExpression {
+    fn arity(&self) -> usize {
+        self.arity()
+    }
+
+    fn evaluate_chunk(&self, inputs: &[&[f64]], output: &mut [f64]) -> Result<(), EvalError> {
+        evaluate_elements(inputs, output, |parameters| self.call(parameters))
+    }
+}
+
+impl BatchEvaluation for OpenClExpression {
+    fn arity(&self) -> usize {
+        self.arity()
+    }
+
+    fn evaluate_chunk(&self, inputs: &[&[f64]], output: &mut [f64]) -> Result<(), EvalError> {
+        self.evaluate_slices(inputs, output)
+    }
+
+    /// Launches a single kernel, the device evaluates the elements in parallel by itself
+    fn evaluate_batch(&self, inputs: &[&[f64]], output: &mut [f64]) -> Result<(), EvalError> {
+        self.evaluate_slices(inputs, output)
+    }
+}
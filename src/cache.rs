@@ -0,0 +1,164 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use sha2::{Digest, Sha256};
+
+/// Age after which a temporary directory is considered to be left behind by a crashed
+/// compilation, compilations only take seconds
+const STALE_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// An on-disk cache of compiled expression libraries.
+///
+/// Libraries are addressed by a hash of the generated code, the `rustc` version and the flags,
+/// so changing any of them leads to a recompilation.
+/// Several processes can share one cache directory: libraries are compiled in a temporary
+/// directory and moved into place with an atomic rename, so nobody sees a partially written file.
+#[derive(Debug, Clone)]
+pub struct CompilationCache {
+    directory: PathBuf,
+    max_size: u64,
+}
+
+impl CompilationCache {
+    /// Default limit for the total size of the cached libraries, 512 MiB
+    pub const DEFAULT_MAX_SIZE: u64 = 512 * 1024 * 1024;
+
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            max_size: Self::DEFAULT_MAX_SIZE,
+        }
+    }
+
+    /// Limit for the total size of the cached libraries in bytes.
+    /// If it is exceeded, the least recently used libraries are removed.
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub(crate) fn key(source: &str, rustc_version: &str, flags: &[String]) -> String {
+        let mut hasher = Sha256::new();
+
+        // separate the parts so that moving text from one part to the next changes the key
+        for part in [source, rustc_version]
+            .into_iter()
+            .chain(flags.iter().map(String::as_str))
+        {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part.as_bytes());
+        }
+
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Returns the path of the cached library for `key` and marks it as recently used
+    pub(crate) fn lookup(&self, key: &str) -> Option<PathBuf> {
+        let path = self.path(key);
+
+        let file = File::open(&path).ok()?;
+        // only affects the eviction order, so a failure is not worth aborting for
+        let _ = file.set_modified(SystemTime::now());
+
+        Some(path)
+    }
+
+    /// A temporary directory in the cache directory, so that its files can be renamed into the cache
+    pub(crate) fn temporary_directory(&self) -> io::Result<tempfile::TempDir> {
+        fs::create_dir_all(&self.directory)?;
+
+        tempfile::Builder::new()
+            .prefix(".tmp")
+            .tempdir_in(&self.directory)
+    }
+
+    /// Moves `library` into the cache and returns its new path
+    pub(crate) fn insert(&self, key: &str, library: &Path) -> io::Result<PathBuf> {
+        let path = self.path(key);
+
+        // if another process compiled the same library meanwhile, this replaces an identical file
+        fs::rename(library, &path)?;
+
+        Ok(path)
+    }
+
+    /// Removes the least recently used libraries until the cache fits into its size limit,
+    /// and the temporary directories of crashed compilations. `keep` is never removed.
+    pub(crate) fn evict(&self, keep: &Path) {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries.filter_map(Result::ok).collect::<Vec<_>>(),
+            Err(_) => return,
+        };
+
+        for entry in &entries {
+            if !entry.file_name().to_string_lossy().starts_with(".tmp") {
+                continue;
+            }
+
+            let stale = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age > STALE_AGE);
+            if stale {
+                // another process may be removing it as well
+                let _ = fs::remove_dir_all(entry.path());
+            }
+        }
+
+        let mut libraries = entries
+            .iter()
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .ends_with(std::env::consts::DLL_SUFFIX)
+            })
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                Some((modified, metadata.len(), entry.path()))
+            })
+            .collect::<Vec<_>>();
+
+        let mut size = libraries.iter().map(|(_, len, _)| len).sum::<u64>();
+        if size <= self.max_size {
+            return;
+        }
+
+        libraries.sort_by_key(|(modified, _, _)| *modified);
+
+        for (_, len, path) in libraries {
+            if size <= self.max_size {
+                break;
+            }
+            if path == keep {
+                continue;
+            }
+
+            // another process may have removed it already, which is just as good
+            let _ = fs::remove_file(&path);
+            size -= len;
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!(
+            "{}{}{}",
+            std::env::consts::DLL_PREFIX,
+            key,
+            std::env::consts::DLL_SUFFIX
+        ))
+    }
+}
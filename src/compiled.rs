@@ -0,0 +1,271 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::sync::OnceLock;
+
+use libloading::{Library, Symbol};
+use quote::{format_ident, quote, ToTokens};
+use tempfile::TempDir;
+
+use crate::cache::CompilationCache;
+use crate::eval::EvalError;
+use crate::Ast;
+
+/// Name of the generated wrapper that reads the parameters from an array
+const CALL_SYMBOL: &str = "__math_expr_call";
+
+/// Signature of the [`CALL_SYMBOL`] wrapper
+type CallFn = unsafe extern "C" fn(*const f64) -> f64;
+
+/// Compiles expressions with `rustc` into dynamic libraries
+#[derive(Debug, Clone)]
+pub struct Compiler {
+    rustc: OsString,
+    flags: Vec<String>,
+    cache: Option<CompilationCache>,
+    /// The output of `rustc -vV`, which is only queried once
+    rustc_version: OnceLock<String>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self {
+            rustc: std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into()),
+            flags: vec!["-C".to_string(), "opt-level=3".to_string()],
+            cache: None,
+            rustc_version: OnceLock::new(),
+        }
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The `rustc` executable, defaults to `$RUSTC` or `rustc` from the `PATH`
+    pub fn rustc(mut self, rustc: impl Into<OsString>) -> Self {
+        self.rustc = rustc.into();
+        self.rustc_version = OnceLock::new();
+        self
+    }
+
+    /// Additional flags for `rustc`, defaults to `-C opt-level=3`
+    pub fn flags(mut self, flags: Vec<String>) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Reuses libraries from `cache` instead of compiling the same expression again
+    pub fn cache(mut self, cache: CompilationCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn compile(&self, ast: &Ast) -> Result<CompiledExpression, CompileError> {
+        let source = source_code(ast);
+        let arity = ast.parameters().len();
+
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => {
+                let directory = tempfile::Builder::new()
+                    .prefix("math-expr")
+                    .tempdir()
+                    .map_err(CompileError::Io)?;
+
+                let library_path = self.compile_library(&source, directory.path())?;
+
+                return CompiledExpression::load(&library_path, arity, Some(directory));
+            }
+        };
+
+        let key = CompilationCache::key(&source, self.rustc_version()?, &self.flags);
+
+        if let Some(library_path) = cache.lookup(&key) {
+            // the library may have been evicted by another process in the meantime
+            if let Ok(expression) = CompiledExpression::load(&library_path, arity, None) {
+                return Ok(expression);
+            }
+        }
+
+        let directory = cache.temporary_directory().map_err(CompileError::Io)?;
+        let library_path = self.compile_library(&source, directory.path())?;
+        let library_path = cache
+            .insert(&key, &library_path)
+            .map_err(CompileError::Io)?;
+
+        let expression = CompiledExpression::load(&library_path, arity, None)?;
+
+        cache.evict(&library_path);
+
+        Ok(expression)
+    }
+
+    /// The verbose version of `rustc`, which includes the commit hash and the host target
+    fn rustc_version(&self) -> Result<&str, CompileError> {
+        if let Some(version) = self.rustc_version.get() {
+            return Ok(version);
+        }
+
+        let output = Command::new(&self.rustc)
+            .arg("-vV")
+            .output()
+            .map_err(CompileError::Io)?;
+
+        if !output.status.success() {
+            return Err(CompileError::Rustc {
+                status: output.status,
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            });
+        }
+
+        Ok(self
+            .rustc_version
+            .get_or_init(|| String::from_utf8_lossy(&output.stdout).into_owned()))
+    }
+
+    /// Writes `source` to `directory`, compiles it and returns the path of the library
+    fn compile_library(
+        &self,
+        source: &str,
+        directory: &Path,
+    ) -> Result<std::path::PathBuf, CompileError> {
+        const CRATE_NAME: &str = "expression";
+
+        let source_path = directory.join(format!("{}.rs", CRATE_NAME));
+        std::fs::write(&source_path, source).map_err(CompileError::Io)?;
+
+        let output = Command::new(&self.rustc)
+            .args(["--crate-type", "cdylib", "--crate-name", CRATE_NAME])
+            .args(&self.flags)
+            .arg("--out-dir")
+            .arg(directory)
+            .arg(&source_path)
+            .output()
+            .map_err(CompileError::Io)?;
+
+        if !output.status.success() {
+            return Err(CompileError::Rustc {
+                status: output.status,
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            });
+        }
+
+        Ok(directory.join(format!(
+            "{}{}{}",
+            std::env::consts::DLL_PREFIX,
+            CRATE_NAME,
+            std::env::consts::DLL_SUFFIX
+        )))
+    }
+}
+
+/// The code of the `ast` plus a wrapper with a fixed signature, independent of the number of parameters
+fn source_code(ast: &Ast) -> String {
+    let fn_name = format_ident!("{}", ast.name());
+    let call_name = format_ident!("{}", CALL_SYMBOL);
+    let args = ast.parameter_types().iter().enumerate().map(|(i, dtype)| {
+        let dtype = dtype.rust_type();
+        quote! { *parameters.add(#i) as #dtype }
+    });
+
+    let mut tokens = ast.to_token_stream();
+    tokens.extend(quote! {
+        /// # Safety
+        /// `parameters` must point to as many values as the expression has parameters
+        #[no_mangle]
+        pub unsafe extern "C" fn #call_name (parameters: *const f64) -> f64 {
+            #fn_name(#(#args),*) as f64
+        }
+    });
+
+    tokens.to_string()
+}
+
+/// An expression that was compiled to native code and loaded into the process
+#[derive(Debug)]
+pub struct CompiledExpression {
+    function: CallFn,
+    arity: usize,
+    // `function` points into the library, so it must stay loaded as long as `self` lives
+    _library: Library,
+    _directory: Option<TempDir>,
+}
+
+impl CompiledExpression {
+    /// Compiles `ast` with the default [`Compiler`]
+    pub fn new(ast: &Ast) -> Result<Self, CompileError> {
+        Compiler::default().compile(ast)
+    }
+
+    fn load(
+        library_path: &Path,
+        arity: usize,
+        directory: Option<TempDir>,
+    ) -> Result<Self, CompileError> {
+        // SAFETY: the library was generated by `Compiler` and has no initialization routines
+        let library = unsafe { Library::new(library_path) }.map_err(CompileError::Load)?;
+
+        // SAFETY: the wrapper was generated with the `CallFn` signature
+        let function = unsafe {
+            let symbol: Symbol<CallFn> = library
+                .get(CALL_SYMBOL.as_bytes())
+                .map_err(CompileError::Load)?;
+            *symbol
+        };
+
+        Ok(Self {
+            function,
+            arity,
+            _library: library,
+            _directory: directory,
+        })
+    }
+
+    /// Number of parameters of the expression
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Calls the expression with `parameters` in the order of the declared parameters
+    pub fn call(&self, parameters: &[f64]) -> Result<f64, EvalError> {
+        if parameters.len() != self.arity {
+            return Err(EvalError::WrongParameterCount {
+                expected: self.arity,
+                actual: parameters.len(),
+            });
+        }
+
+        // SAFETY: the length of `parameters` matches the arity of the wrapper
+        Ok(unsafe { (self.function)(parameters.as_ptr()) })
+    }
+}
+
+#[derive(Debug)]
+pub enum CompileError {
+    Io(std::io::Error),
+    Rustc { status: ExitStatus, stderr: String },
+    Load(libloading::Error),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "cannot prepare compilation: {}", error),
+            Self::Rustc { status, stderr } => write!(f, "rustc failed ({}):\n{}", status, stderr),
+            Self::Load(error) => write!(f, "cannot load compiled expression: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Rustc { .. } => None,
+            Self::Load(error) => Some(error),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+
+/// Names that expressions can refer to without declaring them as parameters.
+///
+/// Parameters and variables of the same name take precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamedConstant {
+    Pi,
+    E,
+    Tau,
+    /// Marks missing data, represented as NaN
+    NoData,
+}
+
+impl NamedConstant {
+    const ALL: [NamedConstant; 4] = [Self::Pi, Self::E, Self::Tau, Self::NoData];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|constant| constant.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Pi => "pi",
+            Self::E => "e",
+            Self::Tau => "tau",
+            Self::NoData => "nodata",
+        }
+    }
+
+    pub fn value(&self) -> f64 {
+        match self {
+            Self::Pi => std::f64::consts::PI,
+            Self::E => std::f64::consts::E,
+            Self::Tau => std::f64::consts::TAU,
+            Self::NoData => f64::NAN,
+        }
+    }
+
+    pub(crate) fn names() -> impl Iterator<Item = &'static str> {
+        Self::ALL.iter().map(Self::name)
+    }
+}
+
+impl ToTokens for NamedConstant {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let new_tokens = match self {
+            Self::Pi => quote! { std::f64::consts::PI },
+            Self::E => quote! { std::f64::consts::E },
+            Self::Tau => quote! { std::f64::consts::TAU },
+            Self::NoData => quote! { f64::NAN },
+        };
+
+        tokens.extend(new_tokens);
+    }
+}
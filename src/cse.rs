@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+
+use crate::optimize::Optimization;
+use crate::scope::temporary_identifier;
+use crate::{
+    Assignment, AstNode, AstNodeKind, BooleanExpression, BooleanExpressionKind, Branch, Span,
+};
+
+/// Hoists subexpressions that occur more than once into `let` bindings, e.g.
+/// `(a - b) / (a + b) * (a - b)` becomes `let common_0 = a - b; common_0 / (a + b) * common_0`.
+///
+/// The binding is placed in the innermost block that contains every occurrence, right before the
+/// first statement that needs it. Occurrences in different branches are computed once before the
+/// branch, which is fine since expressions have no side effects.
+pub(crate) fn eliminate_common_subexpressions(
+    root: AstNode,
+    parameters: &[String],
+    changes: &mut Vec<Optimization>,
+) -> AstNode {
+    let mut root = root;
+
+    // the largest subexpression first, its parts are found again in the binding in the next round
+    loop {
+        let mut collection = Elimination::new(parameters, Mode::Collect(Vec::new()));
+        root = collection.root(root);
+
+        let candidates = match collection.mode {
+            Mode::Collect(candidates) => candidates,
+            Mode::Replace(_) => unreachable!("collecting does not replace"),
+        };
+
+        let candidate = candidates
+            .into_iter()
+            .filter(|candidate| candidate.paths.len() > 1)
+            .fold(
+                None,
+                |largest: Option<Candidate>, candidate| match largest {
+                    Some(largest) if largest.size >= candidate.size => Some(largest),
+                    _ => Some(candidate),
+                },
+            );
+
+        let candidate = match candidate {
+            Some(candidate) => candidate,
+            None => break,
+        };
+
+        let (scope, index) = candidate.placement();
+        // the binding must not shadow a variable, which may be from an earlier optimization
+        let identifier = (0usize..)
+            .map(|n| temporary_identifier("common", n))
+            .find(|identifier| !collection.assigned.contains(identifier))
+            .expect("there are unused names");
+
+        changes.push(Optimization::SubexpressionEliminated {
+            name: identifier.to_string(),
+            occurrences: candidate.paths.len(),
+            span: candidate.span,
+        });
+
+        let mut replacement = Elimination::new(
+            parameters,
+            Mode::Replace(Replacement {
+                key: candidate.key,
+                scope,
+                index,
+                identifier,
+                expression: None,
+            }),
+        );
+        root = replacement.root(root);
+    }
+
+    root
+}
+
+/// Where a node is evaluated: the blocks around it, each with the index of the statement
+/// that contains the node, where the final expression comes after the assignments
+type Path = Vec<(usize, usize)>;
+
+/// A subexpression that may occur more than once
+struct Candidate {
+    key: String,
+    /// Number of nodes
+    size: usize,
+    span: Span,
+    /// Where each occurrence is evaluated
+    paths: Vec<Path>,
+}
+
+impl Candidate {
+    /// The block and the statement index where the binding is inserted
+    fn placement(&self) -> (usize, usize) {
+        let mut placement = None;
+
+        for depth in 0.. {
+            let (scope, index) = match self.paths[0].get(depth) {
+                Some(position) => *position,
+                None => break,
+            };
+
+            let mut same_index = true;
+            let mut first_index = index;
+            for path in &self.paths[1..] {
+                match path.get(depth) {
+                    Some((other_scope, other_index)) if *other_scope == scope => {
+                        same_index &= *other_index == index;
+                        first_index = first_index.min(*other_index);
+                    }
+                    _ => return placement.expect("all occurrences are in the root"),
+                }
+            }
+
+            placement = Some((scope, first_index));
+
+            // different statements contain different nested blocks
+            if !same_index {
+                break;
+            }
+        }
+
+        placement.expect("all occurrences are in the root")
+    }
+}
+
+struct Replacement {
+    key: String,
+    scope: usize,
+    index: usize,
+    identifier: String,
+    /// The first occurrence, which becomes the value of the binding
+    expression: Option<AstNode>,
+}
+
+enum Mode {
+    Collect(Vec<Candidate>),
+    Replace(Replacement),
+}
+
+struct Elimination {
+    /// Visible variables and the ids of their definitions, later ones shadow earlier ones
+    scope: Vec<(String, usize)>,
+    /// Number of definitions, which makes their ids unique
+    definitions: usize,
+    /// The names of all assigned variables
+    assigned: Vec<String>,
+    /// Number of blocks, in the order they are visited
+    blocks: usize,
+    path: Path,
+    /// Indices of the candidates by their keys
+    keys: HashMap<String, usize>,
+    mode: Mode,
+}
+
+impl Elimination {
+    fn new(parameters: &[String], mode: Mode) -> Self {
+        Self {
+            scope: parameters
+                .iter()
+                .cloned()
+                .enumerate()
+                .map(|(id, parameter)| (parameter, id))
+                .collect(),
+            definitions: parameters.len(),
+            assigned: Vec::new(),
+            blocks: 0,
+            path: Vec::new(),
+            keys: HashMap::new(),
+            mode,
+        }
+    }
+
+    fn root(&mut self, root: AstNode) -> AstNode {
+        self.body(root)
+    }
+
+    /// The root or the body of a branch, which becomes a block if a binding is placed in it
+    fn body(&mut self, node: AstNode) -> AstNode {
+        if matches!(node.kind(), AstNodeKind::AssignmentsAndExpression { .. }) {
+            return self.node(node).0;
+        }
+
+        let block = self.blocks;
+        self.blocks += 1;
+
+        self.path.push((block, 0));
+        let (node, _) = self.node(node);
+        self.path.pop();
+
+        match self.insertion(block, 0) {
+            Some(assignment) => {
+                let span = node.span();
+                AstNode::new(
+                    AstNodeKind::AssignmentsAndExpression {
+                        assignments: vec![assignment],
+                        expression: Box::new(node),
+                    },
+                    span,
+                )
+            }
+            None => node,
+        }
+    }
+
+    /// The rewritten node and a key that is the same for nodes that compute the same value,
+    /// if it has no blocks or branches
+    fn node(&mut self, node: AstNode) -> (AstNode, Option<(String, usize)>) {
+        let AstNode { kind, span } = node;
+
+        let (kind, key) = match kind {
+            AstNodeKind::Constant(n) => (kind, Some((format!("c{}", n.to_bits()), 1))),
+            AstNodeKind::Integer(n) => (kind, Some((format!("c{}", f64::from(n).to_bits()), 1))),
+            AstNodeKind::NamedConstant(constant) => {
+                (kind, Some((format!("c{}", constant.value().to_bits()), 1)))
+            }
+            AstNodeKind::Variable(ref identifier) => {
+                let id = self
+                    .scope
+                    .iter()
+                    .rev()
+                    .find(|(name, _)| name == identifier)
+                    .map(|(_, id)| *id)
+                    .expect("variables are checked when building the ast");
+                let key = format!("v{}#{}", identifier, id);
+                (kind, Some((key, 1)))
+            }
+            AstNodeKind::Negation(operand) => {
+                let (operand, key) = self.node(*operand);
+                let key = key.map(|(key, size)| (format!("-({})", key), size + 1));
+                (AstNodeKind::Negation(Box::new(operand)), key)
+            }
+            AstNodeKind::Operation { left, op, right } => {
+                let (left, left_key) = self.node(*left);
+                let (right, right_key) = self.node(*right);
+                let key = match (left_key, right_key) {
+                    (Some((left_key, left_size)), Some((right_key, right_size))) => Some((
+                        format!("({}{}{})", left_key, op.symbol(), right_key),
+                        left_size + right_size + 1,
+                    )),
+                    _ => None,
+                };
+                (
+                    AstNodeKind::Operation {
+                        left: Box::new(left),
+                        op,
+                        right: Box::new(right),
+                    },
+                    key,
+                )
+            }
+            // calls without arguments are constants
+            AstNodeKind::Function { name, args } if args.is_empty() => {
+                let key = format!("{}()", name);
+                (AstNodeKind::Function { name, args }, Some((key, 1)))
+            }
+            AstNodeKind::Function { name, args } => {
+                let (args, keys): (Vec<_>, Vec<_>) =
+                    args.into_iter().map(|arg| self.node(arg)).unzip();
+                let key = keys.into_iter().collect::<Option<Vec<_>>>().map(|keys| {
+                    let size = keys.iter().map(|(_, size)| size).sum::<usize>() + 1;
+                    let keys = keys.into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+                    (format!("{}({})", name, keys.join(",")), size)
+                });
+                (AstNodeKind::Function { name, args }, key)
+            }
+            AstNodeKind::Branch {
+                condition_branches,
+                else_branch,
+            } => {
+                let condition_branches = condition_branches
+                    .into_iter()
+                    .map(|branch| {
+                        let condition = self.condition(branch.condition);
+                        let body = self.body(branch.body);
+                        Branch { condition, body }
+                    })
+                    .collect();
+                let else_branch = self.body(*else_branch);
+                (
+                    AstNodeKind::Branch {
+                        condition_branches,
+                        else_branch: Box::new(else_branch),
+                    },
+                    None,
+                )
+            }
+            AstNodeKind::AssignmentsAndExpression {
+                assignments,
+                expression,
+            } => {
+                let block = self.blocks;
+                self.blocks += 1;
+                let scope_start = self.scope.len();
+                let statements = assignments.len();
+
+                let mut rewritten_assignments = Vec::with_capacity(assignments.len() + 1);
+                for (index, assignment) in assignments.into_iter().enumerate() {
+                    self.path.push((block, index));
+                    let (expression, _) = self.node(assignment.expression);
+                    self.path.pop();
+
+                    if let Some(assignment) = self.insertion(block, index) {
+                        rewritten_assignments.push(assignment);
+                    }
+
+                    self.scope
+                        .push((assignment.identifier.clone(), self.definitions));
+                    self.definitions += 1;
+                    self.assigned.push(assignment.identifier.clone());
+
+                    rewritten_assignments.push(Assignment {
+                        expression,
+                        ..assignment
+                    });
+                }
+
+                self.path.push((block, statements));
+                let (expression, _) = self.node(*expression);
+                self.path.pop();
+
+                if let Some(assignment) = self.insertion(block, statements) {
+                    rewritten_assignments.push(assignment);
+                }
+
+                self.scope.truncate(scope_start);
+
+                (
+                    AstNodeKind::AssignmentsAndExpression {
+                        assignments: rewritten_assignments,
+                        expression: Box::new(expression),
+                    },
+                    None,
+                )
+            }
+        };
+
+        let node = AstNode::new(kind, span);
+
+        let (key, size) = match key {
+            Some(key) => key,
+            None => return (node, None),
+        };
+
+        // constants, variables and their negations are not worth a binding
+        if size == 1 || (size == 2 && matches!(node.kind(), AstNodeKind::Negation(_))) {
+            return (node, Some((key, size)));
+        }
+
+        match &mut self.mode {
+            Mode::Collect(candidates) => {
+                match self.keys.get(&key) {
+                    Some(index) => candidates[*index].paths.push(self.path.clone()),
+                    None => {
+                        self.keys.insert(key.clone(), candidates.len());
+                        candidates.push(Candidate {
+                            key: key.clone(),
+                            size,
+                            span,
+                            paths: vec![self.path.clone()],
+                        });
+                    }
+                }
+
+                (node, Some((key, size)))
+            }
+            Mode::Replace(replacement) if replacement.key == key => {
+                if replacement.expression.is_none() {
+                    replacement.expression = Some(node);
+                }
+
+                let variable = AstNodeKind::Variable(replacement.identifier.clone());
+                (AstNode::new(variable, span), None)
+            }
+            Mode::Replace(_) => (node, Some((key, size))),
+        }
+    }
+
+    fn condition(&mut self, expression: BooleanExpression) -> BooleanExpression {
+        let BooleanExpression { kind, span } = expression;
+
+        let kind = match kind {
+            BooleanExpressionKind::Constant(_) => kind,
+            BooleanExpressionKind::Not(operand) => {
+                BooleanExpressionKind::Not(Box::new(self.condition(*operand)))
+            }
+            BooleanExpressionKind::Comparison { left, op, right } => {
+                BooleanExpressionKind::Comparison {
+                    left: Box::new(self.node(*left).0),
+                    op,
+                    right: Box::new(self.node(*right).0),
+                }
+            }
+            BooleanExpressionKind::Operation { left, op, right } => {
+                BooleanExpressionKind::Operation {
+                    left: Box::new(self.condition(*left)),
+                    op,
+                    right: Box::new(self.condition(*right)),
+                }
+            }
+        };
+
+        BooleanExpression::new(kind, span)
+    }
+
+    /// The binding that is placed before the statement `index` of `block`, if any
+    fn insertion(&mut self, block: usize, index: usize) -> Option<Assignment> {
+        match &mut self.mode {
+            Mode::Replace(replacement)
+                if replacement.scope == block && replacement.index == index =>
+            {
+                let expression = replacement
+                    .expression
+                    .take()
+                    .expect("the binding is placed before its occurrences");
+
+                Some(Assignment {
+                    identifier: replacement.identifier.clone(),
+                    span: expression.span(),
+                    expression,
+                })
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+
+use proc_macro2::Ident;
+use quote::format_ident;
+
+/// The numeric type of a parameter or of the output of an expression.
+///
+/// All arithmetic is done in `f64`, which represents every value of these types exactly.
+/// Converting to a type truncates towards zero and saturates at its bounds, and `NaN` becomes `0`
+/// for integer types, like an `as` cast in Rust.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DataType {
+    U8,
+    U16,
+    I16,
+    F32,
+    #[default]
+    F64,
+}
+
+impl DataType {
+    pub fn is_integer(self) -> bool {
+        matches!(self, Self::U8 | Self::U16 | Self::I16)
+    }
+
+    /// The smallest and largest value of an integer type
+    pub(crate) fn integer_bounds(self) -> Option<(f64, f64)> {
+        match self {
+            Self::U8 => Some((u8::MIN.into(), u8::MAX.into())),
+            Self::U16 => Some((u16::MIN.into(), u16::MAX.into())),
+            Self::I16 => Some((i16::MIN.into(), i16::MAX.into())),
+            Self::F32 | Self::F64 => None,
+        }
+    }
+
+    /// Converts `value` to this type and back to `f64`
+    pub fn cast(self, value: f64) -> f64 {
+        match self {
+            Self::U8 => (value as u8).into(),
+            Self::U16 => (value as u16).into(),
+            Self::I16 => (value as i16).into(),
+            Self::F32 => (value as f32).into(),
+            Self::F64 => value,
+        }
+    }
+
+    /// The Rust type, e.g. `u8`
+    pub(crate) fn rust_type(self) -> Ident {
+        format_ident!("{}", self.to_string())
+    }
+
+    /// The OpenCL C type, e.g. `uchar`
+    pub(crate) fn opencl_type(self) -> &'static str {
+        match self {
+            Self::U8 => "uchar",
+            Self::U16 => "ushort",
+            Self::I16 => "short",
+            Self::F32 => "float",
+            Self::F64 => "double",
+        }
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::U8 => "u8",
+            Self::U16 => "u16",
+            Self::I16 => "i16",
+            Self::F32 => "f32",
+            Self::F64 => "f64",
+        };
+        write!(f, "{}", name)
+    }
+}
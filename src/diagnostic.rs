@@ -0,0 +1,201 @@
+use std::fmt::Write;
+
+use crate::error::{ExpressionError, Span};
+use crate::usage::Warning;
+
+/// A message that points to a part of the expression source.
+///
+/// Use [`Diagnostic::render`] to print it with the source line and a caret underline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    severity: Severity,
+    message: String,
+    span: Span,
+    hint: Option<String>,
+}
+
+/// Whether a [`Diagnostic`] keeps the expression from being used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn new(message: String, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+            span,
+            hint: None,
+        }
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    pub fn with_hint(mut self, hint: String) -> Self {
+        self.hint = Some(hint);
+        self
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn hint(&self) -> Option<&str> {
+        self.hint.as_deref()
+    }
+
+    /// Renders the diagnostic for the `source` it was created from, e.g.
+    ///
+    /// ```text
+    /// error: unknown variable `c`
+    ///  --> 1:6
+    ///   |
+    /// 1 | (a - c) / (a + b)
+    ///   |      ^ did you mean `a`?
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let start = self.span.start().min(source.len());
+        let end = self.span.end().clamp(start, source.len());
+
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let line = source[line_start..line_end].trim_end_matches('\r');
+        let line_number = source[..line_start].matches('\n').count() + 1;
+
+        let column = source[line_start..start].chars().count();
+        let underline_length = source[start..end.min(line_end)].chars().count().max(1);
+
+        let gutter = " ".repeat(line_number.to_string().len());
+
+        let mut output = String::new();
+        // writing to a `String` cannot fail
+        let _ = writeln!(output, "{}: {}", self.severity.label(), self.message);
+        let _ = writeln!(output, "{}--> {}:{}", gutter, line_number, column + 1);
+        let _ = writeln!(output, "{} |", gutter);
+        let _ = writeln!(output, "{} | {}", line_number, line);
+        let _ = write!(
+            output,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(column),
+            "^".repeat(underline_length)
+        );
+        if let Some(hint) = &self.hint {
+            let _ = write!(output, " {}", hint);
+        }
+        output.push('\n');
+
+        output
+    }
+}
+
+impl From<&ExpressionError> for Diagnostic {
+    fn from(error: &ExpressionError) -> Self {
+        let diagnostic = Diagnostic::new(error.to_string(), error.span());
+
+        let hint = match error {
+            ExpressionError::UnknownVariable {
+                suggestion: Some(suggestion),
+                ..
+            }
+            | ExpressionError::UnknownFunction {
+                suggestion: Some(suggestion),
+                ..
+            } => Some(format!("did you mean `{}`?", suggestion)),
+            ExpressionError::AssignmentToParameter { .. } => {
+                Some("parameters are read-only, use a new variable name".to_string())
+            }
+            ExpressionError::UseBeforeAssignment { name, .. } => Some(format!(
+                "move `let {} = ...;` before this use",
+                name
+            )),
+            ExpressionError::FloatOperand { .. } => Some(
+                "integers need integer arithmetic and a literal, an integer parameter or an integer result"
+                    .to_string(),
+            ),
+            _ => None,
+        };
+
+        match hint {
+            Some(hint) => diagnostic.with_hint(hint),
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Self {
+        let hint = match warning {
+            Warning::UnusedParameter { .. } => "its values are not needed to compute the result",
+            Warning::UnusedVariable { .. } => "remove the assignment",
+            Warning::OverwrittenVariable { .. } => "the value is never read",
+        };
+
+        Diagnostic::new(warning.to_string(), warning.span())
+            .with_severity(Severity::Warning)
+            .with_hint(hint.to_string())
+    }
+}
+
+/// Finds the candidate that is closest to `name` if it is close enough to be a likely typo
+pub(crate) fn closest_match<'c>(
+    name: &str,
+    candidates: impl Iterator<Item = &'c str>,
+) -> Option<String> {
+    let max_distance = (name.chars().count() / 3).max(1);
+
+    candidates
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+/// Levenshtein distance between `a` and `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+
+    let mut previous_row = (0..=b.len()).collect::<Vec<_>>();
+    let mut current_row = vec![0; b.len() + 1];
+
+    for (i, a_char) in a.chars().enumerate() {
+        current_row[0] = i + 1;
+
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution_cost = usize::from(a_char != *b_char);
+
+            current_row[j + 1] = (previous_row[j] + substitution_cost)
+                .min(previous_row[j + 1] + 1)
+                .min(current_row[j] + 1);
+        }
+
+        std::mem::swap(&mut previous_row, &mut current_row);
+    }
+
+    previous_row[b.len()]
+}
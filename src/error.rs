@@ -0,0 +1,199 @@
+use std::fmt;
+
+use crate::diagnostic::Diagnostic;
+use crate::Rule;
+
+/// A byte range `start..end` into the expression source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    start: usize,
+    end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        debug_assert!(start <= end);
+
+        Self { start, end }
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// The smallest span that covers both `self` and `other`
+    pub fn join(self, other: Span) -> Span {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(span: pest::Span<'_>) -> Self {
+        Self::new(span.start(), span.end())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpressionError {
+    Parse {
+        message: String,
+        span: Span,
+    },
+    UnknownVariable {
+        name: String,
+        span: Span,
+        /// A declared name that is similar to `name`
+        suggestion: Option<String>,
+    },
+    AssignmentToParameter {
+        name: String,
+        span: Span,
+    },
+    /// A variable that is read before it is assigned, later in the same or an enclosing block
+    UseBeforeAssignment {
+        name: String,
+        span: Span,
+        /// The `let` statement that assigns it
+        assignment: Span,
+    },
+    UnknownFunction {
+        name: String,
+        span: Span,
+        /// A known function that is similar to `name`
+        suggestion: Option<String>,
+    },
+    WrongArgumentCount {
+        name: String,
+        expected: usize,
+        actual: usize,
+        span: Span,
+    },
+    /// A bitwise operator or shift with an operand that is not an integer
+    FloatOperand {
+        operator: String,
+        span: Span,
+    },
+    /// An expression name that is a keyword or not an identifier, which cannot name the generated
+    /// function. The span is empty, since the name is not part of the expression.
+    InvalidName {
+        name: String,
+        span: Span,
+    },
+    /// A parameter name that is a keyword or not an identifier.
+    /// The span is empty, since the name is not part of the expression.
+    InvalidParameter {
+        name: String,
+        span: Span,
+    },
+    /// A function with the name of the expression, which the generated code cannot tell apart.
+    /// The span is empty, since the name is not part of the expression.
+    FunctionNamedLikeExpression {
+        name: String,
+        span: Span,
+    },
+}
+
+impl ExpressionError {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Parse { span, .. }
+            | Self::UnknownVariable { span, .. }
+            | Self::AssignmentToParameter { span, .. }
+            | Self::UseBeforeAssignment { span, .. }
+            | Self::UnknownFunction { span, .. }
+            | Self::WrongArgumentCount { span, .. }
+            | Self::FloatOperand { span, .. }
+            | Self::InvalidName { span, .. }
+            | Self::InvalidParameter { span, .. }
+            | Self::FunctionNamedLikeExpression { span, .. } => *span,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::from(self)
+    }
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse { message, .. } => write!(f, "{}", message),
+            Self::UnknownVariable { name, .. } => write!(f, "unknown variable `{}`", name),
+            Self::AssignmentToParameter { name, .. } => {
+                write!(f, "cannot assign to parameter `{}`", name)
+            }
+            Self::UseBeforeAssignment { name, .. } => {
+                write!(f, "variable `{}` is used before it is assigned", name)
+            }
+            Self::UnknownFunction { name, .. } => write!(f, "unknown function `{}`", name),
+            Self::WrongArgumentCount {
+                name,
+                expected,
+                actual,
+                ..
+            } => write!(
+                f,
+                "function `{}` takes {} argument{} but {} {} supplied",
+                name,
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                actual,
+                if *actual == 1 { "was" } else { "were" },
+            ),
+            Self::FloatOperand { operator, .. } => {
+                write!(f, "operator `{}` needs integer operands", operator)
+            }
+            Self::InvalidName { name, .. } => {
+                write!(f, "`{}` is not a valid expression name", name)
+            }
+            Self::InvalidParameter { name, .. } => {
+                write!(f, "`{}` is not a valid parameter name", name)
+            }
+            Self::FunctionNamedLikeExpression { name, .. } => {
+                write!(f, "function `{}` has the name of the expression", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+impl From<pest::error::Error<Rule>> for ExpressionError {
+    fn from(error: pest::error::Error<Rule>) -> Self {
+        let span = match error.location {
+            pest::error::InputLocation::Pos(pos) => Span::new(pos, pos),
+            pest::error::InputLocation::Span((start, end)) => Span::new(start, end),
+        };
+
+        let message = match error.variant {
+            pest::error::ErrorVariant::ParsingError {
+                positives,
+                negatives,
+            } => match (positives.is_empty(), negatives.is_empty()) {
+                (false, _) => format!("expected {}", enumerate_rules(&positives)),
+                (true, false) => format!("unexpected {}", enumerate_rules(&negatives)),
+                (true, true) => "unknown parsing error".to_string(),
+            },
+            pest::error::ErrorVariant::CustomError { message } => message,
+        };
+
+        Self::Parse { message, span }
+    }
+}
+
+fn enumerate_rules(rules: &[Rule]) -> String {
+    let names = rules
+        .iter()
+        .map(|rule| format!("{:?}", rule))
+        .collect::<Vec<_>>();
+
+    match names.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
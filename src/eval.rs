@@ -0,0 +1,214 @@
+use std::fmt;
+
+use crate::functions::FunctionRegistry;
+use crate::{
+    Ast, AstNode, AstNodeKind, AstOperator, BooleanComparator, BooleanExpression,
+    BooleanExpressionKind, BooleanOperator,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    WrongParameterCount {
+        expected: usize,
+        actual: usize,
+    },
+    /// The expression calls a function with a Rust body, see [`UserFunction::rust`](crate::UserFunction::rust)
+    RustFunction {
+        name: String,
+    },
+    /// An input slice does not have the same length as the output
+    WrongInputLength {
+        expected: usize,
+        actual: usize,
+    },
+    /// The wasm sandbox stopped the evaluation with a runtime error
+    Trap {
+        message: String,
+    },
+    /// The OpenCL device could not evaluate the expression
+    OpenCl {
+        message: String,
+    },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongParameterCount { expected, actual } => write!(
+                f,
+                "expected {} parameter values but got {}",
+                expected, actual
+            ),
+            Self::RustFunction { name } => write!(
+                f,
+                "function `{}` has a Rust body and cannot be interpreted",
+                name
+            ),
+            Self::WrongInputLength { expected, actual } => write!(
+                f,
+                "expected {} values per parameter but got {}",
+                expected, actual
+            ),
+            Self::Trap { message } => write!(f, "evaluation trapped: {}", message),
+            Self::OpenCl { message } => write!(f, "OpenCL evaluation failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl Ast {
+    /// Evaluates the expression in-process, with `parameters` in the order of the declared parameters.
+    ///
+    /// The parameters and the result are converted to their [`DataType`](crate::DataType)s.
+    pub fn evaluate(&self, parameters: &[f64]) -> Result<f64, EvalError> {
+        if parameters.len() != self.parameters.len() {
+            return Err(EvalError::WrongParameterCount {
+                expected: self.parameters.len(),
+                actual: parameters.len(),
+            });
+        }
+
+        let parameters = parameters
+            .iter()
+            .zip(&self.parameter_types)
+            .map(|(value, dtype)| dtype.cast(*value))
+            .collect::<Vec<_>>();
+
+        Ok(self.output_type.cast(self.evaluate_f64(&parameters)?))
+    }
+
+    /// Evaluates the expression without the conversions to the parameter and output types,
+    /// like a function body that is called from another expression
+    pub(crate) fn evaluate_f64(&self, parameters: &[f64]) -> Result<f64, EvalError> {
+        let mut variables = self
+            .parameters
+            .iter()
+            .map(String::as_str)
+            .zip(parameters.iter().copied())
+            .collect();
+
+        Interpreter {
+            functions: &self.functions,
+        }
+        .evaluate(&self.root, &mut variables)
+    }
+}
+
+/// Variables in order of their definition, so that later ones shadow earlier ones
+type Variables<'a> = Vec<(&'a str, f64)>;
+
+struct Interpreter<'f> {
+    functions: &'f FunctionRegistry,
+}
+
+impl<'f> Interpreter<'f> {
+    fn evaluate<'a>(
+        &self,
+        node: &'a AstNode,
+        variables: &mut Variables<'a>,
+    ) -> Result<f64, EvalError> {
+        Ok(match node.kind() {
+            AstNodeKind::Constant(n) => *n,
+            AstNodeKind::Integer(n) => f64::from(*n),
+            AstNodeKind::NamedConstant(constant) => constant.value(),
+            AstNodeKind::Variable(identifier) => variables
+                .iter()
+                .rev()
+                .find(|(name, _)| *name == identifier)
+                .map(|(_, value)| *value)
+                .expect("variables are checked when building the ast"),
+            AstNodeKind::Negation(operand) => -self.evaluate(operand, variables)?,
+            AstNodeKind::Operation { left, op, right } => {
+                let left = self.evaluate(left, variables)?;
+                let right = self.evaluate(right, variables)?;
+
+                match op {
+                    AstOperator::Add => left + right,
+                    AstOperator::Subtract => left - right,
+                    AstOperator::Multiply => left * right,
+                    AstOperator::Divide => left / right,
+                    op => unreachable!("`{}` is replaced when the types are checked", op.symbol()),
+                }
+            }
+            AstNodeKind::Function { name, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.evaluate(arg, variables))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                self.functions
+                    .get(&name.to_string())
+                    .expect("functions are checked when building the ast")
+                    .evaluate(&args)?
+            }
+            AstNodeKind::Branch {
+                condition_branches,
+                else_branch,
+            } => {
+                for branch in condition_branches {
+                    if self.evaluate_boolean(branch.condition(), variables)? {
+                        return self.evaluate(branch.body(), variables);
+                    }
+                }
+
+                self.evaluate(else_branch, variables)?
+            }
+            AstNodeKind::AssignmentsAndExpression {
+                assignments,
+                expression,
+            } => {
+                let scope_start = variables.len();
+
+                for assignment in assignments {
+                    let value = self.evaluate(assignment.expression(), variables)?;
+                    variables.push((assignment.identifier(), value));
+                }
+
+                let value = self.evaluate(expression, variables)?;
+
+                variables.truncate(scope_start);
+
+                value
+            }
+        })
+    }
+
+    fn evaluate_boolean<'a>(
+        &self,
+        expression: &'a BooleanExpression,
+        variables: &mut Variables<'a>,
+    ) -> Result<bool, EvalError> {
+        Ok(match expression.kind() {
+            BooleanExpressionKind::Constant(b) => *b,
+            BooleanExpressionKind::Not(operand) => !self.evaluate_boolean(operand, variables)?,
+            BooleanExpressionKind::Comparison { left, op, right } => {
+                let left = self.evaluate(left, variables)?;
+                let right = self.evaluate(right, variables)?;
+
+                match op {
+                    BooleanComparator::Equal => left == right,
+                    BooleanComparator::NotEqual => left != right,
+                    BooleanComparator::LessThan => left < right,
+                    BooleanComparator::LessThanOrEqual => left <= right,
+                    BooleanComparator::GreaterThan => left > right,
+                    BooleanComparator::GreaterThanOrEqual => left >= right,
+                }
+            }
+            BooleanExpressionKind::Operation { left, op, right } => match op {
+                BooleanOperator::And => {
+                    self.evaluate_boolean(left, variables)?
+                        && self.evaluate_boolean(right, variables)?
+                }
+                BooleanOperator::Or => {
+                    self.evaluate_boolean(left, variables)?
+                        || self.evaluate_boolean(right, variables)?
+                }
+                BooleanOperator::Xor => {
+                    self.evaluate_boolean(left, variables)?
+                        ^ self.evaluate_boolean(right, variables)?
+                }
+            },
+        })
+    }
+}
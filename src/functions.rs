@@ -0,0 +1,866 @@
+use std::fmt;
+
+use proc_macro2::TokenStream;
+use quote::ToTokens;
+
+use crate::eval::EvalError;
+use crate::Ast;
+
+/// Names of the parameters of a function body, in order
+const PARAMETER_NAMES: [&str; 3] = ["a", "b", "c"];
+
+/// A function of the standard library that can be called from expressions
+#[derive(Debug, Clone, Copy)]
+pub struct BuiltinFunction {
+    name: &'static str,
+    arity: usize,
+    /// Rust code of the body, which refers to the arguments as `a`, `b` and `c`
+    rust_body: &'static str,
+    /// OpenCL C expression of the body with the same parameter names as `rust_body`
+    opencl_body: &'static str,
+    /// Computes the same result as `rust_body` for the interpreter
+    evaluate: fn(&[f64]) -> f64,
+}
+
+impl BuiltinFunction {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    pub(crate) fn parameters(&self) -> Vec<String> {
+        PARAMETER_NAMES[..self.arity]
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    pub(crate) fn rust_body(&self) -> TokenStream {
+        self.rust_body
+            .parse()
+            .expect("builtin function body must be valid Rust")
+    }
+
+    pub(crate) fn opencl_body(&self) -> &'static str {
+        self.opencl_body
+    }
+
+    pub fn evaluate(&self, args: &[f64]) -> f64 {
+        debug_assert_eq!(args.len(), self.arity);
+
+        (self.evaluate)(args)
+    }
+}
+
+const BUILTIN_FUNCTIONS: &[BuiltinFunction] = &[
+    BuiltinFunction {
+        name: "min",
+        arity: 2,
+        rust_body: "f64::min(a, b)",
+        opencl_body: "fmin(a, b)",
+        evaluate: |args| f64::min(args[0], args[1]),
+    },
+    BuiltinFunction {
+        name: "max",
+        arity: 2,
+        rust_body: "f64::max(a, b)",
+        opencl_body: "fmax(a, b)",
+        evaluate: |args| f64::max(args[0], args[1]),
+    },
+    BuiltinFunction {
+        name: "abs",
+        arity: 1,
+        rust_body: "f64::abs(a)",
+        opencl_body: "fabs(a)",
+        evaluate: |args| f64::abs(args[0]),
+    },
+    BuiltinFunction {
+        name: "sqrt",
+        arity: 1,
+        rust_body: "f64::sqrt(a)",
+        opencl_body: "sqrt(a)",
+        evaluate: |args| f64::sqrt(args[0]),
+    },
+    BuiltinFunction {
+        name: "exp",
+        arity: 1,
+        rust_body: "f64::exp(a)",
+        opencl_body: "exp(a)",
+        evaluate: |args| f64::exp(args[0]),
+    },
+    BuiltinFunction {
+        name: "ln",
+        arity: 1,
+        rust_body: "f64::ln(a)",
+        opencl_body: "log(a)",
+        evaluate: |args| f64::ln(args[0]),
+    },
+    BuiltinFunction {
+        name: "log10",
+        arity: 1,
+        rust_body: "f64::log10(a)",
+        opencl_body: "log10(a)",
+        evaluate: |args| f64::log10(args[0]),
+    },
+    // log(base, x)
+    BuiltinFunction {
+        name: "log",
+        arity: 2,
+        rust_body: "f64::log(b, a)",
+        opencl_body: "log(b) / log(a)",
+        evaluate: |args| f64::log(args[1], args[0]),
+    },
+    BuiltinFunction {
+        name: "pow",
+        arity: 2,
+        rust_body: "f64::powf(a, b)",
+        opencl_body: "pow(a, b)",
+        evaluate: |args| f64::powf(args[0], args[1]),
+    },
+    BuiltinFunction {
+        name: "sin",
+        arity: 1,
+        rust_body: "f64::sin(a)",
+        opencl_body: "sin(a)",
+        evaluate: |args| f64::sin(args[0]),
+    },
+    BuiltinFunction {
+        name: "cos",
+        arity: 1,
+        rust_body: "f64::cos(a)",
+        opencl_body: "cos(a)",
+        evaluate: |args| f64::cos(args[0]),
+    },
+    BuiltinFunction {
+        name: "tan",
+        arity: 1,
+        rust_body: "f64::tan(a)",
+        opencl_body: "tan(a)",
+        evaluate: |args| f64::tan(args[0]),
+    },
+    BuiltinFunction {
+        name: "asin",
+        arity: 1,
+        rust_body: "f64::asin(a)",
+        opencl_body: "asin(a)",
+        evaluate: |args| f64::asin(args[0]),
+    },
+    BuiltinFunction {
+        name: "acos",
+        arity: 1,
+        rust_body: "f64::acos(a)",
+        opencl_body: "acos(a)",
+        evaluate: |args| f64::acos(args[0]),
+    },
+    BuiltinFunction {
+        name: "atan",
+        arity: 1,
+        rust_body: "f64::atan(a)",
+        opencl_body: "atan(a)",
+        evaluate: |args| f64::atan(args[0]),
+    },
+    // atan2(y, x)
+    BuiltinFunction {
+        name: "atan2",
+        arity: 2,
+        rust_body: "f64::atan2(a, b)",
+        opencl_body: "atan2(a, b)",
+        evaluate: |args| f64::atan2(args[0], args[1]),
+    },
+    BuiltinFunction {
+        name: "floor",
+        arity: 1,
+        rust_body: "f64::floor(a)",
+        opencl_body: "floor(a)",
+        evaluate: |args| f64::floor(args[0]),
+    },
+    BuiltinFunction {
+        name: "ceil",
+        arity: 1,
+        rust_body: "f64::ceil(a)",
+        opencl_body: "ceil(a)",
+        evaluate: |args| f64::ceil(args[0]),
+    },
+    // rounds half-way cases away from zero
+    BuiltinFunction {
+        name: "round",
+        arity: 1,
+        rust_body: "f64::round(a)",
+        opencl_body: "round(a)",
+        evaluate: |args| f64::round(args[0]),
+    },
+    // clamp(x, min, max), unlike `f64::clamp` it does not panic on bad bounds
+    BuiltinFunction {
+        name: "clamp",
+        arity: 3,
+        rust_body: "f64::min(f64::max(a, b), c)",
+        opencl_body: "fmin(fmax(a, b), c)",
+        evaluate: |args| f64::min(f64::max(args[0], args[1]), args[2]),
+    },
+    // zero for zero, unlike `f64::signum`
+    BuiltinFunction {
+        name: "sign",
+        arity: 1,
+        rust_body: "if a > 0. { 1. } else if a < 0. { -1. } else { a }",
+        opencl_body: "a > 0.0 ? 1.0 : a < 0.0 ? -1.0 : a",
+        evaluate: |args| sign(args[0]),
+    },
+    BuiltinFunction {
+        name: "hypot",
+        arity: 2,
+        rust_body: "f64::hypot(a, b)",
+        opencl_body: "hypot(a, b)",
+        evaluate: |args| f64::hypot(args[0], args[1]),
+    },
+    // remainder with the sign of the dividend, like C's `fmod`
+    BuiltinFunction {
+        name: "fmod",
+        arity: 2,
+        rust_body: "a % b",
+        opencl_body: "fmod(a, b)",
+        evaluate: |args| args[0] % args[1],
+    },
+    // 1 for no-data, 0 otherwise
+    BuiltinFunction {
+        name: "isnodata",
+        arity: 1,
+        rust_body: "if a.is_nan() { 1. } else { 0. }",
+        opencl_body: "isnan(a) ? 1.0 : 0.0",
+        evaluate: |args| if args[0].is_nan() { 1. } else { 0. },
+    },
+    // coalesce(x, fallback), the fallback replaces no-data
+    BuiltinFunction {
+        name: "coalesce",
+        arity: 2,
+        rust_body: "if a.is_nan() { b } else { a }",
+        opencl_body: "isnan(a) ? b : a",
+        evaluate: |args| coalesce(args[0], args[1]),
+    },
+];
+
+/// Functions that replace the operators whose result depends on the types of the operands.
+///
+/// Integers are `f64` values in the range of `i32`, and `NaN` marks an invalid result, e.g. of a
+/// division by zero.
+/// The names contain `_`, so expressions cannot call them directly.
+const OPERATOR_FUNCTIONS: &[BuiltinFunction] = &[
+    BuiltinFunction {
+        name: "add_wrapping",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } \
+            else { f64::from((a as i32).wrapping_add(b as i32)) }",
+        opencl_body: "isnan(a) || isnan(b) ? NAN : (double)as_int((uint)(int)a + (uint)(int)b)",
+        evaluate: |args| integer_operation(args, |a, b| Some(a.wrapping_add(b))),
+    },
+    BuiltinFunction {
+        name: "add_checked",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } \
+            else { (a as i32).checked_add(b as i32).map_or(f64::NAN, f64::from) }",
+        opencl_body: "isnan(a) || isnan(b) || (long)a + (long)b < INT_MIN \
+            || (long)a + (long)b > INT_MAX ? NAN : (double)((long)a + (long)b)",
+        evaluate: |args| integer_operation(args, i32::checked_add),
+    },
+    BuiltinFunction {
+        name: "subtract_wrapping",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } \
+            else { f64::from((a as i32).wrapping_sub(b as i32)) }",
+        opencl_body: "isnan(a) || isnan(b) ? NAN : (double)as_int((uint)(int)a - (uint)(int)b)",
+        evaluate: |args| integer_operation(args, |a, b| Some(a.wrapping_sub(b))),
+    },
+    BuiltinFunction {
+        name: "subtract_checked",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } \
+            else { (a as i32).checked_sub(b as i32).map_or(f64::NAN, f64::from) }",
+        opencl_body: "isnan(a) || isnan(b) || (long)a - (long)b < INT_MIN \
+            || (long)a - (long)b > INT_MAX ? NAN : (double)((long)a - (long)b)",
+        evaluate: |args| integer_operation(args, i32::checked_sub),
+    },
+    BuiltinFunction {
+        name: "multiply_wrapping",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } \
+            else { f64::from((a as i32).wrapping_mul(b as i32)) }",
+        opencl_body: "isnan(a) || isnan(b) ? NAN : (double)as_int((uint)(int)a * (uint)(int)b)",
+        evaluate: |args| integer_operation(args, |a, b| Some(a.wrapping_mul(b))),
+    },
+    BuiltinFunction {
+        name: "multiply_checked",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } \
+            else { (a as i32).checked_mul(b as i32).map_or(f64::NAN, f64::from) }",
+        opencl_body: "isnan(a) || isnan(b) || (long)a * (long)b < INT_MIN \
+            || (long)a * (long)b > INT_MAX ? NAN : (double)((long)a * (long)b)",
+        evaluate: |args| integer_operation(args, i32::checked_mul),
+    },
+    BuiltinFunction {
+        name: "negate_wrapping",
+        arity: 1,
+        rust_body: "if a.is_nan() { f64::NAN } else { f64::from((a as i32).wrapping_neg()) }",
+        opencl_body: "isnan(a) ? NAN : (double)as_int(0u - (uint)(int)a)",
+        evaluate: |args| integer_operation(&[0., args[0]], |_, a| Some(a.wrapping_neg())),
+    },
+    BuiltinFunction {
+        name: "negate_checked",
+        arity: 1,
+        rust_body: "if a.is_nan() { f64::NAN } \
+            else { (a as i32).checked_neg().map_or(f64::NAN, f64::from) }",
+        opencl_body: "isnan(a) || -(long)a > INT_MAX ? NAN : (double)(-(long)a)",
+        evaluate: |args| integer_operation(&[0., args[0]], |_, a| a.checked_neg()),
+    },
+    BuiltinFunction {
+        name: "floordivide_wrapping",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() || b == 0. { f64::NAN } \
+            else { let (a, b) = (a as i32, b as i32); let q = a.wrapping_div(b); \
+            f64::from(if a.wrapping_rem(b) != 0 && (a < 0) != (b < 0) { q - 1 } else { q }) }",
+        opencl_body: "isnan(a) || isnan(b) || b == 0.0 \
+            ? NAN : (double)as_int((uint)(long)floor(a / b))",
+        evaluate: |args| {
+            integer_operation(args, |a, b| {
+                floor_divide(a, b, |a, b| Some(a.wrapping_div(b)))
+            })
+        },
+    },
+    BuiltinFunction {
+        name: "floordivide_checked",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() || b == 0. { f64::NAN } \
+            else { let (a, b) = (a as i32, b as i32); a.checked_div(b).map_or(f64::NAN, |q| \
+            f64::from(if a.wrapping_rem(b) != 0 && (a < 0) != (b < 0) { q - 1 } else { q })) }",
+        opencl_body: "isnan(a) || isnan(b) || b == 0.0 || floor(a / b) > INT_MAX \
+            ? NAN : (double)(long)floor(a / b)",
+        evaluate: |args| integer_operation(args, |a, b| floor_divide(a, b, i32::checked_div)),
+    },
+    // cannot overflow, `i32::MIN % -1` is `0`
+    BuiltinFunction {
+        name: "remainder_integer",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() || b == 0. { f64::NAN } \
+            else { let (a, b) = (a as i32, b as i32); let r = a.wrapping_rem(b); \
+            f64::from(if r != 0 && (r < 0) != (b < 0) { r + b } else { r }) }",
+        opencl_body: "isnan(a) || isnan(b) || b == 0.0 \
+            ? NAN : (double)((long)a - (long)b * (long)floor(a / b))",
+        evaluate: |args| integer_operation(args, remainder),
+    },
+    BuiltinFunction {
+        name: "bitand_integer",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } \
+            else { f64::from((a as i32) & (b as i32)) }",
+        opencl_body: "isnan(a) || isnan(b) ? NAN : (double)((int)a & (int)b)",
+        evaluate: |args| integer_operation(args, |a, b| Some(a & b)),
+    },
+    BuiltinFunction {
+        name: "bitor_integer",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } \
+            else { f64::from((a as i32) | (b as i32)) }",
+        opencl_body: "isnan(a) || isnan(b) ? NAN : (double)((int)a | (int)b)",
+        evaluate: |args| integer_operation(args, |a, b| Some(a | b)),
+    },
+    BuiltinFunction {
+        name: "bitxor_integer",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } \
+            else { f64::from((a as i32) ^ (b as i32)) }",
+        opencl_body: "isnan(a) || isnan(b) ? NAN : (double)((int)a ^ (int)b)",
+        evaluate: |args| integer_operation(args, |a, b| Some(a ^ b)),
+    },
+    // the shift amount is taken modulo 32
+    BuiltinFunction {
+        name: "shiftleft_wrapping",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } \
+            else { f64::from((a as i32).wrapping_shl(b as i32 as u32)) }",
+        opencl_body: "isnan(a) || isnan(b) ? NAN : (double)as_int((uint)(int)a << ((int)b & 31))",
+        evaluate: |args| integer_operation(args, |a, b| Some(a.wrapping_shl(b as u32))),
+    },
+    // shift amounts outside of `0..32` are invalid
+    BuiltinFunction {
+        name: "shiftleft_checked",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } \
+            else { (a as i32).checked_shl(b as i32 as u32).map_or(f64::NAN, f64::from) }",
+        opencl_body: "isnan(a) || isnan(b) || b < 0.0 || b > 31.0 \
+            ? NAN : (double)as_int((uint)(int)a << (int)b)",
+        evaluate: |args| integer_operation(args, |a, b| a.checked_shl(b as u32)),
+    },
+    BuiltinFunction {
+        name: "shiftright_wrapping",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } \
+            else { f64::from((a as i32).wrapping_shr(b as i32 as u32)) }",
+        opencl_body: "isnan(a) || isnan(b) ? NAN : (double)((int)a >> ((int)b & 31))",
+        evaluate: |args| integer_operation(args, |a, b| Some(a.wrapping_shr(b as u32))),
+    },
+    BuiltinFunction {
+        name: "shiftright_checked",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } \
+            else { (a as i32).checked_shr(b as i32 as u32).map_or(f64::NAN, f64::from) }",
+        opencl_body: "isnan(a) || isnan(b) || b < 0.0 || b > 31.0 \
+            ? NAN : (double)((int)a >> (int)b)",
+        evaluate: |args| integer_operation(args, |a, b| a.checked_shr(b as u32)),
+    },
+    BuiltinFunction {
+        name: "remainder_float",
+        arity: 2,
+        rust_body: "{ let r = a % b; if r != 0. && (r < 0.) != (b < 0.) { r + b } else { r } }",
+        opencl_body: "fmod(a, b) != 0.0 && (fmod(a, b) < 0.0) != (b < 0.0) \
+            ? fmod(a, b) + b : fmod(a, b)",
+        evaluate: |args| {
+            let (a, b) = (args[0], args[1]);
+            let r = a % b;
+            if r != 0. && (r < 0.) != (b < 0.) {
+                r + b
+            } else {
+                r
+            }
+        },
+    },
+    BuiltinFunction {
+        name: "floordivide_float",
+        arity: 2,
+        rust_body: "f64::floor(a / b)",
+        opencl_body: "floor(a / b)",
+        evaluate: |args| f64::floor(args[0] / args[1]),
+    },
+];
+
+/// Functions for expressions with no-data values, which is `NaN`.
+///
+/// They replace the builtin functions that ignore `NaN` arguments, and mark the no-data values of
+/// the parameters. The names contain `_`, so expressions cannot call them directly.
+const NODATA_FUNCTIONS: &[BuiltinFunction] = &[
+    // nodata_value(x, nodata)
+    BuiltinFunction {
+        name: "nodata_value",
+        arity: 2,
+        rust_body: "if a == b { f64::NAN } else { a }",
+        opencl_body: "a == b ? NAN : a",
+        evaluate: |args| {
+            if args[0] == args[1] {
+                f64::NAN
+            } else {
+                args[0]
+            }
+        },
+    },
+    // nodata_mask(x, mask), zero or no-data in the mask mark no-data
+    BuiltinFunction {
+        name: "nodata_mask",
+        arity: 2,
+        rust_body: "if b == 0. || b.is_nan() { f64::NAN } else { a }",
+        opencl_body: "b == 0.0 || isnan(b) ? NAN : a",
+        evaluate: |args| {
+            if args[1] == 0. || args[1].is_nan() {
+                f64::NAN
+            } else {
+                args[0]
+            }
+        },
+    },
+    BuiltinFunction {
+        name: "min_nodata",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } else { f64::min(a, b) }",
+        opencl_body: "isnan(a) || isnan(b) ? NAN : fmin(a, b)",
+        evaluate: |args| propagate_nodata(args, |args| f64::min(args[0], args[1])),
+    },
+    BuiltinFunction {
+        name: "max_nodata",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } else { f64::max(a, b) }",
+        opencl_body: "isnan(a) || isnan(b) ? NAN : fmax(a, b)",
+        evaluate: |args| propagate_nodata(args, |args| f64::max(args[0], args[1])),
+    },
+    BuiltinFunction {
+        name: "clamp_nodata",
+        arity: 3,
+        rust_body: "if a.is_nan() || b.is_nan() || c.is_nan() { f64::NAN } \
+            else { f64::min(f64::max(a, b), c) }",
+        opencl_body: "isnan(a) || isnan(b) || isnan(c) ? NAN : fmin(fmax(a, b), c)",
+        evaluate: |args| {
+            propagate_nodata(args, |args| f64::min(f64::max(args[0], args[1]), args[2]))
+        },
+    },
+    // `hypot(inf, NaN)` is `inf`
+    BuiltinFunction {
+        name: "hypot_nodata",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } else { f64::hypot(a, b) }",
+        opencl_body: "isnan(a) || isnan(b) ? NAN : hypot(a, b)",
+        evaluate: |args| propagate_nodata(args, |args| f64::hypot(args[0], args[1])),
+    },
+    // `pow(1, NaN)` and `pow(NaN, 0)` are `1`
+    BuiltinFunction {
+        name: "pow_nodata",
+        arity: 2,
+        rust_body: "if a.is_nan() || b.is_nan() { f64::NAN } else { f64::powf(a, b) }",
+        opencl_body: "isnan(a) || isnan(b) ? NAN : pow(a, b)",
+        evaluate: |args| propagate_nodata(args, |args| f64::powf(args[0], args[1])),
+    },
+];
+
+/// The builtin functions that have a variant in [`NODATA_FUNCTIONS`] since they ignore `NaN`
+pub(crate) fn ignores_nodata(name: &str) -> bool {
+    matches!(name, "min" | "max" | "clamp" | "hypot" | "pow")
+}
+
+/// `NaN` if any argument is `NaN`, otherwise the result of `function`
+fn propagate_nodata(args: &[f64], function: fn(&[f64]) -> f64) -> f64 {
+    if args.iter().any(|arg| arg.is_nan()) {
+        f64::NAN
+    } else {
+        function(args)
+    }
+}
+
+fn coalesce(a: f64, b: f64) -> f64 {
+    if a.is_nan() {
+        b
+    } else {
+        a
+    }
+}
+
+/// Applies `operation` to the arguments as `i32`s, `NaN` arguments and `None` results become `NaN`
+fn integer_operation(args: &[f64], operation: impl Fn(i32, i32) -> Option<i32>) -> f64 {
+    let (a, b) = (args[0], args[1]);
+
+    if a.is_nan() || b.is_nan() {
+        return f64::NAN;
+    }
+
+    operation(a as i32, b as i32).map_or(f64::NAN, f64::from)
+}
+
+/// Turns the quotient of `divide`, which rounds towards zero, into the one of a floor division
+fn floor_divide(a: i32, b: i32, divide: fn(i32, i32) -> Option<i32>) -> Option<i32> {
+    if b == 0 {
+        return None;
+    }
+
+    divide(a, b).map(|q| {
+        if a.wrapping_rem(b) != 0 && (a < 0) != (b < 0) {
+            q - 1
+        } else {
+            q
+        }
+    })
+}
+
+/// The remainder of a floor division, which has the sign of `b`
+fn remainder(a: i32, b: i32) -> Option<i32> {
+    if b == 0 {
+        return None;
+    }
+
+    let r = a.wrapping_rem(b);
+    Some(if r != 0 && (r < 0) != (b < 0) {
+        r + b
+    } else {
+        r
+    })
+}
+
+fn sign(a: f64) -> f64 {
+    if a > 0. {
+        1.
+    } else if a < 0. {
+        -1.
+    } else {
+        a
+    }
+}
+
+/// All functions that expressions can call without registering them
+pub fn builtin_functions() -> &'static [BuiltinFunction] {
+    BUILTIN_FUNCTIONS
+}
+
+pub(crate) fn builtin_function(name: &str) -> Option<&'static BuiltinFunction> {
+    BUILTIN_FUNCTIONS
+        .iter()
+        .chain(OPERATOR_FUNCTIONS)
+        .chain(NODATA_FUNCTIONS)
+        .find(|function| function.name == name)
+}
+
+/// A function that is defined by the user of the library
+#[derive(Debug, Clone)]
+pub struct UserFunction {
+    name: String,
+    parameters: Vec<String>,
+    body: UserFunctionBody,
+}
+
+#[derive(Debug, Clone)]
+enum UserFunctionBody {
+    /// Rust code for the code generation that refers to the arguments by their parameter names
+    Rust(String),
+    /// A definition in the expression language
+    Expression(Box<Ast>),
+}
+
+impl UserFunction {
+    /// A function whose body is Rust code, e.g. `(a - b) / (a + b)` for the parameters `a` and `b`.
+    ///
+    /// User defined functions with a Rust body only work with the code generation of
+    /// [`CompiledExpression`](crate::CompiledExpression). The interpreter and the other backends
+    /// return an error for expressions that call them.
+    pub fn rust(name: String, parameters: &[String], body: String) -> Self {
+        Self {
+            name,
+            parameters: parameters.to_vec(),
+            body: UserFunctionBody::Rust(body),
+        }
+    }
+
+    /// A function that is defined by an expression.
+    /// Its name and parameters are the ones of the `Ast`.
+    /// Calls pass `f64` values, so the data types of the `Ast` do not apply.
+    pub fn expression(ast: Ast) -> Self {
+        Self {
+            name: ast.name().to_string(),
+            parameters: ast.parameters().iter().map(ToString::to_string).collect(),
+            body: UserFunctionBody::Expression(Box::new(ast)),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arity(&self) -> usize {
+        self.parameters.len()
+    }
+}
+
+/// User defined functions that expressions can call in addition to the builtin ones
+#[derive(Debug, Clone, Default)]
+pub struct FunctionRegistry {
+    functions: Vec<UserFunction>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, function: UserFunction) -> Result<(), FunctionRegistryError> {
+        for name in std::iter::once(&function.name).chain(&function.parameters) {
+            if !is_identifier(name) {
+                return Err(FunctionRegistryError::InvalidName { name: name.clone() });
+            }
+        }
+
+        if self.get(&function.name).is_some() {
+            return Err(FunctionRegistryError::DuplicateFunction {
+                name: function.name,
+            });
+        }
+
+        match &function.body {
+            UserFunctionBody::Rust(body) => {
+                if let Err(error) = body.parse::<TokenStream>() {
+                    return Err(FunctionRegistryError::InvalidRustBody {
+                        name: function.name,
+                        message: error.to_string(),
+                    });
+                }
+            }
+            UserFunctionBody::Expression(ast) => {
+                let missing = ast
+                    .imports()
+                    .into_iter()
+                    .find(|dependency| self.get(dependency).is_none());
+
+                if let Some(dependency) = missing {
+                    return Err(FunctionRegistryError::MissingDependency {
+                        name: function.name,
+                        dependency,
+                    });
+                }
+            }
+        }
+
+        self.functions.push(function);
+
+        Ok(())
+    }
+
+    /// Looks up a builtin or user defined function
+    pub(crate) fn get(&self, name: &str) -> Option<Function<'_>> {
+        if let Some(function) = builtin_function(name) {
+            return Some(Function::Builtin(function));
+        }
+
+        self.functions
+            .iter()
+            .find(|function| function.name == name)
+            .map(Function::User)
+    }
+
+    /// Names of all functions that can be called
+    pub(crate) fn names(&self) -> impl Iterator<Item = &str> {
+        BUILTIN_FUNCTIONS
+            .iter()
+            .map(|function| -> &str { function.name })
+            .chain(self.functions.iter().map(UserFunction::name))
+    }
+}
+
+/// A function that can be called from an expression
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Function<'f> {
+    Builtin(&'static BuiltinFunction),
+    User(&'f UserFunction),
+}
+
+impl<'f> Function<'f> {
+    pub fn arity(&self) -> usize {
+        match self {
+            Self::Builtin(function) => function.arity(),
+            Self::User(function) => function.arity(),
+        }
+    }
+
+    pub fn parameters(&self) -> Vec<String> {
+        match self {
+            Self::Builtin(function) => function.parameters(),
+            Self::User(function) => function.parameters.clone(),
+        }
+    }
+
+    pub fn rust_body(&self) -> TokenStream {
+        match self {
+            Self::Builtin(function) => function.rust_body(),
+            Self::User(UserFunction {
+                body: UserFunctionBody::Rust(body),
+                ..
+            }) => body
+                .parse()
+                .expect("user function body is checked on registration"),
+            Self::User(UserFunction {
+                body: UserFunctionBody::Expression(ast),
+                ..
+            }) => ast.root().to_token_stream(),
+        }
+    }
+
+    pub fn evaluate(&self, args: &[f64]) -> Result<f64, EvalError> {
+        match self {
+            Self::Builtin(function) => Ok(function.evaluate(args)),
+            Self::User(UserFunction {
+                body: UserFunctionBody::Expression(ast),
+                ..
+            }) => ast.evaluate_f64(args),
+            Self::User(
+                function @ UserFunction {
+                    body: UserFunctionBody::Rust(_),
+                    ..
+                },
+            ) => Err(EvalError::RustFunction {
+                name: function.name.clone(),
+            }),
+        }
+    }
+
+    /// The definition of a function that is defined by an expression
+    pub fn expression(&self) -> Option<&'f Ast> {
+        match self {
+            Self::User(UserFunction {
+                body: UserFunctionBody::Expression(ast),
+                ..
+            }) => Some(ast),
+            _ => None,
+        }
+    }
+
+    /// Functions that the body of this function calls
+    pub fn dependencies(&self) -> Vec<String> {
+        match self {
+            Self::User(UserFunction {
+                body: UserFunctionBody::Expression(ast),
+                ..
+            }) => ast.imports(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FunctionRegistryError {
+    DuplicateFunction {
+        name: String,
+    },
+    InvalidName {
+        name: String,
+    },
+    InvalidRustBody {
+        name: String,
+        message: String,
+    },
+    /// The function calls a user defined function that is not part of the registry
+    MissingDependency {
+        name: String,
+        dependency: String,
+    },
+}
+
+impl fmt::Display for FunctionRegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicateFunction { name } => {
+                write!(f, "function `{}` is already defined", name)
+            }
+            Self::InvalidName { name } => write!(f, "`{}` is not a valid identifier", name),
+            Self::InvalidRustBody { name, message } => {
+                write!(
+                    f,
+                    "body of function `{}` is not valid Rust: {}",
+                    name, message
+                )
+            }
+            Self::MissingDependency { name, dependency } => write!(
+                f,
+                "function `{}` calls `{}` which is not registered",
+                name, dependency
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FunctionRegistryError {}
+
+/// Words of the grammar that are not identifiers, `true` and `false` also in other cases
+const GRAMMAR_KEYWORDS: &[&str] = &["if", "else", "let", "xor", "nan", "inf"];
+
+/// Strict and reserved keywords of Rust, which the generated code cannot use as names
+const RUST_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Checks that expressions can refer to `name` and that it is a name in the generated code,
+/// cf. `identifier` in the grammar
+pub(crate) fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric())
+        && !GRAMMAR_KEYWORDS.contains(&name)
+        && !name.eq_ignore_ascii_case("true")
+        && !name.eq_ignore_ascii_case("false")
+        && !RUST_KEYWORDS.contains(&name)
+}
@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use cranelift_codegen::ir::condcodes::FloatCC;
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, Function as IrFunction, InstBuilder, MemFlags, Signature,
+    StackSlotData, StackSlotKind, Type, Value,
+};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{FuncId, Linkage, Module, ModuleError};
+
+use crate::data_type::DataType;
+use crate::eval::EvalError;
+use crate::functions::{BuiltinFunction, Function};
+use crate::{
+    Ast, AstNode, AstNodeKind, AstOperator, BooleanComparator, BooleanExpression,
+    BooleanExpressionKind, BooleanOperator,
+};
+
+/// Name of the host function that the generated code calls for builtin functions
+const TRAMPOLINE_SYMBOL: &str = "__math_expr_builtin";
+
+/// Signature of the generated entry point, which reads the parameters from an array
+type CallFn = unsafe extern "C" fn(*const f64) -> f64;
+
+/// Calls a builtin function for the generated code, so that it computes exactly the same as Rust
+extern "C" fn call_builtin(function: *const BuiltinFunction, args: *const f64) -> f64 {
+    // SAFETY: the generated code passes a `&'static BuiltinFunction` and as many arguments as its arity
+    let function = unsafe { &*function };
+    let args = unsafe { std::slice::from_raw_parts(args, function.arity()) };
+
+    function.evaluate(args)
+}
+
+/// An expression that was compiled to machine code in-process with Cranelift.
+///
+/// Compilation takes well below a millisecond instead of the seconds of [`crate::Compiler`],
+/// at the price of less optimized code.
+pub struct JitExpression {
+    function: CallFn,
+    arity: usize,
+    // `function` points into the memory of the module, which is freed on drop
+    module: Option<JITModule>,
+}
+
+// `JITModule` is `Send`, so is `JitExpression`, but it is not `Sync` since it keeps the symbols of
+// the module in a `RefCell`.
+//
+// SAFETY: a shared `JitExpression` only exposes `function` and `arity`, the module is only
+// accessed through `&mut self` when it is dropped. Calls of the finalized code can run
+// concurrently: it only reads its parameters and the global offset table, which is not changed
+// after `finalize_definitions`, and it calls builtin functions that are `'static` and pure.
+unsafe impl Sync for JitExpression {}
+
+impl JitExpression {
+    pub fn new(ast: &Ast) -> Result<Self, JitError> {
+        let mut flags = settings::builder();
+        flags
+            .set("opt_level", "speed")
+            .expect("`opt_level` is a valid setting");
+
+        let isa = cranelift_native::builder()
+            .map_err(|message| JitError::Isa(message.to_string()))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|error| JitError::Isa(error.to_string()))?;
+
+        let mut builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+        builder.symbol(TRAMPOLINE_SYMBOL, call_builtin as *const u8);
+
+        let mut compiler = JitCompiler::new(JITModule::new(builder))?;
+
+        for name in ast.imports() {
+            let function = ast
+                .functions
+                .get(&name)
+                .expect("imports are checked when building the ast");
+
+            if let Function::User(_) = function {
+                let expression = function
+                    .expression()
+                    .ok_or_else(|| JitError::RustFunction {
+                        name: name.to_string(),
+                    })?;
+                compiler.function(expression)?;
+            }
+        }
+
+        let id = compiler.entry_point(ast)?;
+
+        let mut module = compiler.module;
+        module.finalize_definitions()?;
+
+        // SAFETY: the entry point was generated with the `CallFn` signature
+        let function =
+            unsafe { std::mem::transmute::<*const u8, CallFn>(module.get_finalized_function(id)) };
+
+        Ok(Self {
+            function,
+            arity: ast.parameters().len(),
+            module: Some(module),
+        })
+    }
+
+    /// Number of parameters of the expression
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Calls the expression with `parameters` in the order of the declared parameters
+    pub fn call(&self, parameters: &[f64]) -> Result<f64, EvalError> {
+        if parameters.len() != self.arity {
+            return Err(EvalError::WrongParameterCount {
+                expected: self.arity,
+                actual: parameters.len(),
+            });
+        }
+
+        // SAFETY: the length of `parameters` matches the arity of the entry point
+        Ok(unsafe { (self.function)(parameters.as_ptr()) })
+    }
+}
+
+impl Drop for JitExpression {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // SAFETY: `self.function` is the only pointer into the module and it dies with `self`
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+impl fmt::Debug for JitExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JitExpression")
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Defines the functions of one expression in a module
+struct JitCompiler {
+    module: JITModule,
+    context: FunctionBuilderContext,
+    trampoline: FuncId,
+    /// User defined functions that were already defined
+    functions: HashMap<String, FuncId>,
+}
+
+impl JitCompiler {
+    fn new(mut module: JITModule) -> Result<Self, JitError> {
+        let pointer = module.target_config().pointer_type();
+
+        let mut signature = module.make_signature();
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(pointer));
+        signature.returns.push(AbiParam::new(types::F64));
+
+        let trampoline = module.declare_function(TRAMPOLINE_SYMBOL, Linkage::Import, &signature)?;
+
+        Ok(Self {
+            module,
+            context: FunctionBuilderContext::new(),
+            trampoline,
+            functions: HashMap::new(),
+        })
+    }
+
+    /// Defines a user defined function that takes its parameters as `f64` arguments
+    fn function(&mut self, ast: &Ast) -> Result<(), JitError> {
+        let mut signature = self.module.make_signature();
+        for _ in ast.parameters() {
+            signature.params.push(AbiParam::new(types::F64));
+        }
+        signature.returns.push(AbiParam::new(types::F64));
+
+        let id = self
+            .module
+            .declare_function(ast.name(), Linkage::Local, &signature)?;
+
+        // calls from other expressions pass `f64` values, so there are no conversions
+        self.define(id, signature, ast, DataType::F64, |builder, entry| {
+            builder.block_params(entry).to_vec()
+        })?;
+
+        self.functions.insert(ast.name().to_string(), id);
+
+        Ok(())
+    }
+
+    /// Defines the entry point that reads the parameters from an array
+    fn entry_point(&mut self, ast: &Ast) -> Result<FuncId, JitError> {
+        let pointer = self.module.target_config().pointer_type();
+
+        let mut signature = self.module.make_signature();
+        signature.params.push(AbiParam::new(pointer));
+        signature.returns.push(AbiParam::new(types::F64));
+
+        let id = self.module.declare_anonymous_function(&signature)?;
+
+        self.define(id, signature, ast, ast.output_type(), |builder, entry| {
+            let parameters = builder.block_params(entry)[0];
+
+            ast.parameter_types()
+                .iter()
+                .enumerate()
+                .map(|(i, dtype)| {
+                    let value = builder.ins().load(
+                        types::F64,
+                        MemFlags::trusted(),
+                        parameters,
+                        (8 * i) as i32,
+                    );
+                    cast(builder, value, *dtype)
+                })
+                .collect()
+        })?;
+
+        Ok(id)
+    }
+
+    /// Defines the function `id` that computes `ast` with the parameter values that `parameters` emits
+    /// and converts the result to `output_type`
+    fn define(
+        &mut self,
+        id: FuncId,
+        signature: Signature,
+        ast: &Ast,
+        output_type: DataType,
+        parameters: impl FnOnce(&mut FunctionBuilder, Block) -> Vec<Value>,
+    ) -> Result<(), JitError> {
+        let mut context = self.module.make_context();
+        context.func = IrFunction::with_name_signature(Default::default(), signature);
+
+        let mut builder = FunctionBuilder::new(&mut context.func, &mut self.context);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+
+        let parameters = parameters(&mut builder, entry);
+
+        let mut lowering = FunctionLowering {
+            builder,
+            module: &mut self.module,
+            trampoline: self.trampoline,
+            functions: &self.functions,
+            ast,
+            scope: ast
+                .parameters()
+                .iter()
+                .map(String::as_str)
+                .zip(parameters)
+                .collect(),
+        };
+
+        let result = lowering.value(ast.root());
+        let result = cast(&mut lowering.builder, result, output_type);
+        lowering.builder.ins().return_(&[result]);
+        lowering.builder.seal_all_blocks();
+        lowering.builder.finalize();
+
+        self.module.define_function(id, &mut context)?;
+        self.module.clear_context(&mut context);
+
+        Ok(())
+    }
+}
+
+/// Converts `value` to `data_type` and back like [`DataType::cast`]
+fn cast(builder: &mut FunctionBuilder, value: Value, data_type: DataType) -> Value {
+    match data_type {
+        DataType::F64 => value,
+        DataType::F32 => {
+            let value = builder.ins().fdemote(types::F32, value);
+            builder.ins().fpromote(types::F64, value)
+        }
+        DataType::U8 | DataType::U16 | DataType::I16 => {
+            let (min, max) = data_type
+                .integer_bounds()
+                .expect("integer types have bounds");
+
+            // every integer type fits into `i32`, whose saturating conversion also turns `NaN` into `0`
+            let value = builder.ins().fcvt_to_sint_sat(types::I32, value);
+            let value = builder.ins().fcvt_from_sint(types::F64, value);
+            let min = builder.ins().f64const(min);
+            let max = builder.ins().f64const(max);
+            let value = builder.ins().fmax(value, min);
+            builder.ins().fmin(value, max)
+        }
+    }
+}
+
+/// Translates the nodes of an `Ast` into instructions of one function
+struct FunctionLowering<'a, 'b> {
+    builder: FunctionBuilder<'b>,
+    module: &'a mut JITModule,
+    trampoline: FuncId,
+    functions: &'a HashMap<String, FuncId>,
+    ast: &'a Ast,
+    /// Visible variables and their values, later ones shadow earlier ones
+    scope: Vec<(&'a str, Value)>,
+}
+
+impl<'a, 'b> FunctionLowering<'a, 'b> {
+    fn value(&mut self, node: &'a AstNode) -> Value {
+        match node.kind() {
+            AstNodeKind::Constant(n) => self.builder.ins().f64const(*n),
+            AstNodeKind::Integer(n) => self.builder.ins().f64const(f64::from(*n)),
+            AstNodeKind::NamedConstant(constant) => self.builder.ins().f64const(constant.value()),
+            AstNodeKind::Variable(identifier) => self
+                .scope
+                .iter()
+                .rev()
+                .find(|(name, _)| *name == identifier)
+                .map(|(_, value)| *value)
+                .expect("variables are checked when building the ast"),
+            AstNodeKind::Negation(operand) => {
+                let operand = self.value(operand);
+                self.builder.ins().fneg(operand)
+            }
+            AstNodeKind::Operation { left, op, right } => {
+                let left = self.value(left);
+                let right = self.value(right);
+                match op {
+                    AstOperator::Add => self.builder.ins().fadd(left, right),
+                    AstOperator::Subtract => self.builder.ins().fsub(left, right),
+                    AstOperator::Multiply => self.builder.ins().fmul(left, right),
+                    AstOperator::Divide => self.builder.ins().fdiv(left, right),
+                    op => unreachable!("`{}` is replaced when the types are checked", op.symbol()),
+                }
+            }
+            AstNodeKind::Function { name, args } => {
+                let args = args.iter().map(|arg| self.value(arg)).collect::<Vec<_>>();
+                self.call(name, &args)
+            }
+            AstNodeKind::Branch {
+                condition_branches,
+                else_branch,
+            } => {
+                let merge = self.builder.create_block();
+                self.builder.append_block_param(merge, types::F64);
+
+                for branch in condition_branches {
+                    let condition = self.condition(branch.condition());
+
+                    let then_block = self.builder.create_block();
+                    let else_block = self.builder.create_block();
+                    self.builder
+                        .ins()
+                        .brif(condition, then_block, &[], else_block, &[]);
+
+                    self.builder.switch_to_block(then_block);
+                    let value = self.value(branch.body());
+                    self.builder.ins().jump(merge, &[value]);
+
+                    self.builder.switch_to_block(else_block);
+                }
+
+                let value = self.value(else_branch);
+                self.builder.ins().jump(merge, &[value]);
+
+                self.builder.switch_to_block(merge);
+                self.builder.block_params(merge)[0]
+            }
+            AstNodeKind::AssignmentsAndExpression {
+                assignments,
+                expression,
+            } => {
+                let scope_start = self.scope.len();
+
+                for assignment in assignments {
+                    let value = self.value(assignment.expression());
+                    self.scope.push((assignment.identifier(), value));
+                }
+
+                let value = self.value(expression);
+
+                self.scope.truncate(scope_start);
+
+                value
+            }
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[Value]) -> Value {
+        if let Some(id) = self.functions.get(name) {
+            let callee = self.module.declare_func_in_func(*id, self.builder.func);
+            let call = self.builder.ins().call(callee, args);
+            return self.builder.inst_results(call)[0];
+        }
+
+        let builtin = match self.ast.functions.get(name) {
+            Some(Function::Builtin(builtin)) => builtin,
+            _ => unreachable!("user defined functions are defined before they are called"),
+        };
+
+        // instructions that compute exactly the same as the Rust body
+        match (builtin.name(), args) {
+            (_, []) => return self.builder.ins().f64const(builtin.evaluate(&[])),
+            ("abs", [x]) => return self.builder.ins().fabs(*x),
+            ("sqrt", [x]) => return self.builder.ins().sqrt(*x),
+            ("floor", [x]) => return self.builder.ins().floor(*x),
+            ("ceil", [x]) => return self.builder.ins().ceil(*x),
+            _ => {}
+        }
+
+        let pointer = self.module.target_config().pointer_type();
+
+        let slot = self.builder.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            (8 * args.len()) as u32,
+            3,
+        ));
+        for (i, arg) in args.iter().enumerate() {
+            self.builder.ins().stack_store(*arg, slot, (8 * i) as i32);
+        }
+        let args = self.builder.ins().stack_addr(pointer, slot, 0);
+
+        let function = self
+            .builder
+            .ins()
+            .iconst(pointer, builtin as *const BuiltinFunction as i64);
+
+        let callee = self
+            .module
+            .declare_func_in_func(self.trampoline, self.builder.func);
+        let call = self.builder.ins().call(callee, &[function, args]);
+        self.builder.inst_results(call)[0]
+    }
+
+    /// Emits a condition as an `i8` that is `0` or `1`
+    fn condition(&mut self, expression: &'a BooleanExpression) -> Value {
+        const BOOL: Type = types::I8;
+
+        match expression.kind() {
+            BooleanExpressionKind::Constant(b) => self.builder.ins().iconst(BOOL, i64::from(*b)),
+            BooleanExpressionKind::Not(operand) => {
+                let operand = self.condition(operand);
+                self.builder.ins().bxor_imm(operand, 1)
+            }
+            BooleanExpressionKind::Comparison { left, op, right } => {
+                let left = self.value(left);
+                let right = self.value(right);
+                let condition = match op {
+                    BooleanComparator::Equal => FloatCC::Equal,
+                    // true if either side is NaN, like in Rust
+                    BooleanComparator::NotEqual => FloatCC::NotEqual,
+                    BooleanComparator::LessThan => FloatCC::LessThan,
+                    BooleanComparator::LessThanOrEqual => FloatCC::LessThanOrEqual,
+                    BooleanComparator::GreaterThan => FloatCC::GreaterThan,
+                    BooleanComparator::GreaterThanOrEqual => FloatCC::GreaterThanOrEqual,
+                };
+                self.builder.ins().fcmp(condition, left, right)
+            }
+            BooleanExpressionKind::Operation { left, op, right } => {
+                // expressions have no side effects, so there is no need to short-circuit
+                let left = self.condition(left);
+                let right = self.condition(right);
+                match op {
+                    BooleanOperator::And => self.builder.ins().band(left, right),
+                    BooleanOperator::Or => self.builder.ins().bor(left, right),
+                    BooleanOperator::Xor => self.builder.ins().bxor(left, right),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum JitError {
+    /// The host is not supported by Cranelift
+    Isa(String),
+    /// The expression calls a function with a Rust body, see [`UserFunction::rust`](crate::UserFunction::rust)
+    RustFunction {
+        name: String,
+    },
+    Module(Box<ModuleError>),
+}
+
+impl From<ModuleError> for JitError {
+    fn from(error: ModuleError) -> Self {
+        Self::Module(Box::new(error))
+    }
+}
+
+impl fmt::Display for JitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Isa(message) => write!(f, "unsupported host: {}", message),
+            Self::RustFunction { name } => write!(
+                f,
+                "function `{}` has a Rust body and cannot be compiled just-in-time",
+                name
+            ),
+            Self::Module(error) => write!(f, "cannot compile expression: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for JitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Module(error) => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
@@ -1,41 +1,219 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-
-use pest::iterators::Pairs;
+mod batch;
+mod cache;
+mod compiled;
+mod constants;
+mod cse;
+mod data_type;
+mod diagnostic;
+mod error;
+mod eval;
+mod functions;
+mod jit;
+mod nodata;
+mod opencl;
+mod optimize;
+mod scope;
+mod types;
+mod usage;
+mod vm;
+mod wasm;
+
+use pest::iterators::{Pair, Pairs};
 use pest::prec_climber::{Assoc, Operator, PrecClimber};
 use pest::Parser;
 use pest_derive::Parser;
-use proc_macro2::{Ident, TokenStream};
+use proc_macro2::TokenStream;
 use quote::{format_ident, quote, ToTokens};
 
+pub use crate::batch::{BatchEvaluation, DEFAULT_CHUNK_SIZE};
+pub use crate::cache::CompilationCache;
+pub use crate::compiled::{CompileError, CompiledExpression, Compiler};
+pub use crate::constants::NamedConstant;
+pub use crate::data_type::DataType;
+pub use crate::diagnostic::{Diagnostic, Severity};
+pub use crate::error::{ExpressionError, Span};
+pub use crate::eval::EvalError;
+pub use crate::functions::{
+    builtin_functions, BuiltinFunction, FunctionRegistry, FunctionRegistryError, UserFunction,
+};
+pub use crate::jit::{JitError, JitExpression};
+pub use crate::opencl::{OpenClError, OpenClExpression};
+pub use crate::optimize::{Optimization, OptimizationReport, Optimizer};
+pub use crate::types::OverflowMode;
+pub use crate::usage::Warning;
+pub use crate::vm::BytecodeExpression;
+pub use crate::wasm::{WasmError, WasmExpression};
+
+use crate::functions::is_identifier;
+use crate::scope::{temporary_identifier, SymbolTable};
+
 #[derive(Parser)]
 #[grammar = "expression.pest"] // relative to src
 struct ExpressionParser;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Ast {
     name: String,
     root: AstNode,
-    parameters: Vec<Ident>,
-    variables: Rc<RefCell<Vec<Ident>>>,
-    imports: Rc<RefCell<Vec<Ident>>>,
-    // TODO: dtype Float or Int
+    parameters: Vec<String>,
+    parameter_types: Vec<DataType>,
+    output_type: DataType,
+    integer_arithmetic: Option<OverflowMode>,
+    parameter_nodata: Vec<Option<f64>>,
+    parameter_masks: Vec<Option<String>>,
+    output_nodata: Option<f64>,
+    warnings: Vec<Warning>,
+    imports: Vec<String>,
+    functions: FunctionRegistry,
 }
 
-// TODO: builder pattern
-impl Ast {
-    pub fn new(name: String, parameters: &[String], input: &str) -> Self {
-        let mut this = Self {
+/// Configures how an expression is turned into an [`Ast`]
+#[derive(Debug, Clone)]
+pub struct AstBuilder {
+    name: String,
+    parameters: Vec<String>,
+    parameter_types: Vec<DataType>,
+    output_type: DataType,
+    integer_arithmetic: Option<OverflowMode>,
+    parameter_nodata: Vec<Option<f64>>,
+    parameter_masks: Vec<Option<String>>,
+    output_nodata: Option<f64>,
+    functions: FunctionRegistry,
+}
+
+impl AstBuilder {
+    pub fn new(name: String, parameters: &[String]) -> Self {
+        Self {
             name,
-            root: AstNode::Constant(0.), // TODO: this is bad
-            parameters: parameters.iter().map(|v| format_ident!("{}", v)).collect(),
-            variables: Rc::new(RefCell::new(Vec::new())),
-            imports: Rc::new(RefCell::new(vec![])),
+            parameters: parameters.to_vec(),
+            parameter_types: vec![DataType::default(); parameters.len()],
+            output_type: DataType::default(),
+            integer_arithmetic: None,
+            parameter_nodata: vec![None; parameters.len()],
+            parameter_masks: vec![None; parameters.len()],
+            output_nodata: None,
+            functions: FunctionRegistry::default(),
+        }
+    }
+
+    /// The type of the values of `parameter`, [`DataType::F64`] by default
+    ///
+    /// # Panics
+    ///
+    /// Panics if the expression has no parameter named `parameter`.
+    pub fn parameter_type(mut self, parameter: &str, data_type: DataType) -> Self {
+        let index = self.parameter_index(parameter);
+        self.parameter_types[index] = data_type;
+        self
+    }
+
+    /// The value that marks no-data in `parameter`, after converting it to `f64`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the expression has no parameter named `parameter`.
+    pub fn parameter_nodata(mut self, parameter: &str, nodata: f64) -> Self {
+        let index = self.parameter_index(parameter);
+        self.parameter_nodata[index] = Some(nodata);
+        self
+    }
+
+    /// The parameter `mask` marks where `parameter` is valid, zero or no-data in it mean no-data
+    ///
+    /// # Panics
+    ///
+    /// Panics if the expression has no parameter named `parameter` or `mask`.
+    pub fn parameter_mask(mut self, parameter: &str, mask: &str) -> Self {
+        let index = self.parameter_index(parameter);
+        self.parameter_index(mask);
+        self.parameter_masks[index] = Some(mask.to_string());
+        self
+    }
+
+    /// The type that the result is converted to, [`DataType::F64`] by default
+    pub fn output_type(mut self, data_type: DataType) -> Self {
+        self.output_type = data_type;
+        self
+    }
+
+    /// The value of the result where it is no-data, before converting it to the output type
+    pub fn output_nodata(mut self, nodata: f64) -> Self {
+        self.output_nodata = Some(nodata);
+        self
+    }
+
+    /// Computes with integers in the range of `i32` where all operands are integers,
+    /// instead of promoting them to floats.
+    ///
+    /// Bitwise operators and shifts only work with integer arithmetic.
+    pub fn integer_arithmetic(mut self, overflow: OverflowMode) -> Self {
+        self.integer_arithmetic = Some(overflow);
+        self
+    }
+
+    /// User defined functions that the expression can call
+    pub fn functions(mut self, functions: FunctionRegistry) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    fn parameter_index(&self, parameter: &str) -> usize {
+        self.parameters
+            .iter()
+            .position(|name| name == parameter)
+            .unwrap_or_else(|| panic!("unknown parameter `{}`", parameter))
+    }
+
+    pub fn build(self, input: &str) -> Result<Ast, ExpressionError> {
+        if !is_identifier(&self.name) {
+            return Err(ExpressionError::InvalidName {
+                name: self.name,
+                span: Span::new(0, 0),
+            });
+        }
+
+        if let Some(name) = self.parameters.iter().find(|name| !is_identifier(name)) {
+            return Err(ExpressionError::InvalidParameter {
+                name: name.clone(),
+                span: Span::new(0, 0),
+            });
+        }
+
+        if self.functions.get(&self.name).is_some() {
+            return Err(ExpressionError::FunctionNamedLikeExpression {
+                name: self.name,
+                span: Span::new(0, 0),
+            });
+        }
+
+        let mut ast = Ast {
+            name: self.name,
+            root: AstNode::new(AstNodeKind::Constant(0.), Span::new(0, 0)), // TODO: this is bad
+            parameters: self.parameters,
+            parameter_types: self.parameter_types,
+            output_type: self.output_type,
+            integer_arithmetic: self.integer_arithmetic,
+            parameter_nodata: self.parameter_nodata,
+            parameter_masks: self.parameter_masks,
+            output_nodata: self.output_nodata,
+            warnings: Vec::new(),
+            imports: Vec::new(),
+            functions: self.functions,
         };
 
-        this.parse(input);
+        ast.parse(input)?;
+
+        Ok(ast)
+    }
+}
+
+impl Ast {
+    pub fn new(name: String, parameters: &[String], input: &str) -> Result<Self, ExpressionError> {
+        Self::builder(name, parameters).build(input)
+    }
 
-        this
+    pub fn builder(name: String, parameters: &[String]) -> AstBuilder {
+        AstBuilder::new(name, parameters)
     }
 
     pub fn code(&self) -> String {
@@ -44,21 +222,88 @@ impl Ast {
         rustfmt_wrapper::rustfmt(tokens).unwrap()
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn parameters(&self) -> &[String] {
+        &self.parameters
+    }
+
+    /// The types of the parameters, in the order of the parameters
+    pub fn parameter_types(&self) -> &[DataType] {
+        &self.parameter_types
+    }
+
+    pub fn output_type(&self) -> DataType {
+        self.output_type
+    }
+
+    /// How integers overflow, if the expression uses integer arithmetic
+    pub fn integer_arithmetic(&self) -> Option<OverflowMode> {
+        self.integer_arithmetic
+    }
+
+    /// The values that mark no-data in the parameters, in the order of the parameters
+    pub fn parameter_nodata(&self) -> &[Option<f64>] {
+        &self.parameter_nodata
+    }
+
+    /// The parameters that mask the parameters, in the order of the parameters
+    pub fn parameter_masks(&self) -> &[Option<String>] {
+        &self.parameter_masks
+    }
+
+    /// The value of the result where it is no-data
+    pub fn output_nodata(&self) -> Option<f64> {
+        self.output_nodata
+    }
+
     pub fn root(&self) -> &AstNode {
         &self.root
     }
 
-    fn parse(&mut self, input: &str) {
-        let pairs = ExpressionParser::parse(Rule::main, input).unwrap_or_else(|e| panic!("{}", e));
+    /// Unused parameters and variables in the expression
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
 
-        self.root = self.build_ast(pairs).unwrap();
+    /// Functions that the expression calls, directly or through user defined functions
+    pub(crate) fn imports(&self) -> Vec<String> {
+        self.imports.clone()
     }
 
-    fn build_ast(&self, pairs: Pairs<'_, Rule>) -> Result<AstNode, String> {
+    fn parse(&mut self, input: &str) -> Result<(), ExpressionError> {
+        let pairs = ExpressionParser::parse(Rule::main, input)?;
+
+        let root = self.build_ast(pairs, &mut SymbolTable::new())?;
+        self.warnings = self.unused_warnings(&root);
+        let root = self.check_types(root)?;
+        self.root = self.lower_nodata(root);
+        // the checks replace operators and calls, so only the remaining calls are imported
+        self.imports = self.collect_imports(&self.root);
+
+        Ok(())
+    }
+
+    fn build_ast(
+        &self,
+        pairs: Pairs<'_, Rule>,
+        symbols: &mut SymbolTable,
+    ) -> Result<AstNode, ExpressionError> {
         // TODO: global var
+        // from lowest to highest precedence, like in Rust
         let precedence = PrecClimber::new(vec![
+            Operator::new(Rule::bit_or, Assoc::Left),
+            Operator::new(Rule::bit_xor, Assoc::Left),
+            Operator::new(Rule::bit_and, Assoc::Left),
+            Operator::new(Rule::shift_left, Assoc::Left)
+                | Operator::new(Rule::shift_right, Assoc::Left),
             Operator::new(Rule::add, Assoc::Left) | Operator::new(Rule::subtract, Assoc::Left),
-            Operator::new(Rule::multiply, Assoc::Left) | Operator::new(Rule::divide, Assoc::Left),
+            Operator::new(Rule::multiply, Assoc::Left)
+                | Operator::new(Rule::divide, Assoc::Left)
+                | Operator::new(Rule::floor_divide, Assoc::Left)
+                | Operator::new(Rule::remainder, Assoc::Left),
             Operator::new(Rule::power, Assoc::Right),
         ]);
 
@@ -66,125 +311,183 @@ impl Ast {
             pairs,
             |pair| {
                 // dbg!(&pair);
+                let span: Span = pair.as_span().into();
+
                 match pair.as_rule() {
-                    Rule::number => Ok(AstNode::Constant(pair.as_str().parse().unwrap())),
+                    // literals without a fraction or exponent are integers if they fit into `i32`
+                    Rule::number => Ok(match pair.as_str().parse() {
+                        Ok(n) => AstNode::new(AstNodeKind::Integer(n), span),
+                        Err(_) => AstNode::new(
+                            AstNodeKind::Constant(pair.as_str().parse().unwrap()),
+                            span,
+                        ),
+                    }),
                     Rule::identifier => {
-                        let identifier = format_ident!("{}", pair.as_str());
-                        if self.parameters.contains(&identifier)
-                            || self.variables.borrow().contains(&identifier)
-                        {
-                            Ok(AstNode::Variable(identifier))
+                        let identifier = pair.as_str().to_string();
+                        if self.parameters.contains(&identifier) || symbols.contains(&identifier) {
+                            Ok(AstNode::new(AstNodeKind::Variable(identifier), span))
+                        } else if let Some(assignment) = symbols.later_assignment(&identifier) {
+                            Err(ExpressionError::UseBeforeAssignment {
+                                name: identifier.to_string(),
+                                span,
+                                assignment,
+                            })
+                        } else if let Some(constant) = NamedConstant::from_name(pair.as_str()) {
+                            Ok(AstNode::new(AstNodeKind::NamedConstant(constant), span))
                         } else {
-                            Err(format!("unknown variable {}", identifier))
+                            let candidates = self
+                                .parameters
+                                .iter()
+                                .chain(symbols.names())
+                                .map(ToString::to_string)
+                                .chain(NamedConstant::names().map(ToString::to_string))
+                                .collect::<Vec<_>>();
+
+                            Err(ExpressionError::UnknownVariable {
+                                name: identifier.to_string(),
+                                span,
+                                suggestion: diagnostic::closest_match(
+                                    pair.as_str(),
+                                    candidates.iter().map(String::as_str),
+                                ),
+                            })
+                        }
+                    }
+                    Rule::expression => self.build_ast(pair.into_inner(), symbols),
+                    Rule::unary => {
+                        let mut pairs = pair.into_inner();
+
+                        let operator = pairs.next().expect("unary needs an operator");
+
+                        // the remaining pairs are the operand and its `**` chain
+                        let operand = self.build_ast(pairs, symbols)?;
+
+                        match operator.as_rule() {
+                            Rule::positive => Ok(AstNode::new(operand.kind, span)),
+                            Rule::negative => match operand.kind {
+                                // negative number literals
+                                AstNodeKind::Constant(n) => {
+                                    Ok(AstNode::new(AstNodeKind::Constant(-n), span))
+                                }
+                                // literals are not negative, so this cannot overflow
+                                AstNodeKind::Integer(n) => {
+                                    Ok(AstNode::new(AstNodeKind::Integer(-n), span))
+                                }
+                                _ => {
+                                    Ok(AstNode::new(AstNodeKind::Negation(Box::new(operand)), span))
+                                }
+                            },
+                            _ => {
+                                unreachable!("unexpected unary operator: {:?}", operator.as_rule())
+                            }
                         }
                     }
-                    Rule::expression => self.build_ast(pair.into_inner()),
                     Rule::function => {
                         let mut pairs = pair.into_inner();
 
                         // first one is name
-                        let name = format_ident!("{}", pairs.next().unwrap().as_str());
+                        let name_pair = pairs.next().expect("function needs a name");
+                        let name = name_pair.as_str().to_string();
+
+                        let function = self.functions.get(name_pair.as_str());
+                        // named constants can also be written as calls without arguments, e.g. `pi()`
+                        let constant = match function {
+                            Some(_) => None,
+                            None => NamedConstant::from_name(name_pair.as_str()),
+                        };
+
+                        if function.is_none() && constant.is_none() {
+                            let candidates = self
+                                .functions
+                                .names()
+                                .map(ToString::to_string)
+                                .chain(NamedConstant::names().map(ToString::to_string))
+                                .collect::<Vec<_>>();
+
+                            return Err(ExpressionError::UnknownFunction {
+                                name,
+                                span: name_pair.as_span().into(),
+                                suggestion: diagnostic::closest_match(
+                                    name_pair.as_str(),
+                                    candidates.iter().map(String::as_str),
+                                ),
+                            });
+                        }
 
                         let args = pairs
-                            .map(|pair| self.build_ast(pair.into_inner()))
+                            .map(|pair| self.build_ast(pair.into_inner(), symbols))
                             .collect::<Result<Vec<_>, _>>()?;
 
-                        self.imports.borrow_mut().push(name.clone());
+                        let arity = function.map_or(0, |function| function.arity());
+                        if args.len() != arity {
+                            return Err(ExpressionError::WrongArgumentCount {
+                                name: name.to_string(),
+                                expected: arity,
+                                actual: args.len(),
+                                span,
+                            });
+                        }
 
-                        Ok(AstNode::Function { name, args })
+                        match constant {
+                            Some(constant) => {
+                                Ok(AstNode::new(AstNodeKind::NamedConstant(constant), span))
+                            }
+                            None => Ok(AstNode::new(AstNodeKind::Function { name, args }, span)),
+                        }
                     }
                     Rule::branch => {
-                        // pairs are boolean -> expression
-                        // and last one is just an expression
+                        // pairs are boolean -> block
+                        // and last one is just a block
                         let mut pairs = pair.into_inner();
 
-                        let mut condition_branches: Vec<Branch> = vec![];
+                        // each branch with the operands that its condition evaluates once
+                        let mut condition_branches: Vec<(Branch, Vec<Assignment>)> = vec![];
 
                         while let Some(pair) = pairs.next() {
                             if matches!(pair.as_rule(), Rule::boolean_expression) {
-                                let boolean = self.build_boolean_expression(pair.into_inner())?;
-
-                                let next_pair = pairs.next().ok_or("branch structure malformed")?;
-                                let expression = self.build_ast(next_pair.into_inner())?;
-
-                                condition_branches.push(Branch {
-                                    condition: boolean,
-                                    body: expression,
-                                });
-                            } else {
-                                let expression = self.build_ast(pair.into_inner())?;
-
-                                return Ok(AstNode::Branch {
-                                    condition_branches,
-                                    else_branch: Box::new(expression),
-                                });
-                            }
-                        }
-
-                        Err("unexpected branch structure".to_string())
-                    }
-                    Rule::boolean_expression => {
-                        Err(format!("boolean expression {}", pair.as_str()))
-                    }
-                    Rule::assignments_and_expression => {
-                        let mut assignments: Vec<Assignment> = vec![];
-
-                        for pair in pair.into_inner() {
-                            if matches!(pair.as_rule(), Rule::assignment) {
-                                let mut pairs = pair.into_inner();
-
-                                let first_pair =
-                                    pairs.next().ok_or("assignment needs first pair")?;
-                                let second_pair =
-                                    pairs.next().ok_or("assignment needs second pair")?;
-
-                                let identifier = format_ident!("{}", first_pair.as_str());
-
-                                if self.parameters.contains(&identifier) {
-                                    return Err(format!(
-                                        "cannot assign to parameter {}",
-                                        identifier
-                                    ));
-                                } else {
-                                    // having an assignment allows more variables
-                                    self.variables.borrow_mut().push(identifier.clone());
-                                }
-
-                                let expression = self.build_ast(second_pair.into_inner())?;
-
-                                assignments.push(Assignment {
-                                    identifier,
-                                    expression,
-                                });
+                                let mut operands = Vec::new();
+                                let boolean = self.build_boolean_expression(
+                                    pair.into_inner(),
+                                    symbols,
+                                    &mut operands,
+                                )?;
+
+                                let next_pair = pairs.next().expect("branch structure malformed");
+                                let expression = self.build_branch_body(next_pair, symbols)?;
+
+                                condition_branches.push((
+                                    Branch {
+                                        condition: boolean,
+                                        body: expression,
+                                    },
+                                    operands,
+                                ));
                             } else {
-                                let expression = self.build_ast(pair.into_inner())?;
+                                let expression = self.build_branch_body(pair, symbols)?;
 
-                                return Ok(AstNode::AssignmentsAndExpression {
-                                    assignments,
-                                    expression: Box::new(expression),
-                                });
+                                return Ok(build_branches(condition_branches, expression, span));
                             }
                         }
 
-                        Err(
-                            "unexpected assignment structure: should end with expression"
-                                .to_string(),
-                        )
+                        unreachable!("unexpected branch structure")
                     }
+                    Rule::assignments_and_expression => self.build_block(pair, symbols),
                     _ => unreachable!("unexpected rule: {:?}", pair.as_rule()),
                 }
             },
             |left, op, right| {
                 let (left, right) = (left?, right?);
+                let span = left.span().join(right.span());
 
                 // change some operators to functions
                 if matches!(op.as_rule(), Rule::power) {
-                    self.imports.borrow_mut().push(format_ident!("pow"));
-
-                    return Ok(AstNode::Function {
-                        name: format_ident!("pow"),
-                        args: vec![left, right],
-                    });
+                    return Ok(AstNode::new(
+                        AstNodeKind::Function {
+                            name: "pow".to_string(),
+                            args: vec![left, right],
+                        },
+                        span,
+                    ));
                 }
 
                 // dbg!("merge", &left, &op, &right);
@@ -193,139 +496,482 @@ impl Ast {
                     Rule::subtract => AstOperator::Subtract,
                     Rule::multiply => AstOperator::Multiply,
                     Rule::divide => AstOperator::Divide,
+                    Rule::floor_divide => AstOperator::FloorDivide,
+                    Rule::remainder => AstOperator::Remainder,
+                    Rule::bit_and => AstOperator::BitAnd,
+                    Rule::bit_or => AstOperator::BitOr,
+                    Rule::bit_xor => AstOperator::BitXor,
+                    Rule::shift_left => AstOperator::ShiftLeft,
+                    Rule::shift_right => AstOperator::ShiftRight,
                     _ => unreachable!("unexpected operator: {:?}", op.as_rule()),
                 };
 
-                Ok(AstNode::Operation {
-                    left: Box::new(left),
-                    op: ast_operator,
-                    right: Box::new(right),
-                })
+                Ok(AstNode::new(
+                    AstNodeKind::Operation {
+                        left: Box::new(left),
+                        op: ast_operator,
+                        right: Box::new(right),
+                    },
+                    span,
+                ))
             },
         )
     }
 
+    /// Builds the assignments and the expression of a block, its variables are only visible in it
+    fn build_block(
+        &self,
+        pair: Pair<'_, Rule>,
+        symbols: &mut SymbolTable,
+    ) -> Result<AstNode, ExpressionError> {
+        let mut assignments: Vec<Assignment> = vec![];
+
+        let span: Span = pair.as_span().into();
+        let pairs = pair.into_inner();
+
+        // the variables of the block, to tell reads before assignments from typos
+        symbols.enter(
+            pairs
+                .clone()
+                .filter(|pair| matches!(pair.as_rule(), Rule::assignment))
+                .map(|pair| {
+                    let span = pair.as_span().into();
+                    let identifier_pair = pair
+                        .into_inner()
+                        .next()
+                        .expect("assignment needs first pair");
+                    (identifier_pair.as_str().to_string(), span)
+                })
+                .collect(),
+        );
+
+        for pair in pairs {
+            if matches!(pair.as_rule(), Rule::assignment) {
+                let assignment_span: Span = pair.as_span().into();
+                let mut pairs = pair.into_inner();
+
+                let first_pair = pairs.next().expect("assignment needs first pair");
+                let second_pair = pairs.next().expect("assignment needs second pair");
+
+                let identifier = first_pair.as_str().to_string();
+
+                if self.parameters.contains(&identifier) {
+                    return Err(ExpressionError::AssignmentToParameter {
+                        name: identifier.to_string(),
+                        span: first_pair.as_span().into(),
+                    });
+                }
+
+                // the variable is visible after its own value
+                let expression = self.build_ast(second_pair.into_inner(), symbols)?;
+                symbols.define(identifier.clone());
+
+                assignments.push(Assignment {
+                    identifier,
+                    expression,
+                    span: assignment_span,
+                });
+            } else {
+                let expression = self.build_ast(pair.into_inner(), symbols)?;
+
+                symbols.exit();
+
+                return Ok(AstNode::new(
+                    AstNodeKind::AssignmentsAndExpression {
+                        assignments,
+                        expression: Box::new(expression),
+                    },
+                    span,
+                ));
+            }
+        }
+
+        unreachable!("unexpected assignment structure: should end with expression")
+    }
+
+    /// Builds the body of a branch, which is a plain expression if it has no assignments
+    fn build_branch_body(
+        &self,
+        pair: Pair<'_, Rule>,
+        symbols: &mut SymbolTable,
+    ) -> Result<AstNode, ExpressionError> {
+        let block = self.build_block(pair, symbols)?;
+
+        match block.kind {
+            AstNodeKind::AssignmentsAndExpression {
+                assignments,
+                expression,
+            } if assignments.is_empty() => Ok(*expression),
+            _ => Ok(block),
+        }
+    }
+
+    /// The functions that `root` calls and the ones they depend on, which the code generation
+    /// emits, every function after its dependencies
+    pub(crate) fn collect_imports(&self, root: &AstNode) -> Vec<String> {
+        let mut imports = Vec::new();
+        self.collect_node_imports(root, &mut imports);
+        imports
+    }
+
+    fn collect_node_imports(&self, node: &AstNode, imports: &mut Vec<String>) {
+        match node.kind() {
+            AstNodeKind::Constant(_)
+            | AstNodeKind::Integer(_)
+            | AstNodeKind::NamedConstant(_)
+            | AstNodeKind::Variable(_) => {}
+            AstNodeKind::Negation(operand) => self.collect_node_imports(operand, imports),
+            AstNodeKind::Operation { left, right, .. } => {
+                self.collect_node_imports(left, imports);
+                self.collect_node_imports(right, imports);
+            }
+            AstNodeKind::Function { name, args } => {
+                for arg in args {
+                    self.collect_node_imports(arg, imports);
+                }
+
+                if imports.contains(name) {
+                    return;
+                }
+
+                let function = self
+                    .functions
+                    .get(name)
+                    .expect("functions are checked when building the ast");
+
+                // the function's own `Ast` already collected all functions that it calls transitively
+                for dependency in function.dependencies() {
+                    if !imports.contains(&dependency) {
+                        imports.push(dependency);
+                    }
+                }
+                imports.push(name.clone());
+            }
+            AstNodeKind::Branch {
+                condition_branches,
+                else_branch,
+            } => {
+                for branch in condition_branches {
+                    self.collect_boolean_imports(branch.condition(), imports);
+                    self.collect_node_imports(branch.body(), imports);
+                }
+                self.collect_node_imports(else_branch, imports);
+            }
+            AstNodeKind::AssignmentsAndExpression {
+                assignments,
+                expression,
+            } => {
+                for assignment in assignments {
+                    self.collect_node_imports(assignment.expression(), imports);
+                }
+                self.collect_node_imports(expression, imports);
+            }
+        }
+    }
+
+    fn collect_boolean_imports(&self, expression: &BooleanExpression, imports: &mut Vec<String>) {
+        match expression.kind() {
+            BooleanExpressionKind::Constant(_) => {}
+            BooleanExpressionKind::Not(operand) => self.collect_boolean_imports(operand, imports),
+            BooleanExpressionKind::Comparison { left, right, .. } => {
+                self.collect_node_imports(left, imports);
+                self.collect_node_imports(right, imports);
+            }
+            BooleanExpressionKind::Operation { left, right, .. } => {
+                self.collect_boolean_imports(left, imports);
+                self.collect_boolean_imports(right, imports);
+            }
+        }
+    }
+
+    /// Builds a condition, the middle operands of comparison chains that are not just a variable
+    /// or a constant are added to `operands`, to be assigned before the condition
     fn build_boolean_expression(
         &self,
         pairs: Pairs<'_, Rule>,
-    ) -> Result<BooleanExpression, String> {
+        symbols: &mut SymbolTable,
+        operands: &mut Vec<Assignment>,
+    ) -> Result<BooleanExpression, ExpressionError> {
         // TODO: global var
-        // TODO: reverse??
+        // from lowest to highest precedence, so `a || b && c` is `a || (b && c)` like in Rust
         let precedence = PrecClimber::new(vec![
-            Operator::new(Rule::and, Assoc::Left),
             Operator::new(Rule::or, Assoc::Left),
+            Operator::new(Rule::xor, Assoc::Left),
+            Operator::new(Rule::and, Assoc::Left),
         ]);
 
         precedence.climb(
             pairs,
-            |pair| match pair.as_rule() {
-                Rule::boolean_true => Ok(BooleanExpression::Constant(true)),
-                Rule::boolean_false => Ok(BooleanExpression::Constant(false)),
-                Rule::boolean_comparison => {
-                    let mut pairs = pair.into_inner();
-
-                    let first_pair = pairs.next().ok_or("comparison needs second pair")?;
-                    let second_pair = pairs.next().ok_or("comparison needs second pair")?;
-                    let third_pair = pairs.next().ok_or("comparison needs third pair")?;
-
-                    let left_expression = self.build_ast(first_pair.into_inner())?;
-                    let comparison = match second_pair.as_rule() {
-                        Rule::equals => BooleanComparator::Equal,
-                        Rule::not_equals => BooleanComparator::NotEqual,
-                        Rule::smaller => BooleanComparator::LessThan,
-                        Rule::smaller_equals => BooleanComparator::LessThanOrEqual,
-                        Rule::larger => BooleanComparator::GreaterThan,
-                        Rule::larger_equals => BooleanComparator::GreaterThanOrEqual,
-                        _ => {
-                            return Err(format!(
-                                "unexpected comparator: {:?}",
-                                second_pair.as_rule()
-                            ))
+            |pair| {
+                let span: Span = pair.as_span().into();
+
+                match pair.as_rule() {
+                    Rule::boolean_true => Ok(BooleanExpression::new(
+                        BooleanExpressionKind::Constant(true),
+                        span,
+                    )),
+                    Rule::boolean_false => Ok(BooleanExpression::new(
+                        BooleanExpressionKind::Constant(false),
+                        span,
+                    )),
+                    Rule::boolean_not => {
+                        let operand =
+                            self.build_boolean_expression(pair.into_inner(), symbols, operands)?;
+
+                        Ok(BooleanExpression::new(
+                            BooleanExpressionKind::Not(Box::new(operand)),
+                            span,
+                        ))
+                    }
+                    Rule::boolean_comparison => {
+                        let mut pairs = pair.into_inner();
+
+                        let first_pair = pairs.next().expect("comparison needs first pair");
+                        let mut left_expression =
+                            self.build_ast(first_pair.into_inner(), symbols)?;
+
+                        // chains like `a < b <= c` become `a < b && b <= c`
+                        let mut comparisons: Option<BooleanExpression> = None;
+
+                        while let Some(comparator_pair) = pairs.next() {
+                            let comparison = match comparator_pair.as_rule() {
+                                Rule::equals => BooleanComparator::Equal,
+                                Rule::not_equals => BooleanComparator::NotEqual,
+                                Rule::smaller => BooleanComparator::LessThan,
+                                Rule::smaller_equals => BooleanComparator::LessThanOrEqual,
+                                Rule::larger => BooleanComparator::GreaterThan,
+                                Rule::larger_equals => BooleanComparator::GreaterThanOrEqual,
+                                _ => unreachable!(
+                                    "unexpected comparator: {:?}",
+                                    comparator_pair.as_rule()
+                                ),
+                            };
+
+                            let right_pair = pairs.next().expect("comparison needs right pair");
+                            let mut right_expression =
+                                self.build_ast(right_pair.into_inner(), symbols)?;
+
+                            // a middle operand is compared twice, but only evaluated once
+                            let is_leaf = matches!(
+                                right_expression.kind(),
+                                AstNodeKind::Constant(_)
+                                    | AstNodeKind::Integer(_)
+                                    | AstNodeKind::NamedConstant(_)
+                                    | AstNodeKind::Variable(_)
+                            );
+                            if pairs.peek().is_some() && !is_leaf {
+                                let identifier = temporary_identifier("comparand", operands.len());
+                                let operand_span = right_expression.span();
+
+                                operands.push(Assignment {
+                                    identifier: identifier.clone(),
+                                    expression: right_expression,
+                                    span: operand_span,
+                                });
+                                right_expression =
+                                    AstNode::new(AstNodeKind::Variable(identifier), operand_span);
+                            }
+
+                            let comparison_span =
+                                left_expression.span().join(right_expression.span());
+                            let comparison = BooleanExpression::new(
+                                BooleanExpressionKind::Comparison {
+                                    left: Box::new(left_expression),
+                                    op: comparison,
+                                    right: Box::new(right_expression.clone()),
+                                },
+                                comparison_span,
+                            );
+
+                            comparisons = Some(match comparisons {
+                                None => comparison,
+                                Some(previous) => {
+                                    let operation_span = previous.span().join(comparison_span);
+
+                                    BooleanExpression::new(
+                                        BooleanExpressionKind::Operation {
+                                            left: Box::new(previous),
+                                            op: BooleanOperator::And,
+                                            right: Box::new(comparison),
+                                        },
+                                        operation_span,
+                                    )
+                                }
+                            });
+
+                            left_expression = right_expression;
                         }
-                    };
-                    let right_expression = self.build_ast(third_pair.into_inner())?;
-
-                    Ok(BooleanExpression::Comparison {
-                        left: Box::new(left_expression),
-                        op: comparison,
-                        right: Box::new(right_expression),
-                    })
+
+                        Ok(comparisons.expect("comparison needs a comparator"))
+                    }
+                    Rule::boolean_expression => {
+                        self.build_boolean_expression(pair.into_inner(), symbols, operands)
+                    }
+                    _ => unreachable!("unexpected boolean rule: {:?}", pair.as_rule()),
                 }
-                Rule::boolean_expression => self.build_boolean_expression(pair.into_inner()),
-                _ => Err(format!("unexpected boolean rule: {:?}", pair.as_rule())),
             },
             |left, op, right| {
                 let (left, right) = (left?, right?);
+                let span = left.span().join(right.span());
 
                 // dbg!("merge", &left, &op, &right);
                 let boolean_operator = match op.as_rule() {
                     Rule::and => BooleanOperator::And,
                     Rule::or => BooleanOperator::Or,
+                    Rule::xor => BooleanOperator::Xor,
                     _ => unreachable!("unexpected boolean operator: {:?}", op.as_rule()),
                 };
 
-                Ok(BooleanExpression::Operation {
-                    left: Box::new(left),
-                    op: boolean_operator,
-                    right: Box::new(right),
-                })
+                Ok(BooleanExpression::new(
+                    BooleanExpressionKind::Operation {
+                        left: Box::new(left),
+                        op: boolean_operator,
+                        right: Box::new(right),
+                    },
+                    span,
+                ))
             },
         )
     }
 }
 
+/// Builds an `if` with `else if` branches, where the operands of each condition are assigned right
+/// before it, e.g. `if 0 < a * 2 < 1 { 1 } else { 2 }` becomes
+/// `let comparand_0 = a * 2; if 0 < comparand_0 && comparand_0 < 1 { 1 } else { 2 }`.
+///
+/// The operands of an `else if` are assigned in the `else` branch of the conditions before it.
+fn build_branches(
+    condition_branches: Vec<(Branch, Vec<Assignment>)>,
+    else_branch: AstNode,
+    span: Span,
+) -> AstNode {
+    // from the last branch to the first one, the branches after the operands are their else branch
+    let mut chain = Vec::new();
+    let mut rest = else_branch;
+    for (branch, operands) in condition_branches.into_iter().rev() {
+        chain.insert(0, branch);
+
+        if operands.is_empty() {
+            continue;
+        }
+
+        let branches = AstNode::new(
+            AstNodeKind::Branch {
+                condition_branches: std::mem::take(&mut chain),
+                else_branch: Box::new(rest),
+            },
+            span,
+        );
+        rest = AstNode::new(
+            AstNodeKind::AssignmentsAndExpression {
+                assignments: operands,
+                expression: Box::new(branches),
+            },
+            span,
+        );
+    }
+
+    if chain.is_empty() {
+        return rest;
+    }
+
+    AstNode::new(
+        AstNodeKind::Branch {
+            condition_branches: chain,
+            else_branch: Box::new(rest),
+        },
+        span,
+    )
+}
+
 impl ToTokens for Ast {
     fn to_tokens(&self, tokens: &mut TokenStream) {
         let dtype = format_ident!("{}", "f64");
 
-        for fn_name in self.imports.borrow().iter().map(ToString::to_string) {
+        for fn_name in &self.imports {
             let prefixed_fn_name = format_ident!("import_{}", fn_name);
 
             tokens.extend(quote! {
                 #[inline]
             });
 
-            let fn_tokens = match fn_name.as_str() {
-                "max" => quote! {
-                    fn #prefixed_fn_name (a: #dtype, b: #dtype) -> #dtype {
-                        #dtype::max(a, b)
-                    }
-                },
-                "pow" => quote! {
-                    fn #prefixed_fn_name (a: #dtype, b: #dtype) -> #dtype {
-                        #dtype::powf(a, b)
-                    }
-                },
-                _ => todo!("{} is not yet supported", fn_name),
+            let function = self
+                .functions
+                .get(fn_name)
+                .expect("imports are checked when building the ast");
+            let fn_params = function
+                .parameters()
+                .into_iter()
+                .map(|parameter| format_ident!("{}", parameter));
+            let fn_body = function.rust_body();
+
+            let fn_tokens = quote! {
+                fn #prefixed_fn_name (#(#fn_params : #dtype),*) -> #dtype {
+                    #fn_body
+                }
             };
 
             tokens.extend(fn_tokens);
         }
 
         let fn_name = format_ident!("{}", self.name);
-        let params = &self.parameters;
+        let params = self
+            .parameters
+            .iter()
+            .map(|parameter| format_ident!("{}", parameter))
+            .collect::<Vec<_>>();
+        let param_types = self.parameter_types.iter().map(|dtype| dtype.rust_type());
+        let output_type = self.output_type.rust_type();
         let content = &self.root;
 
+        // the parameters are promoted to `f64` and the result is converted with a saturating cast
         tokens.extend(quote! {
             #[no_mangle]
-            pub extern "C" fn #fn_name (#(#params : #dtype),*) -> #dtype {
-                #content
+            pub extern "C" fn #fn_name (#(#params : #param_types),*) -> #output_type {
+                #(let #params = #params as #dtype;)*
+                let result: #dtype = { #content };
+                result as #output_type
             }
         });
     }
 }
 
-#[derive(Debug)]
-pub enum AstNode {
+#[derive(Debug, Clone)]
+pub struct AstNode {
+    kind: AstNodeKind,
+    span: Span,
+}
+
+impl AstNode {
+    fn new(kind: AstNodeKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    pub fn kind(&self) -> &AstNodeKind {
+        &self.kind
+    }
+
+    /// The part of the source this node was parsed from
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AstNodeKind {
     Constant(f64),
-    Variable(Ident),
+    /// A literal without a fraction or exponent, it is an integer with integer arithmetic
+    Integer(i32),
+    NamedConstant(NamedConstant),
+    Variable(String),
+    Negation(Box<AstNode>),
     Operation {
         left: Box<AstNode>,
         op: AstOperator,
         right: Box<AstNode>,
     },
     Function {
-        name: Ident,
+        name: String,
         args: Vec<AstNode>,
     },
     Branch {
@@ -340,17 +986,23 @@ pub enum AstNode {
 
 impl ToTokens for AstNode {
     fn to_tokens(&self, tokens: &mut TokenStream) {
-        let new_tokens = match self {
-            Self::Constant(n) => quote! { #n },
-            Self::Variable(v) => quote! { #v },
-            Self::Operation { left, op, right } => {
+        let new_tokens = match &self.kind {
+            AstNodeKind::Constant(n) => float_literal(*n),
+            AstNodeKind::Integer(n) => float_literal(f64::from(*n)),
+            AstNodeKind::NamedConstant(c) => quote! { #c },
+            AstNodeKind::Variable(v) => {
+                let v = format_ident!("{}", v);
+                quote! { #v }
+            }
+            AstNodeKind::Negation(operand) => quote! { ( - #operand ) },
+            AstNodeKind::Operation { left, op, right } => {
                 quote! { ( #left #op #right ) }
             }
-            Self::Function { name, args } => {
+            AstNodeKind::Function { name, args } => {
                 let fn_name = format_ident!("import_{}", name);
                 quote! { #fn_name(#(#args),*) }
             }
-            AstNode::Branch {
+            AstNodeKind::Branch {
                 condition_branches,
                 else_branch: default_branch,
             } => {
@@ -384,13 +1036,16 @@ impl ToTokens for AstNode {
 
                 new_tokens
             }
-            Self::AssignmentsAndExpression {
+            AstNodeKind::AssignmentsAndExpression {
                 assignments,
                 expression,
             } => {
+                // a block, since the variables may be part of a larger expression
                 quote! {
-                    #(#assignments)*
-                    #expression
+                    {
+                        #(#assignments)*
+                        #expression
+                    }
                 }
             }
         };
@@ -399,12 +1054,75 @@ impl ToTokens for AstNode {
     }
 }
 
-#[derive(Debug)]
+/// Emits `value` so that the generated code contains exactly the same `f64`
+fn float_literal(value: f64) -> TokenStream {
+    if value.is_nan() {
+        return quote! { f64::NAN };
+    } else if value.is_infinite() && value.is_sign_positive() {
+        return quote! { f64::INFINITY };
+    } else if value.is_infinite() {
+        return quote! { f64::NEG_INFINITY };
+    }
+
+    // both representations are the shortest ones that round-trip, so pick the more compact one
+    let magnitude = value.abs();
+    let decimal = magnitude.to_string();
+    let scientific = format!("{:e}", magnitude);
+    let digits = if scientific.len() < decimal.len() {
+        scientific
+    } else {
+        decimal
+    };
+
+    let literal: TokenStream = format!("{}f64", digits)
+        .parse()
+        .expect("float literal must be a valid token");
+
+    if value.is_sign_negative() {
+        quote! { (-#literal) }
+    } else {
+        literal
+    }
+}
+
+/// The operators of the expression language.
+///
+/// Only the first four remain in an [`Ast`], the others are replaced by calls of
+/// functions for integers or floats when the types are checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AstOperator {
     Add,
     Subtract,
     Multiply,
     Divide,
+    /// `//`, which rounds towards negative infinity
+    FloorDivide,
+    /// `%`, the remainder of `//`, which has the sign of the divisor
+    Remainder,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+impl AstOperator {
+    /// The operator as it is written in expressions
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Subtract => "-",
+            Self::Multiply => "*",
+            Self::Divide => "/",
+            Self::FloorDivide => "//",
+            Self::Remainder => "%",
+            Self::BitAnd => "&",
+            Self::BitOr => "|",
+            Self::BitXor => "^",
+            Self::ShiftLeft => "<<",
+            Self::ShiftRight => ">>",
+        }
+    }
 }
 
 impl ToTokens for AstOperator {
@@ -414,21 +1132,54 @@ impl ToTokens for AstOperator {
             Self::Subtract => quote! { - },
             Self::Multiply => quote! { * },
             Self::Divide => quote! { / },
+            op => unreachable!("`{}` is replaced when the types are checked", op.symbol()),
         };
 
         tokens.extend(new_tokens);
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Branch {
     condition: BooleanExpression,
     body: AstNode,
 }
 
-#[derive(Debug)]
-pub enum BooleanExpression {
+impl Branch {
+    pub fn condition(&self) -> &BooleanExpression {
+        &self.condition
+    }
+
+    pub fn body(&self) -> &AstNode {
+        &self.body
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BooleanExpression {
+    kind: BooleanExpressionKind,
+    span: Span,
+}
+
+impl BooleanExpression {
+    fn new(kind: BooleanExpressionKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    pub fn kind(&self) -> &BooleanExpressionKind {
+        &self.kind
+    }
+
+    /// The part of the source this expression was parsed from
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BooleanExpressionKind {
     Constant(bool),
+    Not(Box<BooleanExpression>),
     Comparison {
         left: Box<AstNode>,
         op: BooleanComparator,
@@ -443,17 +1194,22 @@ pub enum BooleanExpression {
 
 impl ToTokens for BooleanExpression {
     fn to_tokens(&self, tokens: &mut TokenStream) {
-        let new_tokens = match self {
-            Self::Constant(b) => quote! { #b },
-            Self::Comparison { left, op, right } => quote! { ( (#left) #op (#right) ) },
-            Self::Operation { left, op, right } => quote! { ( (#left) #op (#right) ) },
+        let new_tokens = match &self.kind {
+            BooleanExpressionKind::Constant(b) => quote! { #b },
+            BooleanExpressionKind::Not(operand) => quote! { ( !(#operand) ) },
+            BooleanExpressionKind::Comparison { left, op, right } => {
+                quote! { ( (#left) #op (#right) ) }
+            }
+            BooleanExpressionKind::Operation { left, op, right } => {
+                quote! { ( (#left) #op (#right) ) }
+            }
         };
 
         tokens.extend(new_tokens);
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BooleanComparator {
     Equal,
     NotEqual,
@@ -478,10 +1234,11 @@ impl ToTokens for BooleanComparator {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BooleanOperator {
     And,
     Or,
+    Xor,
 }
 
 impl ToTokens for BooleanOperator {
@@ -489,16 +1246,33 @@ impl ToTokens for BooleanOperator {
         let new_tokens = match self {
             Self::And => quote! { && },
             Self::Or => quote! { || },
+            Self::Xor => quote! { ^ },
         };
 
         tokens.extend(new_tokens);
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Assignment {
-    identifier: Ident,
+    identifier: String,
     expression: AstNode,
+    span: Span,
+}
+
+impl Assignment {
+    pub fn identifier(&self) -> &str {
+        &self.identifier
+    }
+
+    pub fn expression(&self) -> &AstNode {
+        &self.expression
+    }
+
+    /// The whole `let` statement in the source
+    pub fn span(&self) -> Span {
+        self.span
+    }
 }
 
 impl ToTokens for Assignment {
@@ -506,7 +1280,9 @@ impl ToTokens for Assignment {
         let Self {
             identifier,
             expression,
+            span: _,
         } = self;
+        let identifier = format_ident!("{}", identifier);
         let new_tokens = quote! {
             let #identifier = #expression;
         };
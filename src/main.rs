@@ -1,4 +1,4 @@
-use math_expr::Ast;
+use math_expr::{Ast, FunctionRegistry, UserFunction};
 
 fn main() {
     for (pattern, variables) in [
@@ -6,6 +6,7 @@ fn main() {
         ("1 + 41", vec![]),
         ("1 + 2 / 3", vec![]),
         ("2**4", vec![]),
+        ("-2**2 * -(1 + 1)", vec![]),
         ("a + 1", vec!["a".to_string()]),
         ("(a-b) / (a+b)", vec!["a".to_string(), "b".to_string()]),
         ("max(a, 0)", vec!["a".to_string()]),
@@ -31,8 +32,28 @@ fn main() {
             a + b + 1",
             vec![],
         ),
+        (
+            "if a > 0 {
+                let b = a * 2;
+                b + 1
+            } else {
+                0
+            }",
+            vec!["a".to_string()],
+        ),
+        ("(a - c) / (a + b)", vec!["a".to_string(), "b".to_string()]),
     ] {
-        let ast = Ast::new("expression".to_string(), &variables, pattern);
+        let ast = match Ast::new("expression".to_string(), &variables, pattern) {
+            Ok(ast) => ast,
+            Err(error) => {
+                eprint!("{}", error.diagnostic().render(pattern));
+                continue;
+            }
+        };
+
+        for warning in ast.warnings() {
+            eprint!("{}", warning.diagnostic().render(pattern));
+        }
 
         dbg!(pattern);
         dbg!(ast.root());
@@ -41,4 +62,28 @@ fn main() {
         eprintln!("{}", ast.code());
         eprintln!("########## </CODE> ##########");
     }
+
+    let mut functions = FunctionRegistry::new();
+    functions
+        .register(UserFunction::expression(
+            Ast::new(
+                "ndvi".to_string(),
+                &["nir".to_string(), "red".to_string()],
+                "(nir - red) / (nir + red)",
+            )
+            .unwrap(),
+        ))
+        .unwrap();
+
+    let ast = Ast::builder(
+        "expression".to_string(),
+        &["a".to_string(), "b".to_string()],
+    )
+    .functions(functions)
+    .build("max(ndvi(a, b), 0)")
+    .unwrap();
+
+    eprintln!("########## <CODE> ##########");
+    eprintln!("{}", ast.code());
+    eprintln!("########## </CODE> ##########");
 }
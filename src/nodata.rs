@@ -0,0 +1,393 @@
+use crate::functions::ignores_nodata;
+use crate::scope::temporary_identifier;
+use crate::{
+    Assignment, Ast, AstNode, AstNodeKind, BooleanComparator, BooleanExpression,
+    BooleanExpressionKind, BooleanOperator, Branch, NamedConstant, Span,
+};
+
+impl Ast {
+    /// Whether a parameter or the output has a no-data value
+    fn handles_nodata(&self) -> bool {
+        self.parameter_nodata.iter().any(Option::is_some)
+            || self.parameter_masks.iter().any(Option::is_some)
+            || self.output_nodata.is_some()
+    }
+
+    /// Turns the no-data values of the parameters into `NaN`, makes every operation on `NaN`
+    /// result in `NaN` and replaces a `NaN` result by the no-data value of the output.
+    ///
+    /// Arithmetic passes `NaN` on by itself. Functions that ignore it, like `min`, are replaced
+    /// by variants that do not, and a branch whose condition compares `NaN` results in `NaN`,
+    /// instead of taking the next branch. Only `isnodata` and `coalesce` look at no-data.
+    ///
+    /// Expressions without no-data values stay as they are.
+    pub(crate) fn lower_nodata(&self, root: AstNode) -> AstNode {
+        if !self.handles_nodata() {
+            return root;
+        }
+
+        let span = root.span();
+
+        let scope = self
+            .parameters
+            .iter()
+            .zip(self.parameter_nodata.iter().zip(&self.parameter_masks))
+            .map(|(parameter, (nodata, mask))| {
+                (parameter.clone(), nodata.is_some() || mask.is_some())
+            })
+            .collect();
+
+        let mut lowering = NoDataLowering { scope, operands: 0 };
+        let (root, _) = lowering.node(root);
+
+        // the parameters are shadowed by their values with `NaN` for no-data,
+        // first by their no-data values, so that masks are read with theirs
+        let mut assignments = Vec::new();
+        for (parameter, nodata) in self.parameters.iter().zip(&self.parameter_nodata) {
+            if let Some(nodata) = nodata {
+                let value = AstNode::new(AstNodeKind::Variable(parameter.clone()), span);
+                let nodata = AstNode::new(AstNodeKind::Constant(*nodata), span);
+                assignments.push(Assignment {
+                    identifier: parameter.clone(),
+                    expression: lowering.call("nodata_value", vec![value, nodata], span),
+                    span,
+                });
+            }
+        }
+
+        // then by their masks, after the masks that are masked themselves, unless they form a cycle
+        let mut masked = self
+            .parameters
+            .iter()
+            .zip(&self.parameter_masks)
+            .filter_map(|(parameter, mask)| Some((parameter, mask.as_ref()?)))
+            .collect::<Vec<_>>();
+        while !masked.is_empty() {
+            let next = masked
+                .iter()
+                .position(|(_, mask)| !masked.iter().any(|(parameter, _)| parameter == mask))
+                .unwrap_or(0);
+            let (parameter, mask) = masked.remove(next);
+
+            let value = AstNode::new(AstNodeKind::Variable(parameter.clone()), span);
+            let mask = AstNode::new(AstNodeKind::Variable(mask.clone()), span);
+            assignments.push(Assignment {
+                identifier: parameter.clone(),
+                expression: lowering.call("nodata_mask", vec![value, mask], span),
+                span,
+            });
+        }
+
+        let root = if assignments.is_empty() {
+            root
+        } else {
+            AstNode::new(
+                AstNodeKind::AssignmentsAndExpression {
+                    assignments,
+                    expression: Box::new(root),
+                },
+                span,
+            )
+        };
+
+        match self.output_nodata {
+            Some(nodata) => {
+                let nodata = AstNode::new(AstNodeKind::Constant(nodata), span);
+                lowering.call("coalesce", vec![root, nodata], span)
+            }
+            None => root,
+        }
+    }
+}
+
+struct NoDataLowering {
+    /// Visible variables and whether they can be no-data, later ones shadow earlier ones
+    scope: Vec<(String, bool)>,
+    /// Number of operands of comparisons that were moved into variables,
+    /// which makes their names unique
+    operands: usize,
+}
+
+impl NoDataLowering {
+    /// The lowered node and whether it can be no-data
+    fn node(&mut self, node: AstNode) -> (AstNode, bool) {
+        let AstNode { kind, span } = node;
+
+        let (kind, nodata) = match kind {
+            AstNodeKind::Constant(n) => (kind, n.is_nan()),
+            AstNodeKind::Integer(_) => (kind, false),
+            AstNodeKind::NamedConstant(constant) => (kind, constant == NamedConstant::NoData),
+            AstNodeKind::Variable(ref identifier) => {
+                let nodata = self
+                    .scope
+                    .iter()
+                    .rev()
+                    .find(|(name, _)| name == identifier)
+                    .map(|(_, nodata)| *nodata)
+                    .expect("variables are checked when building the ast");
+                (kind, nodata)
+            }
+            AstNodeKind::Negation(operand) => {
+                let (operand, nodata) = self.node(*operand);
+                (AstNodeKind::Negation(Box::new(operand)), nodata)
+            }
+            AstNodeKind::Operation { left, op, right } => {
+                let (left, left_nodata) = self.node(*left);
+                let (right, right_nodata) = self.node(*right);
+                (
+                    AstNodeKind::Operation {
+                        left: Box::new(left),
+                        op,
+                        right: Box::new(right),
+                    },
+                    left_nodata || right_nodata,
+                )
+            }
+            AstNodeKind::Function { name, args } => {
+                let (args, nodata): (Vec<_>, Vec<_>) =
+                    args.into_iter().map(|arg| self.node(arg)).unzip();
+
+                match name.to_string().as_str() {
+                    "isnodata" => (AstNodeKind::Function { name, args }, false),
+                    "coalesce" => (
+                        AstNodeKind::Function { name, args },
+                        nodata.iter().all(|nodata| *nodata),
+                    ),
+                    function if ignores_nodata(function) && nodata.contains(&true) => {
+                        let name = format!("{}_nodata", function);
+                        (self.call(&name, args, span).kind, true)
+                    }
+                    // user defined functions get no-data as `NaN`
+                    _ => (AstNodeKind::Function { name, args }, nodata.contains(&true)),
+                }
+            }
+            AstNodeKind::Branch {
+                condition_branches,
+                else_branch,
+            } => return self.branch(condition_branches, *else_branch, span),
+            AstNodeKind::AssignmentsAndExpression {
+                assignments,
+                expression,
+            } => {
+                let scope_start = self.scope.len();
+
+                let mut lowered_assignments = Vec::with_capacity(assignments.len());
+                for assignment in assignments {
+                    let (expression, nodata) = self.node(assignment.expression);
+                    self.scope.push((assignment.identifier.clone(), nodata));
+                    lowered_assignments.push(Assignment {
+                        expression,
+                        ..assignment
+                    });
+                }
+
+                let (expression, nodata) = self.node(*expression);
+
+                self.scope.truncate(scope_start);
+
+                (
+                    AstNodeKind::AssignmentsAndExpression {
+                        assignments: lowered_assignments,
+                        expression: Box::new(expression),
+                    },
+                    nodata,
+                )
+            }
+        };
+
+        (AstNode::new(kind, span), nodata)
+    }
+
+    /// Checks each condition that compares no-data right before it, e.g.
+    /// `if a > 0 { 1 } else { 2 }` becomes
+    /// `let operand_0 = a; if operand_0 != operand_0 { NaN } else if operand_0 > 0 { 1 } else { 2 }`,
+    /// so that the operands are evaluated once.
+    fn branch(
+        &mut self,
+        condition_branches: Vec<Branch>,
+        else_branch: AstNode,
+        span: Span,
+    ) -> (AstNode, bool) {
+        let mut lowered = Vec::with_capacity(condition_branches.len());
+        let mut any_nodata = false;
+
+        for branch in condition_branches {
+            let mut checked = Vec::new();
+            let condition = self.condition(branch.condition, &mut checked);
+            let (body, nodata) = self.node(branch.body);
+            any_nodata |= nodata || !checked.is_empty();
+            lowered.push((Branch { condition, body }, checked));
+        }
+
+        let (else_branch, nodata) = self.node(else_branch);
+        any_nodata |= nodata;
+
+        // from the last branch to the first one, the branches after a check are its else branch
+        let mut chain = Vec::new();
+        let mut rest = else_branch;
+        for (branch, checked) in lowered.into_iter().rev() {
+            chain.insert(0, branch);
+
+            if checked.is_empty() {
+                continue;
+            }
+
+            let (assignments, guard) = self.guard(checked, span);
+            chain.insert(0, guard);
+
+            let branches = AstNode::new(
+                AstNodeKind::Branch {
+                    condition_branches: std::mem::take(&mut chain),
+                    else_branch: Box::new(rest),
+                },
+                span,
+            );
+            rest = if assignments.is_empty() {
+                branches
+            } else {
+                AstNode::new(
+                    AstNodeKind::AssignmentsAndExpression {
+                        assignments,
+                        expression: Box::new(branches),
+                    },
+                    span,
+                )
+            };
+        }
+
+        let node = if chain.is_empty() {
+            rest
+        } else {
+            AstNode::new(
+                AstNodeKind::Branch {
+                    condition_branches: chain,
+                    else_branch: Box::new(rest),
+                },
+                span,
+            )
+        };
+
+        (node, any_nodata)
+    }
+
+    /// Lowers a condition and collects the operands of comparisons that can be no-data
+    fn condition(
+        &mut self,
+        expression: BooleanExpression,
+        checked: &mut Vec<(String, Option<AstNode>)>,
+    ) -> BooleanExpression {
+        let BooleanExpression { kind, span } = expression;
+
+        let kind = match kind {
+            BooleanExpressionKind::Constant(_) => kind,
+            BooleanExpressionKind::Not(operand) => {
+                BooleanExpressionKind::Not(Box::new(self.condition(*operand, checked)))
+            }
+            BooleanExpressionKind::Comparison { left, op, right } => {
+                BooleanExpressionKind::Comparison {
+                    left: Box::new(self.operand(*left, checked)),
+                    op,
+                    right: Box::new(self.operand(*right, checked)),
+                }
+            }
+            BooleanExpressionKind::Operation { left, op, right } => {
+                BooleanExpressionKind::Operation {
+                    left: Box::new(self.condition(*left, checked)),
+                    op,
+                    right: Box::new(self.condition(*right, checked)),
+                }
+            }
+        };
+
+        BooleanExpression::new(kind, span)
+    }
+
+    /// An operand of a comparison, which is moved into a variable if it can be no-data,
+    /// unless it is one already
+    fn operand(
+        &mut self,
+        operand: AstNode,
+        checked: &mut Vec<(String, Option<AstNode>)>,
+    ) -> AstNode {
+        let (operand, nodata) = self.node(operand);
+
+        if !nodata {
+            return operand;
+        }
+
+        let span = operand.span();
+        let identifier = match operand.kind() {
+            AstNodeKind::Variable(identifier) => {
+                let identifier = identifier.clone();
+                if !checked.iter().any(|(name, _)| *name == identifier) {
+                    checked.push((identifier.clone(), None));
+                }
+                identifier
+            }
+            _ => {
+                let identifier = temporary_identifier("operand", self.operands);
+                self.operands += 1;
+                checked.push((identifier.clone(), Some(operand)));
+                identifier
+            }
+        };
+
+        AstNode::new(AstNodeKind::Variable(identifier), span)
+    }
+
+    /// The assignments of the checked operands and a branch that results in `NaN` if one is `NaN`
+    fn guard(
+        &mut self,
+        checked: Vec<(String, Option<AstNode>)>,
+        span: Span,
+    ) -> (Vec<Assignment>, Branch) {
+        let mut assignments = Vec::new();
+        let mut condition: Option<BooleanExpression> = None;
+
+        for (identifier, expression) in checked {
+            if let Some(expression) = expression {
+                assignments.push(Assignment {
+                    identifier: identifier.clone(),
+                    expression,
+                    span,
+                });
+            }
+
+            // only `NaN` is not equal to itself
+            let variable = AstNode::new(AstNodeKind::Variable(identifier), span);
+            let comparison = BooleanExpression::new(
+                BooleanExpressionKind::Comparison {
+                    left: Box::new(variable.clone()),
+                    op: BooleanComparator::NotEqual,
+                    right: Box::new(variable),
+                },
+                span,
+            );
+
+            condition = Some(match condition {
+                None => comparison,
+                Some(previous) => BooleanExpression::new(
+                    BooleanExpressionKind::Operation {
+                        left: Box::new(previous),
+                        op: BooleanOperator::Or,
+                        right: Box::new(comparison),
+                    },
+                    span,
+                ),
+            });
+        }
+
+        let guard = Branch {
+            condition: condition.expect("a guard checks at least one operand"),
+            body: AstNode::new(AstNodeKind::Constant(f64::NAN), span),
+        };
+
+        (assignments, guard)
+    }
+
+    /// A call of the function `name`
+    fn call(&mut self, name: &str, args: Vec<AstNode>, span: Span) -> AstNode {
+        let name = name.to_string();
+        AstNode::new(AstNodeKind::Function { name, args }, span)
+    }
+}
@@ -0,0 +1,508 @@
+use std::fmt;
+
+use ocl::{Buffer, ProQue};
+
+use crate::batch::check_inputs;
+use crate::data_type::DataType;
+use crate::eval::EvalError;
+use crate::functions::Function;
+use crate::scope::temporary_identifier;
+use crate::{
+    Ast, AstNode, AstNodeKind, AstOperator, BooleanComparator, BooleanExpression,
+    BooleanExpressionKind, BooleanOperator,
+};
+
+/// Name of the output buffer, which cannot clash with the prefixed parameters
+const OUTPUT_BUFFER: &str = "output_values";
+
+/// Name of the work item index in the kernel
+const GLOBAL_ID: &str = "global_id";
+
+/// Keywords and type names of OpenCL C that identifiers of the expression language can spell,
+/// also with the sizes of the vector types, e.g. `double4`
+const OPENCL_KEYWORDS: &[&str] = &[
+    "auto",
+    "bool",
+    "break",
+    "case",
+    "char",
+    "complex",
+    "const",
+    "constant",
+    "continue",
+    "default",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "extern",
+    "float",
+    "for",
+    "global",
+    "goto",
+    "half",
+    "if",
+    "imaginary",
+    "inline",
+    "int",
+    "kernel",
+    "local",
+    "long",
+    "pipe",
+    "private",
+    "register",
+    "restrict",
+    "return",
+    "sampler",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "struct",
+    "switch",
+    "typedef",
+    "uchar",
+    "uint",
+    "ulong",
+    "uniform",
+    "union",
+    "unsigned",
+    "ushort",
+    "void",
+    "volatile",
+    "while",
+];
+
+/// The C name of a parameter of the kernel or of a user defined function.
+/// The prefix keeps parameters like `int` or `kernel` from being read as keywords.
+fn parameter_name(parameter: &str) -> String {
+    format!("input_{}", parameter)
+}
+
+/// Checks that the kernel can be named `name` in OpenCL C
+fn is_kernel_name(name: &str) -> bool {
+    let scalar = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    let word = match &name[scalar.len()..] {
+        "" | "2" | "3" | "4" | "8" | "16" => scalar,
+        _ => name,
+    };
+
+    !OPENCL_KEYWORDS.contains(&word)
+}
+
+impl Ast {
+    /// Generates an OpenCL C kernel with the name of the expression.
+    ///
+    /// The kernel takes the output buffer first and then one input buffer per parameter,
+    /// e.g. `__kernel void ndvi(__global double* output_values, __global const double* input_a, __global const double* input_b)`.
+    /// The buffers have the data types of the output and the parameters, and the computation is done in `double`.
+    pub fn opencl(&self) -> Result<String, OpenClError> {
+        if !is_kernel_name(&self.name) {
+            return Err(OpenClError::InvalidKernelName {
+                name: self.name.clone(),
+            });
+        }
+
+        let mut source = vec!["#pragma OPENCL EXTENSION cl_khr_fp64 : enable".to_string()];
+
+        for name in self.imports() {
+            let function = self
+                .functions
+                .get(&name)
+                .expect("imports are checked when building the ast");
+
+            // the bodies of the builtin functions refer to their parameters by name
+            let parameter_names = function
+                .parameters()
+                .into_iter()
+                .map(|parameter| match function {
+                    Function::Builtin(_) => (parameter.clone(), parameter),
+                    Function::User(_) => (parameter_name(&parameter), parameter),
+                })
+                .collect::<Vec<_>>();
+
+            let parameters = match function.arity() {
+                0 => "void".to_string(),
+                _ => parameter_names
+                    .iter()
+                    .map(|(name, _)| format!("double {}", name))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            };
+
+            let mut writer = KernelWriter::new(
+                parameter_names
+                    .into_iter()
+                    .map(|(name, parameter)| (parameter, name)),
+            );
+
+            let result = match function {
+                Function::Builtin(builtin) => builtin.opencl_body().to_string(),
+                Function::User(_) => {
+                    let ast = function
+                        .expression()
+                        .ok_or_else(|| OpenClError::RustFunction {
+                            name: name.to_string(),
+                        })?;
+                    writer.value(ast.root())
+                }
+            };
+            writer.emit(format!("return {};", result));
+
+            source.push(String::new());
+            source.push(format!("double import_{}({}) {{", name, parameters));
+            source.extend(writer.lines);
+            source.push("}".to_string());
+        }
+
+        let parameters = self.parameters.iter().zip(&self.parameter_types);
+
+        let buffers = std::iter::once(format!(
+            "__global {}* {}",
+            self.output_type.opencl_type(),
+            OUTPUT_BUFFER
+        ))
+        .chain(parameters.clone().map(|(parameter, dtype)| {
+            format!(
+                "__global const {}* {}",
+                dtype.opencl_type(),
+                parameter_name(parameter)
+            )
+        }))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+        let mut writer = KernelWriter::new(parameters.map(|(parameter, dtype)| {
+            let element = match dtype {
+                DataType::F64 => format!("{}[{}]", parameter_name(parameter), GLOBAL_ID),
+                _ => format!("((double){}[{}])", parameter_name(parameter), GLOBAL_ID),
+            };
+            (parameter.clone(), element)
+        }));
+        writer.emit(format!("const size_t {} = get_global_id(0);", GLOBAL_ID));
+        let result = match self.output_type {
+            DataType::F64 => writer.value(&self.root),
+            DataType::F32 => format!("((float){})", writer.value(&self.root)),
+            // like a saturating `as` cast in Rust, which turns `NaN` into `0`
+            dtype => format!(
+                "convert_{}_sat_rtz({})",
+                dtype.opencl_type(),
+                writer.value(&self.root)
+            ),
+        };
+        writer.emit(format!("{}[{}] = {};", OUTPUT_BUFFER, GLOBAL_ID, result));
+
+        source.push(String::new());
+        source.push(format!("__kernel void {}({}) {{", self.name, buffers));
+        source.extend(writer.lines);
+        source.push("}".to_string());
+
+        Ok(source.join("\n") + "\n")
+    }
+}
+
+/// An expression that was compiled to an OpenCL kernel, which evaluates whole slices on the device
+pub struct OpenClExpression {
+    pro_que: ProQue,
+    name: String,
+    parameter_types: Vec<DataType>,
+    output_type: DataType,
+}
+
+impl OpenClExpression {
+    /// Builds the kernel of `ast` for the first device of the default platform
+    pub fn new(ast: &Ast) -> Result<Self, OpenClError> {
+        // the buffers hold `double`s, the values are converted to the data types on the host
+        let kernel = Ast {
+            parameter_types: vec![DataType::F64; ast.parameters.len()],
+            output_type: DataType::F64,
+            ..ast.clone()
+        };
+
+        let pro_que = ProQue::builder()
+            .src(kernel.opencl()?)
+            .build()
+            .map_err(|error| OpenClError::Build {
+                message: error.to_string(),
+            })?;
+
+        Ok(Self {
+            pro_que,
+            name: ast.name.clone(),
+            parameter_types: ast.parameter_types.clone(),
+            output_type: ast.output_type,
+        })
+    }
+
+    /// Number of parameters of the expression
+    pub fn arity(&self) -> usize {
+        self.parameter_types.len()
+    }
+
+    /// Evaluates the expression for every element with one kernel launch,
+    /// with one input slice per parameter
+    pub fn evaluate_slices(&self, inputs: &[&[f64]], output: &mut [f64]) -> Result<(), EvalError> {
+        check_inputs(self.arity(), inputs, output.len())?;
+
+        // OpenCL has no empty buffers
+        if output.is_empty() {
+            return Ok(());
+        }
+
+        self.launch(inputs, output)
+            .map_err(|error| EvalError::OpenCl {
+                message: error.to_string(),
+            })?;
+
+        for value in output.iter_mut() {
+            *value = self.output_type.cast(*value);
+        }
+
+        Ok(())
+    }
+
+    fn launch(&self, inputs: &[&[f64]], output: &mut [f64]) -> ocl::Result<()> {
+        let queue = self.pro_que.queue();
+
+        let input_buffers = inputs
+            .iter()
+            .zip(&self.parameter_types)
+            .map(|(input, dtype)| {
+                let values = input
+                    .iter()
+                    .map(|value| dtype.cast(*value))
+                    .collect::<Vec<_>>();
+
+                Buffer::<f64>::builder()
+                    .queue(queue.clone())
+                    .len(values.len())
+                    .copy_host_slice(&values)
+                    .build()
+            })
+            .collect::<ocl::Result<Vec<_>>>()?;
+        let output_buffer = Buffer::<f64>::builder()
+            .queue(queue.clone())
+            .len(output.len())
+            .build()?;
+
+        let mut kernel = self.pro_que.kernel_builder(self.name.as_str());
+        kernel.global_work_size(output.len()).arg(&output_buffer);
+        for buffer in &input_buffers {
+            kernel.arg(buffer);
+        }
+        let kernel = kernel.build()?;
+
+        // SAFETY: the kernel reads one element of every buffer and writes one of the output buffer
+        // per work item, and there are as many work items as elements
+        unsafe {
+            kernel.enq()?;
+        }
+
+        output_buffer.read(output).enq()
+    }
+}
+
+/// Emits the statements of a function body and returns C expressions for the values
+struct KernelWriter {
+    lines: Vec<String>,
+    /// Visible variables and the C expressions that hold their values, later ones shadow earlier ones
+    scope: Vec<(String, String)>,
+    /// Number of emitted temporaries, which makes their names unique
+    temporaries: usize,
+    depth: usize,
+}
+
+impl KernelWriter {
+    fn new(parameters: impl Iterator<Item = (String, String)>) -> Self {
+        Self {
+            lines: Vec::new(),
+            scope: parameters.collect(),
+            temporaries: 0,
+            depth: 1,
+        }
+    }
+
+    fn emit(&mut self, statement: impl Into<String>) {
+        self.lines
+            .push(format!("{}{}", "    ".repeat(self.depth), statement.into()));
+    }
+
+    /// A fresh name for a temporary, cf. [`temporary_identifier`]
+    fn temporary(&mut self, name: &str) -> String {
+        let temporary = temporary_identifier(name, self.temporaries);
+        self.temporaries += 1;
+        temporary.to_string()
+    }
+
+    fn value(&mut self, node: &AstNode) -> String {
+        match node.kind() {
+            AstNodeKind::Constant(n) => float_literal(*n),
+            AstNodeKind::Integer(n) => float_literal(f64::from(*n)),
+            AstNodeKind::NamedConstant(constant) => float_literal(constant.value()),
+            AstNodeKind::Variable(identifier) => self
+                .scope
+                .iter()
+                .rev()
+                .find(|(name, _)| name == identifier)
+                .map(|(_, value)| value.clone())
+                .expect("variables are checked when building the ast"),
+            AstNodeKind::Negation(operand) => format!("(-{})", self.value(operand)),
+            AstNodeKind::Operation { left, op, right } => {
+                let left = self.value(left);
+                let right = self.value(right);
+                let op = match op {
+                    AstOperator::Add => "+",
+                    AstOperator::Subtract => "-",
+                    AstOperator::Multiply => "*",
+                    AstOperator::Divide => "/",
+                    op => unreachable!("`{}` is replaced when the types are checked", op.symbol()),
+                };
+                format!("({} {} {})", left, op, right)
+            }
+            AstNodeKind::Function { name, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.value(arg))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("import_{}({})", name, args)
+            }
+            AstNodeKind::Branch {
+                condition_branches,
+                else_branch,
+            } => {
+                let result = self.temporary("branch");
+                self.emit(format!("double {};", result));
+
+                for branch in condition_branches {
+                    let condition = self.condition(branch.condition());
+                    self.emit(format!("if {} {{", parenthesized(condition)));
+                    self.depth += 1;
+                    let value = self.value(branch.body());
+                    self.emit(format!("{} = {};", result, value));
+                    self.depth -= 1;
+                    self.emit("} else {");
+                    self.depth += 1;
+                }
+
+                let value = self.value(else_branch);
+                self.emit(format!("{} = {};", result, value));
+
+                for _ in condition_branches {
+                    self.depth -= 1;
+                    self.emit("}");
+                }
+
+                result
+            }
+            AstNodeKind::AssignmentsAndExpression {
+                assignments,
+                expression,
+            } => {
+                let scope_start = self.scope.len();
+
+                for assignment in assignments {
+                    let value = self.value(assignment.expression());
+                    let variable = self.temporary(assignment.identifier());
+                    self.emit(format!("const double {} = {};", variable, value));
+                    self.scope
+                        .push((assignment.identifier().to_string(), variable));
+                }
+
+                let value = self.value(expression);
+
+                self.scope.truncate(scope_start);
+
+                value
+            }
+        }
+    }
+
+    fn condition(&mut self, expression: &BooleanExpression) -> String {
+        match expression.kind() {
+            BooleanExpressionKind::Constant(b) => b.to_string(),
+            BooleanExpressionKind::Not(operand) => format!("(!{})", self.condition(operand)),
+            BooleanExpressionKind::Comparison { left, op, right } => {
+                let left = self.value(left);
+                let right = self.value(right);
+                let op = match op {
+                    BooleanComparator::Equal => "==",
+                    BooleanComparator::NotEqual => "!=",
+                    BooleanComparator::LessThan => "<",
+                    BooleanComparator::LessThanOrEqual => "<=",
+                    BooleanComparator::GreaterThan => ">",
+                    BooleanComparator::GreaterThanOrEqual => ">=",
+                };
+                format!("({} {} {})", left, op, right)
+            }
+            BooleanExpressionKind::Operation { left, op, right } => {
+                let left = self.condition(left);
+                let right = self.condition(right);
+                let op = match op {
+                    BooleanOperator::And => "&&",
+                    BooleanOperator::Or => "||",
+                    // both sides are `0` or `1`, so this is a logical xor
+                    BooleanOperator::Xor => "!=",
+                };
+                format!("({} {} {})", left, op, right)
+            }
+        }
+    }
+}
+
+/// Wraps `expression` in parentheses unless the generated code already did
+fn parenthesized(expression: String) -> String {
+    if expression.starts_with('(') {
+        expression
+    } else {
+        format!("({})", expression)
+    }
+}
+
+/// Formats `value` as an OpenCL C literal that is exactly the same `double`
+fn float_literal(value: f64) -> String {
+    if value.is_nan() {
+        "NAN".to_string()
+    } else if value.is_infinite() && value.is_sign_positive() {
+        "INFINITY".to_string()
+    } else if value.is_infinite() {
+        "(-INFINITY)".to_string()
+    } else if value.is_sign_negative() {
+        format!("(-{:?})", -value)
+    } else {
+        // the shortest representation that round-trips, always with a `.` or an exponent
+        format!("{:?}", value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpenClError {
+    /// The expression calls a function with a Rust body, see [`UserFunction::rust`](crate::UserFunction::rust)
+    RustFunction { name: String },
+    /// The name of the expression is a keyword or type of OpenCL C
+    InvalidKernelName { name: String },
+    /// The OpenCL platform could not build the kernel or has no device
+    Build { message: String },
+}
+
+impl fmt::Display for OpenClError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RustFunction { name } => write!(
+                f,
+                "function `{}` has a Rust body and cannot be compiled to OpenCL",
+                name
+            ),
+            Self::InvalidKernelName { name } => {
+                write!(
+                    f,
+                    "`{}` is a keyword of OpenCL C and cannot name a kernel",
+                    name
+                )
+            }
+            Self::Build { message } => write!(f, "cannot build OpenCL kernel: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for OpenClError {}
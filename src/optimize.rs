@@ -0,0 +1,644 @@
+use std::fmt;
+
+use crate::cse::eliminate_common_subexpressions;
+use crate::scope::temporary_identifier;
+use crate::usage::remove_dead_assignments;
+use crate::{
+    Assignment, Ast, AstNode, AstNodeKind, AstOperator, BooleanComparator, BooleanExpression,
+    BooleanExpressionKind, BooleanOperator, Branch, Span,
+};
+
+/// Rewrites an [`Ast`] into one that computes the same results with less work
+#[derive(Debug, Clone)]
+pub struct Optimizer {
+    fast_math: bool,
+    dead_assignments: bool,
+    common_subexpressions: bool,
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self {
+            fast_math: false,
+            dead_assignments: true,
+            common_subexpressions: true,
+        }
+    }
+}
+
+impl Optimizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows rewrites that change the result for `NaN`, infinities or the sign of zero,
+    /// e.g. `x * 0` to `0` and `x + 0` to `x`
+    pub fn fast_math(mut self, fast_math: bool) -> Self {
+        self.fast_math = fast_math;
+        self
+    }
+
+    /// Removes the assignments of variables that are never read, enabled by default
+    pub fn dead_assignments(mut self, dead_assignments: bool) -> Self {
+        self.dead_assignments = dead_assignments;
+        self
+    }
+
+    /// Computes subexpressions that occur more than once only once, enabled by default
+    pub fn common_subexpressions(mut self, common_subexpressions: bool) -> Self {
+        self.common_subexpressions = common_subexpressions;
+        self
+    }
+
+    /// The optimized expression and what was changed to get it
+    pub fn optimize(&self, ast: &Ast) -> (Ast, OptimizationReport) {
+        let mut pass = OptimizationPass {
+            ast,
+            fast_math: self.fast_math,
+            constants: Vec::new(),
+            squares: 0,
+            changes: Vec::new(),
+        };
+
+        let mut root = pass.node(ast.root.clone());
+
+        if self.dead_assignments {
+            let (live_root, removed) = remove_dead_assignments(root, &ast.parameters);
+            root = live_root;
+            pass.changes
+                .extend(removed.into_iter().map(|(identifier, span)| {
+                    Optimization::DeadAssignmentRemoved {
+                        name: identifier.to_string(),
+                        span,
+                    }
+                }));
+        }
+
+        if self.common_subexpressions {
+            root = eliminate_common_subexpressions(root, &ast.parameters, &mut pass.changes);
+        }
+
+        // folded calls are not imported anymore
+        let optimized = Ast {
+            imports: ast.collect_imports(&root),
+            root,
+            ..ast.clone()
+        };
+
+        (
+            optimized,
+            OptimizationReport {
+                changes: pass.changes,
+            },
+        )
+    }
+}
+
+impl Ast {
+    /// Optimizes the expression without rewrites that change its IEEE 754 semantics
+    pub fn optimize(&self) -> (Ast, OptimizationReport) {
+        Optimizer::new().optimize(self)
+    }
+}
+
+/// The changes of an [`Optimizer`], in the order they were made
+#[derive(Debug, Clone, Default)]
+pub struct OptimizationReport {
+    changes: Vec<Optimization>,
+}
+
+impl OptimizationReport {
+    pub fn changes(&self) -> &[Optimization] {
+        &self.changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// A single rewrite of an [`Optimizer`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Optimization {
+    /// A subexpression without variables was replaced by its value
+    ConstantFolded { value: f64, span: Span },
+    /// A variable was replaced by the constant value it was assigned
+    ConstantPropagated {
+        name: String,
+        value: f64,
+        span: Span,
+    },
+    /// A branch was removed since its condition is always `true` or always `false`
+    BranchRemoved { condition: bool, span: Span },
+    /// An algebraic identity was applied, e.g. `x * 1 → x`
+    Simplified { rule: &'static str, span: Span },
+    /// The assignment of a variable that is never read was removed
+    DeadAssignmentRemoved { name: String, span: Span },
+    /// A subexpression that occurs more than once is computed once and assigned to `name`
+    SubexpressionEliminated {
+        name: String,
+        occurrences: usize,
+        span: Span,
+    },
+}
+
+impl Optimization {
+    /// The part of the source that was rewritten
+    pub fn span(&self) -> Span {
+        match self {
+            Self::ConstantFolded { span, .. }
+            | Self::ConstantPropagated { span, .. }
+            | Self::BranchRemoved { span, .. }
+            | Self::Simplified { span, .. }
+            | Self::DeadAssignmentRemoved { span, .. }
+            | Self::SubexpressionEliminated { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for Optimization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ConstantFolded { value, .. } => write!(f, "folded constant to `{}`", value),
+            Self::ConstantPropagated { name, value, .. } => {
+                write!(f, "replaced variable `{}` by `{}`", name, value)
+            }
+            Self::BranchRemoved { condition, .. } => {
+                write!(
+                    f,
+                    "removed branch with condition that is always `{}`",
+                    condition
+                )
+            }
+            Self::Simplified { rule, .. } => write!(f, "simplified `{}`", rule),
+            Self::DeadAssignmentRemoved { name, .. } => {
+                write!(f, "removed assignment of unused variable `{}`", name)
+            }
+            Self::SubexpressionEliminated {
+                name, occurrences, ..
+            } => write!(
+                f,
+                "computed {} occurrences of a subexpression once as `{}`",
+                occurrences, name
+            ),
+        }
+    }
+}
+
+struct OptimizationPass<'a> {
+    ast: &'a Ast,
+    fast_math: bool,
+    /// Visible variables and their values if they are constant, later ones shadow earlier ones
+    constants: Vec<(String, Option<f64>)>,
+    /// Number of squared operands that were moved into variables, which makes their names unique
+    squares: usize,
+    changes: Vec<Optimization>,
+}
+
+impl<'a> OptimizationPass<'a> {
+    fn node(&mut self, node: AstNode) -> AstNode {
+        let AstNode { kind, span } = node;
+        let changes_start = self.changes.len();
+
+        let kind = match kind {
+            AstNodeKind::Constant(_) | AstNodeKind::Integer(_) | AstNodeKind::NamedConstant(_) => {
+                kind
+            }
+            AstNodeKind::Variable(identifier) => {
+                let value = self
+                    .constants
+                    .iter()
+                    .rev()
+                    .find(|(name, _)| *name == identifier)
+                    .and_then(|(_, value)| *value);
+
+                match value {
+                    Some(value) => {
+                        self.changes.push(Optimization::ConstantPropagated {
+                            name: identifier.to_string(),
+                            value,
+                            span,
+                        });
+                        AstNodeKind::Constant(value)
+                    }
+                    None => AstNodeKind::Variable(identifier),
+                }
+            }
+            AstNodeKind::Negation(operand) => {
+                let operand = self.node(*operand);
+
+                if let Some(value) = constant(&operand) {
+                    return self.folded(-value, span, changes_start);
+                }
+
+                match operand.kind {
+                    AstNodeKind::Negation(operand) => {
+                        return self.simplified("-(-x) → x", *operand, span);
+                    }
+                    kind => AstNodeKind::Negation(Box::new(AstNode::new(kind, operand.span))),
+                }
+            }
+            AstNodeKind::Operation { left, op, right } => {
+                let left = self.node(*left);
+                let right = self.node(*right);
+
+                if let (Some(left), Some(right)) = (constant(&left), constant(&right)) {
+                    let value = match op {
+                        AstOperator::Add => left + right,
+                        AstOperator::Subtract => left - right,
+                        AstOperator::Multiply => left * right,
+                        AstOperator::Divide => left / right,
+                        op => {
+                            unreachable!("`{}` is replaced when the types are checked", op.symbol())
+                        }
+                    };
+                    return self.folded(value, span, changes_start);
+                }
+
+                return self.operation(left, op, right, span);
+            }
+            AstNodeKind::Function { name, args } => {
+                let args = args
+                    .into_iter()
+                    .map(|arg| self.node(arg))
+                    .collect::<Vec<_>>();
+
+                let values = args.iter().map(constant).collect::<Option<Vec<_>>>();
+                if let Some(values) = values {
+                    let function = self
+                        .ast
+                        .functions
+                        .get(&name.to_string())
+                        .expect("functions are checked when building the ast");
+
+                    // functions with a Rust body cannot be evaluated here
+                    if let Ok(value) = function.evaluate(&values) {
+                        return self.folded(value, span, changes_start);
+                    }
+                }
+
+                if name == "pow" {
+                    return self.power(name, args, span);
+                }
+
+                AstNodeKind::Function { name, args }
+            }
+            AstNodeKind::Branch {
+                condition_branches,
+                else_branch,
+            } => return self.branch(condition_branches, *else_branch, span),
+            AstNodeKind::AssignmentsAndExpression {
+                assignments,
+                expression,
+            } => {
+                let scope_start = self.constants.len();
+
+                // constant variables are replaced by their values, so their assignments are dropped
+                let mut kept_assignments = Vec::with_capacity(assignments.len());
+                for assignment in assignments {
+                    let expression = self.node(assignment.expression);
+                    let value = constant(&expression);
+                    self.constants.push((assignment.identifier.clone(), value));
+
+                    if value.is_none() {
+                        kept_assignments.push(Assignment {
+                            expression,
+                            ..assignment
+                        });
+                    }
+                }
+
+                let expression = self.node(*expression);
+
+                self.constants.truncate(scope_start);
+
+                if kept_assignments.is_empty() {
+                    return expression;
+                }
+
+                AstNodeKind::AssignmentsAndExpression {
+                    assignments: kept_assignments,
+                    expression: Box::new(expression),
+                }
+            }
+        };
+
+        AstNode::new(kind, span)
+    }
+
+    /// Applies the identities of `left op right` whose operands are already optimized
+    fn operation(&mut self, left: AstNode, op: AstOperator, right: AstNode, span: Span) -> AstNode {
+        let (left_value, right_value) = (constant(&left), constant(&right));
+
+        match (&op, left_value, right_value) {
+            (AstOperator::Multiply, _, Some(1.)) => {
+                return self.simplified("x * 1 → x", left, span)
+            }
+            (AstOperator::Multiply, Some(1.), _) => {
+                return self.simplified("1 * x → x", right, span)
+            }
+            (AstOperator::Multiply, _, Some(-1.)) => {
+                return self.simplified(
+                    "x * -1 → -x",
+                    AstNode::new(AstNodeKind::Negation(Box::new(left)), span),
+                    span,
+                )
+            }
+            (AstOperator::Multiply, Some(-1.), _) => {
+                return self.simplified(
+                    "-1 * x → -x",
+                    AstNode::new(AstNodeKind::Negation(Box::new(right)), span),
+                    span,
+                )
+            }
+            (AstOperator::Divide, _, Some(1.)) => return self.simplified("x / 1 → x", left, span),
+            (AstOperator::Subtract, _, Some(v)) if v == 0. && v.is_sign_positive() => {
+                return self.simplified("x - 0 → x", left, span)
+            }
+            // `-0 + 0` is `0`, so only adding `-0` keeps every `x`
+            (AstOperator::Add, _, Some(v)) if v == 0. && v.is_sign_negative() => {
+                return self.simplified("x + -0 → x", left, span)
+            }
+            (AstOperator::Add, Some(v), _) if v == 0. && v.is_sign_negative() => {
+                return self.simplified("-0 + x → x", right, span)
+            }
+            _ => {}
+        }
+
+        if self.fast_math {
+            match (&op, left_value, right_value) {
+                (AstOperator::Add, _, Some(0.)) => return self.simplified("x + 0 → x", left, span),
+                (AstOperator::Add, Some(0.), _) => {
+                    return self.simplified("0 + x → x", right, span)
+                }
+                (AstOperator::Subtract, Some(0.), _) => {
+                    return self.simplified(
+                        "0 - x → -x",
+                        AstNode::new(AstNodeKind::Negation(Box::new(right)), span),
+                        span,
+                    )
+                }
+                // wrong for `NaN` and infinite `x`
+                (AstOperator::Multiply, _, Some(v)) | (AstOperator::Multiply, Some(v), _)
+                    if v == 0. =>
+                {
+                    return self.simplified(
+                        "x * 0 → 0",
+                        AstNode::new(AstNodeKind::Constant(0.), span),
+                        span,
+                    )
+                }
+                _ => {}
+            }
+        }
+
+        AstNode::new(
+            AstNodeKind::Operation {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            },
+            span,
+        )
+    }
+
+    /// Replaces `pow` with small integer exponents, `pow(x, 0)` is `1` even for `NaN`
+    fn power(&mut self, name: String, mut args: Vec<AstNode>, span: Span) -> AstNode {
+        let exponent = constant(&args[1]);
+
+        if exponent == Some(0.) {
+            return self.simplified(
+                "pow(x, 0) → 1",
+                AstNode::new(AstNodeKind::Constant(1.), span),
+                span,
+            );
+        }
+
+        if exponent == Some(1.) {
+            let base = args.swap_remove(0);
+            return self.simplified("pow(x, 1) → x", base, span);
+        }
+
+        if exponent == Some(2.) {
+            let base = args.swap_remove(0);
+            let base_span = base.span();
+
+            let square = |operand: &AstNode| {
+                AstNode::new(
+                    AstNodeKind::Operation {
+                        left: Box::new(operand.clone()),
+                        op: AstOperator::Multiply,
+                        right: Box::new(operand.clone()),
+                    },
+                    span,
+                )
+            };
+
+            if matches!(base.kind(), AstNodeKind::Variable(_)) {
+                return self.simplified("pow(x, 2) → x * x", square(&base), span);
+            }
+
+            // the base is evaluated once
+            let identifier = temporary_identifier("square", self.squares);
+            self.squares += 1;
+
+            let variable = AstNode::new(AstNodeKind::Variable(identifier.clone()), base_span);
+            let node = AstNode::new(
+                AstNodeKind::AssignmentsAndExpression {
+                    assignments: vec![Assignment {
+                        identifier,
+                        expression: base,
+                        span: base_span,
+                    }],
+                    expression: Box::new(square(&variable)),
+                },
+                span,
+            );
+
+            return self.simplified("pow(x, 2) → x * x", node, span);
+        }
+
+        AstNode::new(AstNodeKind::Function { name, args }, span)
+    }
+
+    /// Removes the branches whose conditions are constant
+    fn branch(
+        &mut self,
+        condition_branches: Vec<Branch>,
+        else_branch: AstNode,
+        span: Span,
+    ) -> AstNode {
+        let mut kept = Vec::with_capacity(condition_branches.len());
+
+        for branch in condition_branches {
+            let condition = self.condition(branch.condition);
+
+            match boolean_constant(&condition) {
+                Some(false) => {
+                    self.changes.push(Optimization::BranchRemoved {
+                        condition: false,
+                        span: condition.span(),
+                    });
+                }
+                // the branches after it are never taken
+                Some(true) => {
+                    self.changes.push(Optimization::BranchRemoved {
+                        condition: true,
+                        span: condition.span(),
+                    });
+                    let body = self.node(branch.body);
+                    return self.branches(kept, body, span);
+                }
+                None => {
+                    let body = self.node(branch.body);
+                    kept.push(Branch { condition, body });
+                }
+            }
+        }
+
+        let else_branch = self.node(else_branch);
+        self.branches(kept, else_branch, span)
+    }
+
+    fn branches(
+        &mut self,
+        condition_branches: Vec<Branch>,
+        else_branch: AstNode,
+        span: Span,
+    ) -> AstNode {
+        if condition_branches.is_empty() {
+            return else_branch;
+        }
+
+        AstNode::new(
+            AstNodeKind::Branch {
+                condition_branches,
+                else_branch: Box::new(else_branch),
+            },
+            span,
+        )
+    }
+
+    /// Folds constant comparisons and removes constant operands of boolean operators
+    fn condition(&mut self, expression: BooleanExpression) -> BooleanExpression {
+        let BooleanExpression { kind, span } = expression;
+
+        let kind = match kind {
+            BooleanExpressionKind::Constant(_) => kind,
+            BooleanExpressionKind::Not(operand) => match self.condition(*operand) {
+                BooleanExpression {
+                    kind: BooleanExpressionKind::Constant(b),
+                    ..
+                } => BooleanExpressionKind::Constant(!b),
+                operand => BooleanExpressionKind::Not(Box::new(operand)),
+            },
+            BooleanExpressionKind::Comparison { left, op, right } => {
+                let left = self.node(*left);
+                let right = self.node(*right);
+
+                match (constant(&left), constant(&right)) {
+                    (Some(left), Some(right)) => BooleanExpressionKind::Constant(match op {
+                        BooleanComparator::Equal => left == right,
+                        BooleanComparator::NotEqual => left != right,
+                        BooleanComparator::LessThan => left < right,
+                        BooleanComparator::LessThanOrEqual => left <= right,
+                        BooleanComparator::GreaterThan => left > right,
+                        BooleanComparator::GreaterThanOrEqual => left >= right,
+                    }),
+                    _ => BooleanExpressionKind::Comparison {
+                        left: Box::new(left),
+                        op,
+                        right: Box::new(right),
+                    },
+                }
+            }
+            BooleanExpressionKind::Operation { left, op, right } => {
+                let left = self.condition(*left);
+                let right = self.condition(*right);
+
+                // conditions have no side effects, so operands can be dropped
+                let (kind, rule) = match (op, boolean_constant(&left), boolean_constant(&right)) {
+                    (BooleanOperator::Xor, Some(left), Some(right)) => (
+                        BooleanExpressionKind::Constant(left ^ right),
+                        "c xor d → constant",
+                    ),
+                    (BooleanOperator::And, Some(false), _)
+                    | (BooleanOperator::And, _, Some(false)) => {
+                        (BooleanExpressionKind::Constant(false), "x && false → false")
+                    }
+                    (BooleanOperator::Or, Some(true), _) | (BooleanOperator::Or, _, Some(true)) => {
+                        (BooleanExpressionKind::Constant(true), "x || true → true")
+                    }
+                    (BooleanOperator::And, Some(true), _) => (right.kind, "true && x → x"),
+                    (BooleanOperator::And, _, Some(true)) => (left.kind, "x && true → x"),
+                    (BooleanOperator::Or, Some(false), _) => (right.kind, "false || x → x"),
+                    (BooleanOperator::Or, _, Some(false)) => (left.kind, "x || false → x"),
+                    (BooleanOperator::Xor, Some(false), _) => (right.kind, "false xor x → x"),
+                    (BooleanOperator::Xor, _, Some(false)) => (left.kind, "x xor false → x"),
+                    (BooleanOperator::Xor, Some(true), _) => (
+                        BooleanExpressionKind::Not(Box::new(right)),
+                        "true xor x → !x",
+                    ),
+                    (BooleanOperator::Xor, _, Some(true)) => (
+                        BooleanExpressionKind::Not(Box::new(left)),
+                        "x xor true → !x",
+                    ),
+                    (op, None, None) => {
+                        return BooleanExpression::new(
+                            BooleanExpressionKind::Operation {
+                                left: Box::new(left),
+                                op,
+                                right: Box::new(right),
+                            },
+                            span,
+                        )
+                    }
+                };
+
+                self.changes.push(Optimization::Simplified { rule, span });
+
+                kind
+            }
+        };
+
+        BooleanExpression::new(kind, span)
+    }
+
+    /// Replaces a node by its value, only the outermost fold of nested constants is reported
+    fn folded(&mut self, value: f64, span: Span, changes_start: usize) -> AstNode {
+        let nested = self.changes.split_off(changes_start);
+        self.changes.extend(
+            nested
+                .into_iter()
+                .filter(|change| !matches!(change, Optimization::ConstantFolded { .. })),
+        );
+
+        self.changes
+            .push(Optimization::ConstantFolded { value, span });
+
+        AstNode::new(AstNodeKind::Constant(value), span)
+    }
+
+    fn simplified(&mut self, rule: &'static str, node: AstNode, span: Span) -> AstNode {
+        self.changes.push(Optimization::Simplified { rule, span });
+
+        node
+    }
+}
+
+/// The value of a literal
+fn constant(node: &AstNode) -> Option<f64> {
+    match node.kind() {
+        AstNodeKind::Constant(n) => Some(*n),
+        AstNodeKind::Integer(n) => Some(f64::from(*n)),
+        AstNodeKind::NamedConstant(constant) => Some(constant.value()),
+        _ => None,
+    }
+}
+
+fn boolean_constant(expression: &BooleanExpression) -> Option<bool> {
+    match expression.kind() {
+        BooleanExpressionKind::Constant(b) => Some(*b),
+        _ => None,
+    }
+}
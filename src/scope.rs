@@ -0,0 +1,87 @@
+use crate::error::Span;
+
+/// The `let` variables that are visible while building the ast.
+///
+/// The scoping follows Rust: a variable is visible from the statement after its assignment to the
+/// end of the block that contains it, which includes nested blocks. An assignment may shadow a
+/// variable of the same or an enclosing block, but not a parameter. A variable cannot be read
+/// before its assignment, not even in its own value, unless a variable of the same name is
+/// already visible.
+#[derive(Debug, Default)]
+pub(crate) struct SymbolTable {
+    blocks: Vec<Block>,
+}
+
+#[derive(Debug)]
+struct Block {
+    /// Visible variables in the order of their assignments
+    variables: Vec<String>,
+    /// Variables that are assigned later in the block, with the spans of their assignments
+    pending: Vec<(String, Span)>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a block that assigns `assignments` in this order
+    pub fn enter(&mut self, assignments: Vec<(String, Span)>) {
+        self.blocks.push(Block {
+            variables: Vec::new(),
+            pending: assignments,
+        });
+    }
+
+    /// Closes the innermost block, its variables are no longer visible
+    pub fn exit(&mut self) {
+        self.blocks.pop().expect("exited block must be entered");
+    }
+
+    /// Makes the next variable that the innermost block assigns visible
+    pub fn define(&mut self, identifier: String) {
+        let block = self
+            .blocks
+            .last_mut()
+            .expect("variables are defined in a block");
+
+        debug_assert_eq!(
+            block.pending.first().map(|(name, _)| name),
+            Some(&identifier)
+        );
+        block.pending.remove(0);
+        block.variables.push(identifier);
+    }
+
+    pub fn contains(&self, identifier: &str) -> bool {
+        self.blocks.iter().any(|block| {
+            block
+                .variables
+                .iter()
+                .any(|variable| variable == identifier)
+        })
+    }
+
+    /// The assignment of a variable that is not visible yet, but later in an enclosing block
+    pub fn later_assignment(&self, identifier: &str) -> Option<Span> {
+        self.blocks.iter().rev().find_map(|block| {
+            block
+                .pending
+                .iter()
+                .find(|(name, _)| name == identifier)
+                .map(|(_, span)| *span)
+        })
+    }
+
+    /// Names of the visible variables
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.blocks.iter().flat_map(|block| block.variables.iter())
+    }
+}
+
+/// The name of a variable that is introduced by the compiler, e.g. `operand_0`.
+///
+/// It cannot clash with the identifiers of the expression, since they cannot contain `_`.
+pub(crate) fn temporary_identifier(prefix: &str, index: usize) -> String {
+    format!("{}_{}", prefix, index)
+}
@@ -0,0 +1,275 @@
+use crate::error::ExpressionError;
+use crate::{
+    Assignment, Ast, AstNode, AstNodeKind, AstOperator, BooleanExpression, BooleanExpressionKind,
+    Branch,
+};
+
+/// What happens if integer arithmetic leaves the range of `i32`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OverflowMode {
+    /// The result wraps around, like `i32::wrapping_add`
+    Wrapping,
+    /// The result is `NaN`, like `i32::checked_add` returning `None`
+    Checked,
+}
+
+impl OverflowMode {
+    fn suffix(self) -> &'static str {
+        match self {
+            Self::Wrapping => "wrapping",
+            Self::Checked => "checked",
+        }
+    }
+}
+
+/// The type of a value while checking an expression, at runtime every value is an `f64`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueType {
+    Integer,
+    Float,
+}
+
+impl Ast {
+    /// Infers which values are integers and replaces the operators whose meaning depends on it
+    /// by calls of operator functions.
+    ///
+    /// Without integer arithmetic every value is a float. With it, integer literals and parameters
+    /// with integer data types are integers, and so are the results of operators on two integers,
+    /// except for `/`. Bitwise operators and shifts need integers.
+    pub(crate) fn check_types(&self, root: AstNode) -> Result<AstNode, ExpressionError> {
+        let scope = self
+            .parameters
+            .iter()
+            .zip(&self.parameter_types)
+            .map(|(parameter, dtype)| {
+                let value_type = if self.integer_arithmetic.is_some() && dtype.is_integer() {
+                    ValueType::Integer
+                } else {
+                    ValueType::Float
+                };
+                (parameter.clone(), value_type)
+            })
+            .collect();
+
+        let mut checker = TypeChecker { ast: self, scope };
+        let (root, _) = checker.node(root)?;
+
+        Ok(root)
+    }
+}
+
+struct TypeChecker<'a> {
+    ast: &'a Ast,
+    /// Visible variables and their types, later ones shadow earlier ones
+    scope: Vec<(String, ValueType)>,
+}
+
+impl<'a> TypeChecker<'a> {
+    fn node(&mut self, node: AstNode) -> Result<(AstNode, ValueType), ExpressionError> {
+        let AstNode { kind, span } = node;
+
+        let (kind, value_type) = match kind {
+            AstNodeKind::Constant(_) | AstNodeKind::NamedConstant(_) => (kind, ValueType::Float),
+            AstNodeKind::Integer(_) => match self.ast.integer_arithmetic {
+                Some(_) => (kind, ValueType::Integer),
+                None => (kind, ValueType::Float),
+            },
+            AstNodeKind::Variable(ref identifier) => {
+                let value_type = self
+                    .scope
+                    .iter()
+                    .rev()
+                    .find(|(name, _)| name == identifier)
+                    .map(|(_, value_type)| *value_type)
+                    .expect("variables are checked when building the ast");
+                (kind, value_type)
+            }
+            AstNodeKind::Negation(operand) => {
+                let (operand, value_type) = self.node(*operand)?;
+
+                match (value_type, self.ast.integer_arithmetic) {
+                    (ValueType::Integer, Some(overflow)) => (
+                        self.call(format!("negate_{}", overflow.suffix()), vec![operand]),
+                        ValueType::Integer,
+                    ),
+                    _ => (AstNodeKind::Negation(Box::new(operand)), value_type),
+                }
+            }
+            AstNodeKind::Operation { left, op, right } => {
+                let (left, left_type) = self.node(*left)?;
+                let (right, right_type) = self.node(*right)?;
+
+                let integer = match (left_type, right_type, self.ast.integer_arithmetic) {
+                    (ValueType::Integer, ValueType::Integer, Some(overflow)) => Some(overflow),
+                    _ => None,
+                };
+
+                let function = match (&op, integer) {
+                    (AstOperator::Divide, _)
+                    | (AstOperator::Add | AstOperator::Subtract | AstOperator::Multiply, None) => {
+                        None
+                    }
+                    (AstOperator::Add, Some(overflow)) => {
+                        Some(format!("add_{}", overflow.suffix()))
+                    }
+                    (AstOperator::Subtract, Some(overflow)) => {
+                        Some(format!("subtract_{}", overflow.suffix()))
+                    }
+                    (AstOperator::Multiply, Some(overflow)) => {
+                        Some(format!("multiply_{}", overflow.suffix()))
+                    }
+                    (AstOperator::FloorDivide, Some(overflow)) => {
+                        Some(format!("floordivide_{}", overflow.suffix()))
+                    }
+                    (AstOperator::FloorDivide, None) => Some("floordivide_float".to_string()),
+                    (AstOperator::Remainder, Some(_)) => Some("remainder_integer".to_string()),
+                    (AstOperator::Remainder, None) => Some("remainder_float".to_string()),
+                    (AstOperator::BitAnd, Some(_)) => Some("bitand_integer".to_string()),
+                    (AstOperator::BitOr, Some(_)) => Some("bitor_integer".to_string()),
+                    (AstOperator::BitXor, Some(_)) => Some("bitxor_integer".to_string()),
+                    (AstOperator::ShiftLeft, Some(overflow)) => {
+                        Some(format!("shiftleft_{}", overflow.suffix()))
+                    }
+                    (AstOperator::ShiftRight, Some(overflow)) => {
+                        Some(format!("shiftright_{}", overflow.suffix()))
+                    }
+                    (
+                        AstOperator::BitAnd
+                        | AstOperator::BitOr
+                        | AstOperator::BitXor
+                        | AstOperator::ShiftLeft
+                        | AstOperator::ShiftRight,
+                        None,
+                    ) => {
+                        return Err(ExpressionError::FloatOperand {
+                            operator: op.symbol().to_string(),
+                            span,
+                        })
+                    }
+                };
+
+                let value_type = match (&op, integer) {
+                    (AstOperator::Divide, _) | (_, None) => ValueType::Float,
+                    (_, Some(_)) => ValueType::Integer,
+                };
+
+                let kind = match function {
+                    Some(function) => self.call(function, vec![left, right]),
+                    None => AstNodeKind::Operation {
+                        left: Box::new(left),
+                        op,
+                        right: Box::new(right),
+                    },
+                };
+
+                (kind, value_type)
+            }
+            AstNodeKind::Function { name, args } => {
+                let args = args
+                    .into_iter()
+                    .map(|arg| Ok(self.node(arg)?.0))
+                    .collect::<Result<Vec<_>, ExpressionError>>()?;
+
+                // calls pass and return `f64` values
+                (AstNodeKind::Function { name, args }, ValueType::Float)
+            }
+            AstNodeKind::Branch {
+                condition_branches,
+                else_branch,
+            } => {
+                let mut value_type = ValueType::Integer;
+
+                let condition_branches = condition_branches
+                    .into_iter()
+                    .map(|branch| {
+                        let condition = self.condition(branch.condition)?;
+                        let (body, body_type) = self.node(branch.body)?;
+                        if body_type == ValueType::Float {
+                            value_type = ValueType::Float;
+                        }
+                        Ok(Branch { condition, body })
+                    })
+                    .collect::<Result<Vec<_>, ExpressionError>>()?;
+
+                let (else_branch, else_type) = self.node(*else_branch)?;
+                if else_type == ValueType::Float {
+                    value_type = ValueType::Float;
+                }
+
+                (
+                    AstNodeKind::Branch {
+                        condition_branches,
+                        else_branch: Box::new(else_branch),
+                    },
+                    value_type,
+                )
+            }
+            AstNodeKind::AssignmentsAndExpression {
+                assignments,
+                expression,
+            } => {
+                let scope_start = self.scope.len();
+
+                let mut checked_assignments = Vec::with_capacity(assignments.len());
+                for assignment in assignments {
+                    let (expression, value_type) = self.node(assignment.expression)?;
+                    self.scope.push((assignment.identifier.clone(), value_type));
+                    checked_assignments.push(Assignment {
+                        expression,
+                        ..assignment
+                    });
+                }
+
+                let (expression, value_type) = self.node(*expression)?;
+
+                self.scope.truncate(scope_start);
+
+                (
+                    AstNodeKind::AssignmentsAndExpression {
+                        assignments: checked_assignments,
+                        expression: Box::new(expression),
+                    },
+                    value_type,
+                )
+            }
+        };
+
+        Ok((AstNode::new(kind, span), value_type))
+    }
+
+    fn condition(
+        &mut self,
+        expression: BooleanExpression,
+    ) -> Result<BooleanExpression, ExpressionError> {
+        let BooleanExpression { kind, span } = expression;
+
+        let kind = match kind {
+            BooleanExpressionKind::Constant(_) => kind,
+            BooleanExpressionKind::Not(operand) => {
+                BooleanExpressionKind::Not(Box::new(self.condition(*operand)?))
+            }
+            // integers and floats compare by their values
+            BooleanExpressionKind::Comparison { left, op, right } => {
+                BooleanExpressionKind::Comparison {
+                    left: Box::new(self.node(*left)?.0),
+                    op,
+                    right: Box::new(self.node(*right)?.0),
+                }
+            }
+            BooleanExpressionKind::Operation { left, op, right } => {
+                BooleanExpressionKind::Operation {
+                    left: Box::new(self.condition(*left)?),
+                    op,
+                    right: Box::new(self.condition(*right)?),
+                }
+            }
+        };
+
+        Ok(BooleanExpression::new(kind, span))
+    }
+
+    /// A call of the operator function `name`
+    fn call(&mut self, name: String, args: Vec<AstNode>) -> AstNodeKind {
+        AstNodeKind::Function { name, args }
+    }
+}
@@ -0,0 +1,356 @@
+use std::fmt;
+
+use crate::diagnostic::Diagnostic;
+use crate::{
+    Assignment, Ast, AstNode, AstNodeKind, BooleanExpression, BooleanExpressionKind, Branch, Span,
+};
+
+/// Something in an expression that is probably a mistake, but does not keep it from being evaluated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// A parameter that is never read, `span` is the whole expression
+    UnusedParameter { name: String, span: Span },
+    /// A `let` variable that is never read
+    UnusedVariable { name: String, span: Span },
+    /// A `let` variable that is assigned again in the same block before it is read
+    OverwrittenVariable { name: String, span: Span },
+}
+
+impl Warning {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::UnusedParameter { span, .. }
+            | Self::UnusedVariable { span, .. }
+            | Self::OverwrittenVariable { span, .. } => *span,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::from(self)
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnusedParameter { name, .. } => write!(f, "unused parameter `{}`", name),
+            Self::UnusedVariable { name, .. } => write!(f, "unused variable `{}`", name),
+            Self::OverwrittenVariable { name, .. } => {
+                write!(f, "variable `{}` is assigned again before it is read", name)
+            }
+        }
+    }
+}
+
+impl Ast {
+    /// Warns about the parameters and variables that the expression never reads,
+    /// before they are shadowed by the no-data handling
+    pub(crate) fn unused_warnings(&self, root: &AstNode) -> Vec<Warning> {
+        let reads = Reads::count(root, &self.parameters);
+
+        let mut warnings = Vec::new();
+
+        for (i, parameter) in self.parameters.iter().enumerate() {
+            // masks are read by the no-data handling
+            let mask = self
+                .parameter_masks
+                .iter()
+                .any(|mask| mask.as_ref() == Some(parameter));
+
+            if reads.counts[i] == 0 && !mask {
+                warnings.push(Warning::UnusedParameter {
+                    name: parameter.to_string(),
+                    span: root.span(),
+                });
+            }
+        }
+
+        for (definition, count) in reads
+            .definitions
+            .iter()
+            .zip(&reads.counts[self.parameters.len()..])
+        {
+            if *count > 0 {
+                continue;
+            }
+
+            let name = definition.identifier.to_string();
+            let span = definition.span;
+
+            warnings.push(if definition.overwritten {
+                Warning::OverwrittenVariable { name, span }
+            } else {
+                Warning::UnusedVariable { name, span }
+            });
+        }
+
+        warnings
+    }
+
+    /// The parameters whose values can change the result, in the order of the parameters.
+    ///
+    /// The values of the other parameters are not needed, any value can be passed for them,
+    /// e.g. without loading them from disk.
+    pub fn used_parameters(&self) -> Vec<&str> {
+        let (root, _) = remove_dead_assignments(self.root.clone(), &self.parameters);
+        let reads = Reads::count(&root, &self.parameters);
+
+        self.parameters
+            .iter()
+            .zip(&reads.counts)
+            .filter(|(_, count)| **count > 0)
+            .map(|(parameter, _)| parameter.as_str())
+            .collect()
+    }
+}
+
+/// Removes the assignments of variables that are never read, until only read ones remain,
+/// and returns the names and spans of the removed ones
+pub(crate) fn remove_dead_assignments(
+    root: AstNode,
+    parameters: &[String],
+) -> (AstNode, Vec<(String, Span)>) {
+    let mut root = root;
+    let mut removed = Vec::new();
+
+    // removing an assignment can make the variables that it reads unused
+    loop {
+        let reads = Reads::count(&root, parameters);
+
+        let mut removal = Removal {
+            counts: reads.counts,
+            definitions: parameters.len(),
+            removed: Vec::new(),
+        };
+        root = removal.node(root);
+
+        if removal.removed.is_empty() {
+            return (root, removed);
+        }
+
+        removed.append(&mut removal.removed);
+    }
+}
+
+struct Definition {
+    identifier: String,
+    span: Span,
+    /// Whether it is shadowed by another assignment in the same block before it is read
+    overwritten: bool,
+}
+
+/// Numbers the definitions of variables in the order they are visited and counts their reads
+struct Reads {
+    /// Visible variables and the ids of their definitions, later ones shadow earlier ones
+    scope: Vec<(String, usize)>,
+    /// The number of reads of each definition, the parameters come first
+    counts: Vec<usize>,
+    /// The `let` variables, their ids come after the ones of the parameters
+    definitions: Vec<Definition>,
+}
+
+impl Reads {
+    fn count(root: &AstNode, parameters: &[String]) -> Self {
+        let mut reads = Self {
+            scope: parameters
+                .iter()
+                .cloned()
+                .enumerate()
+                .map(|(id, parameter)| (parameter, id))
+                .collect(),
+            counts: vec![0; parameters.len()],
+            definitions: Vec::new(),
+        };
+
+        reads.node(root);
+
+        reads
+    }
+
+    fn node(&mut self, node: &AstNode) {
+        match node.kind() {
+            AstNodeKind::Constant(_) | AstNodeKind::Integer(_) | AstNodeKind::NamedConstant(_) => {}
+            AstNodeKind::Variable(identifier) => {
+                let id = self
+                    .scope
+                    .iter()
+                    .rev()
+                    .find(|(name, _)| name == identifier)
+                    .map(|(_, id)| *id)
+                    .expect("variables are checked when building the ast");
+                self.counts[id] += 1;
+            }
+            AstNodeKind::Negation(operand) => self.node(operand),
+            AstNodeKind::Operation { left, right, .. } => {
+                self.node(left);
+                self.node(right);
+            }
+            AstNodeKind::Function { args, .. } => {
+                for arg in args {
+                    self.node(arg);
+                }
+            }
+            AstNodeKind::Branch {
+                condition_branches,
+                else_branch,
+            } => {
+                for branch in condition_branches {
+                    self.condition(branch.condition());
+                    self.node(branch.body());
+                }
+                self.node(else_branch);
+            }
+            AstNodeKind::AssignmentsAndExpression {
+                assignments,
+                expression,
+            } => {
+                let scope_start = self.scope.len();
+
+                for assignment in assignments {
+                    self.node(assignment.expression());
+
+                    // the value is dropped unread if it is shadowed in the same block
+                    if let Some((_, id)) = self.scope[scope_start..]
+                        .iter()
+                        .rev()
+                        .find(|(name, _)| name == assignment.identifier())
+                    {
+                        if self.counts[*id] == 0 {
+                            let parameters = self.counts.len() - self.definitions.len();
+                            self.definitions[*id - parameters].overwritten = true;
+                        }
+                    }
+
+                    self.scope
+                        .push((assignment.identifier().to_string(), self.counts.len()));
+                    self.counts.push(0);
+                    self.definitions.push(Definition {
+                        identifier: assignment.identifier().to_string(),
+                        span: assignment.span(),
+                        overwritten: false,
+                    });
+                }
+
+                self.node(expression);
+
+                self.scope.truncate(scope_start);
+            }
+        }
+    }
+
+    fn condition(&mut self, expression: &BooleanExpression) {
+        match expression.kind() {
+            BooleanExpressionKind::Constant(_) => {}
+            BooleanExpressionKind::Not(operand) => self.condition(operand),
+            BooleanExpressionKind::Comparison { left, right, .. } => {
+                self.node(left);
+                self.node(right);
+            }
+            BooleanExpressionKind::Operation { left, right, .. } => {
+                self.condition(left);
+                self.condition(right);
+            }
+        }
+    }
+}
+
+/// Drops the assignments whose definitions have no reads, numbered in the same order as [`Reads`]
+struct Removal {
+    counts: Vec<usize>,
+    /// Number of definitions that were visited
+    definitions: usize,
+    removed: Vec<(String, Span)>,
+}
+
+impl Removal {
+    fn node(&mut self, node: AstNode) -> AstNode {
+        let AstNode { kind, span } = node;
+
+        let kind = match kind {
+            AstNodeKind::Constant(_)
+            | AstNodeKind::Integer(_)
+            | AstNodeKind::NamedConstant(_)
+            | AstNodeKind::Variable(_) => kind,
+            AstNodeKind::Negation(operand) => AstNodeKind::Negation(Box::new(self.node(*operand))),
+            AstNodeKind::Operation { left, op, right } => AstNodeKind::Operation {
+                left: Box::new(self.node(*left)),
+                op,
+                right: Box::new(self.node(*right)),
+            },
+            AstNodeKind::Function { name, args } => AstNodeKind::Function {
+                name,
+                args: args.into_iter().map(|arg| self.node(arg)).collect(),
+            },
+            AstNodeKind::Branch {
+                condition_branches,
+                else_branch,
+            } => AstNodeKind::Branch {
+                condition_branches: condition_branches
+                    .into_iter()
+                    .map(|branch| Branch {
+                        condition: self.condition(branch.condition),
+                        body: self.node(branch.body),
+                    })
+                    .collect(),
+                else_branch: Box::new(self.node(*else_branch)),
+            },
+            AstNodeKind::AssignmentsAndExpression {
+                assignments,
+                expression,
+            } => {
+                let mut live_assignments = Vec::with_capacity(assignments.len());
+
+                for assignment in assignments {
+                    // the nested definitions are numbered even if the assignment is removed
+                    let expression = self.node(assignment.expression);
+
+                    let id = self.definitions;
+                    self.definitions += 1;
+
+                    if self.counts[id] == 0 {
+                        self.removed.push((assignment.identifier, assignment.span));
+                    } else {
+                        live_assignments.push(Assignment {
+                            expression,
+                            ..assignment
+                        });
+                    }
+                }
+
+                AstNodeKind::AssignmentsAndExpression {
+                    assignments: live_assignments,
+                    expression: Box::new(self.node(*expression)),
+                }
+            }
+        };
+
+        AstNode::new(kind, span)
+    }
+
+    fn condition(&mut self, expression: BooleanExpression) -> BooleanExpression {
+        let BooleanExpression { kind, span } = expression;
+
+        let kind = match kind {
+            BooleanExpressionKind::Constant(_) => kind,
+            BooleanExpressionKind::Not(operand) => {
+                BooleanExpressionKind::Not(Box::new(self.condition(*operand)))
+            }
+            BooleanExpressionKind::Comparison { left, op, right } => {
+                BooleanExpressionKind::Comparison {
+                    left: Box::new(self.node(*left)),
+                    op,
+                    right: Box::new(self.node(*right)),
+                }
+            }
+            BooleanExpressionKind::Operation { left, op, right } => {
+                BooleanExpressionKind::Operation {
+                    left: Box::new(self.condition(*left)),
+                    op,
+                    right: Box::new(self.condition(*right)),
+                }
+            }
+        };
+
+        BooleanExpression::new(kind, span)
+    }
+}
@@ -0,0 +1,560 @@
+use crate::batch::check_inputs;
+use crate::data_type::DataType;
+use crate::eval::EvalError;
+use crate::functions::{BuiltinFunction, Function};
+use crate::{
+    Ast, AstNode, AstNodeKind, AstOperator, BooleanComparator, BooleanExpression,
+    BooleanExpressionKind, BooleanOperator, Branch,
+};
+
+/// Number of elements that every instruction processes at once
+const LANES: usize = 64;
+
+/// Index of a register, which holds one value per lane.
+/// Booleans are stored as `1.0` and `0.0`.
+type Register = usize;
+
+#[derive(Debug, Clone)]
+enum Instruction {
+    Constant {
+        dst: Register,
+        value: f64,
+    },
+    Parameter {
+        dst: Register,
+        index: usize,
+    },
+    /// Converts to `data_type` and back like [`DataType::cast`]
+    Cast {
+        dst: Register,
+        operand: Register,
+        data_type: DataType,
+    },
+    Negate {
+        dst: Register,
+        operand: Register,
+    },
+    Arithmetic {
+        dst: Register,
+        op: AstOperator,
+        left: Register,
+        right: Register,
+    },
+    Call {
+        dst: Register,
+        function: &'static BuiltinFunction,
+        args: Vec<Register>,
+    },
+    Compare {
+        dst: Register,
+        op: BooleanComparator,
+        left: Register,
+        right: Register,
+    },
+    Not {
+        dst: Register,
+        operand: Register,
+    },
+    Logic {
+        dst: Register,
+        op: BooleanOperator,
+        left: Register,
+        right: Register,
+    },
+    /// Takes `if_true` for lanes where `condition` holds and `if_false` for the others
+    Select {
+        dst: Register,
+        condition: Register,
+        if_true: Register,
+        if_false: Register,
+    },
+    /// Skips to `target` if `condition` holds in no lane
+    JumpIfNone {
+        condition: Register,
+        target: usize,
+    },
+    /// Skips to `target` if `condition` holds in every lane
+    JumpIfAll {
+        condition: Register,
+        target: usize,
+    },
+}
+
+/// An expression compiled to bytecode for a virtual machine that works on whole slices.
+///
+/// Every instruction processes a block of elements, so the dispatch overhead is shared by all of them.
+/// Branches skip a body if no element of the block needs it and otherwise merge the bodies per element.
+#[derive(Debug, Clone)]
+pub struct BytecodeExpression {
+    instructions: Vec<Instruction>,
+    registers: usize,
+    result: Register,
+    arity: usize,
+}
+
+impl BytecodeExpression {
+    pub fn new(ast: &Ast) -> Result<Self, EvalError> {
+        let mut compiler = BytecodeCompiler {
+            instructions: Vec::new(),
+            registers: 0,
+            ast,
+            scope: Vec::new(),
+        };
+
+        let parameters = ast.parameters().iter().zip(ast.parameter_types());
+        for (index, (parameter, data_type)) in parameters.enumerate() {
+            let dst = compiler.register();
+            compiler.emit(Instruction::Parameter { dst, index });
+            let dst = compiler.cast(dst, *data_type);
+            compiler.scope.push((parameter, dst));
+        }
+
+        let result = compiler.value(ast.root())?;
+        let result = compiler.cast(result, ast.output_type());
+
+        Ok(Self {
+            instructions: compiler.instructions,
+            registers: compiler.registers,
+            result,
+            arity: ast.parameters().len(),
+        })
+    }
+
+    /// Number of parameters of the expression
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Calls the expression with `parameters` in the order of the declared parameters
+    pub fn call(&self, parameters: &[f64]) -> Result<f64, EvalError> {
+        let inputs = parameters
+            .iter()
+            .map(std::slice::from_ref)
+            .collect::<Vec<_>>();
+
+        let mut output = [0.];
+        self.evaluate_slices(&inputs, &mut output)?;
+
+        Ok(output[0])
+    }
+
+    /// Evaluates the expression for every element, with one input slice per parameter
+    pub fn evaluate_slices(&self, inputs: &[&[f64]], output: &mut [f64]) -> Result<(), EvalError> {
+        check_inputs(self.arity, inputs, output.len())?;
+
+        let mut registers = vec![[0.; LANES]; self.registers];
+
+        for (block, output) in output.chunks_mut(LANES).enumerate() {
+            let start = block * LANES;
+            let inputs = inputs
+                .iter()
+                .map(|input| &input[start..start + output.len()])
+                .collect::<Vec<_>>();
+
+            self.run(&mut registers, &inputs, output.len());
+
+            output.copy_from_slice(&registers[self.result][..output.len()]);
+        }
+
+        Ok(())
+    }
+
+    /// Runs the program for the first `lanes` lanes
+    fn run(&self, registers: &mut [[f64; LANES]], inputs: &[&[f64]], lanes: usize) {
+        let mut pc = 0;
+
+        while let Some(instruction) = self.instructions.get(pc) {
+            pc += 1;
+
+            match instruction {
+                Instruction::Constant { dst, value } => registers[*dst][..lanes].fill(*value),
+                Instruction::Parameter { dst, index } => {
+                    registers[*dst][..lanes].copy_from_slice(inputs[*index])
+                }
+                Instruction::Cast {
+                    dst,
+                    operand,
+                    data_type,
+                } => {
+                    let operand = registers[*operand];
+                    for (dst, operand) in registers[*dst][..lanes].iter_mut().zip(operand) {
+                        *dst = data_type.cast(operand);
+                    }
+                }
+                Instruction::Negate { dst, operand } => {
+                    let operand = registers[*operand];
+                    for (dst, operand) in registers[*dst][..lanes].iter_mut().zip(operand) {
+                        *dst = -operand;
+                    }
+                }
+                Instruction::Arithmetic {
+                    dst,
+                    op,
+                    left,
+                    right,
+                } => {
+                    let op = match op {
+                        AstOperator::Add => |a: f64, b: f64| a + b,
+                        AstOperator::Subtract => |a, b| a - b,
+                        AstOperator::Multiply => |a, b| a * b,
+                        AstOperator::Divide => |a, b| a / b,
+                        op => {
+                            unreachable!("`{}` is replaced when the types are checked", op.symbol())
+                        }
+                    };
+                    binary(registers, *dst, *left, *right, lanes, op);
+                }
+                Instruction::Call {
+                    dst,
+                    function,
+                    args,
+                } => {
+                    // builtin functions take at most three arguments
+                    let mut values = [0.; 3];
+                    let mut result = [0.; LANES];
+                    for (lane, result) in result[..lanes].iter_mut().enumerate() {
+                        for (value, arg) in values.iter_mut().zip(args) {
+                            *value = registers[*arg][lane];
+                        }
+                        *result = function.evaluate(&values[..args.len()]);
+                    }
+                    registers[*dst][..lanes].copy_from_slice(&result[..lanes]);
+                }
+                Instruction::Compare {
+                    dst,
+                    op,
+                    left,
+                    right,
+                } => {
+                    let op = match op {
+                        BooleanComparator::Equal => |a: f64, b: f64| a == b,
+                        BooleanComparator::NotEqual => |a, b| a != b,
+                        BooleanComparator::LessThan => |a, b| a < b,
+                        BooleanComparator::LessThanOrEqual => |a, b| a <= b,
+                        BooleanComparator::GreaterThan => |a, b| a > b,
+                        BooleanComparator::GreaterThanOrEqual => |a, b| a >= b,
+                    };
+                    binary(registers, *dst, *left, *right, lanes, |a, b| {
+                        f64::from(u8::from(op(a, b)))
+                    });
+                }
+                Instruction::Not { dst, operand } => {
+                    let operand = registers[*operand];
+                    for (dst, operand) in registers[*dst][..lanes].iter_mut().zip(operand) {
+                        *dst = 1. - operand;
+                    }
+                }
+                Instruction::Logic {
+                    dst,
+                    op,
+                    left,
+                    right,
+                } => {
+                    let op = match op {
+                        BooleanOperator::And => |a: bool, b: bool| a && b,
+                        BooleanOperator::Or => |a, b| a || b,
+                        BooleanOperator::Xor => |a, b| a ^ b,
+                    };
+                    binary(registers, *dst, *left, *right, lanes, |a, b| {
+                        f64::from(u8::from(op(a != 0., b != 0.)))
+                    });
+                }
+                Instruction::Select {
+                    dst,
+                    condition,
+                    if_true,
+                    if_false,
+                } => {
+                    let condition = registers[*condition];
+                    let if_true = registers[*if_true];
+                    let if_false = registers[*if_false];
+                    for lane in 0..lanes {
+                        registers[*dst][lane] = if condition[lane] != 0. {
+                            if_true[lane]
+                        } else {
+                            if_false[lane]
+                        };
+                    }
+                }
+                Instruction::JumpIfNone { condition, target } => {
+                    if registers[*condition][..lanes].iter().all(|c| *c == 0.) {
+                        pc = *target;
+                    }
+                }
+                Instruction::JumpIfAll { condition, target } => {
+                    if registers[*condition][..lanes].iter().all(|c| *c != 0.) {
+                        pc = *target;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Applies `op` lane-wise, the operands are copied since they may be the same register
+fn binary(
+    registers: &mut [[f64; LANES]],
+    dst: Register,
+    left: Register,
+    right: Register,
+    lanes: usize,
+    op: impl Fn(f64, f64) -> f64,
+) {
+    let left = registers[left];
+    let right = registers[right];
+
+    for ((dst, left), right) in registers[dst][..lanes].iter_mut().zip(left).zip(right) {
+        *dst = op(left, right);
+    }
+}
+
+struct BytecodeCompiler<'a> {
+    instructions: Vec<Instruction>,
+    registers: usize,
+    /// The expression whose functions are visible, which changes when a user defined function is inlined
+    ast: &'a Ast,
+    /// Visible variables and their registers, later ones shadow earlier ones
+    scope: Vec<(&'a str, Register)>,
+}
+
+impl<'a> BytecodeCompiler<'a> {
+    fn register(&mut self) -> Register {
+        self.registers += 1;
+        self.registers - 1
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.instructions.push(instruction);
+    }
+
+    /// Emits a jump whose target is set with [`Self::patch`] later
+    fn emit_jump(
+        &mut self,
+        jump: fn(Register, usize) -> Instruction,
+        condition: Register,
+    ) -> usize {
+        self.instructions.push(jump(condition, usize::MAX));
+        self.instructions.len() - 1
+    }
+
+    /// Lets the jump at `index` continue after the last emitted instruction
+    fn patch(&mut self, index: usize) {
+        let end = self.instructions.len();
+        match &mut self.instructions[index] {
+            Instruction::JumpIfNone { target, .. } | Instruction::JumpIfAll { target, .. } => {
+                *target = end
+            }
+            _ => unreachable!("only jumps are patched"),
+        }
+    }
+
+    fn value(&mut self, node: &'a AstNode) -> Result<Register, EvalError> {
+        Ok(match node.kind() {
+            AstNodeKind::Constant(value) => self.constant(*value),
+            AstNodeKind::Integer(value) => self.constant(f64::from(*value)),
+            AstNodeKind::NamedConstant(constant) => self.constant(constant.value()),
+            AstNodeKind::Variable(identifier) => self
+                .scope
+                .iter()
+                .rev()
+                .find(|(name, _)| *name == identifier)
+                .map(|(_, register)| *register)
+                .expect("variables are checked when building the ast"),
+            AstNodeKind::Negation(operand) => {
+                let operand = self.value(operand)?;
+                let dst = self.register();
+                self.emit(Instruction::Negate { dst, operand });
+                dst
+            }
+            AstNodeKind::Operation { left, op, right } => {
+                let left = self.value(left)?;
+                let right = self.value(right)?;
+                let dst = self.register();
+                self.emit(Instruction::Arithmetic {
+                    dst,
+                    op: op.clone(),
+                    left,
+                    right,
+                });
+                dst
+            }
+            AstNodeKind::Function { name, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.value(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.call(name, args)?
+            }
+            AstNodeKind::Branch {
+                condition_branches,
+                else_branch,
+            } => self.branch(condition_branches, else_branch)?,
+            AstNodeKind::AssignmentsAndExpression {
+                assignments,
+                expression,
+            } => {
+                let scope_start = self.scope.len();
+
+                for assignment in assignments {
+                    let register = self.value(assignment.expression())?;
+                    self.scope.push((assignment.identifier(), register));
+                }
+
+                let result = self.value(expression)?;
+
+                self.scope.truncate(scope_start);
+
+                result
+            }
+        })
+    }
+
+    fn cast(&mut self, operand: Register, data_type: DataType) -> Register {
+        if data_type == DataType::F64 {
+            return operand;
+        }
+
+        let dst = self.register();
+        self.emit(Instruction::Cast {
+            dst,
+            operand,
+            data_type,
+        });
+        dst
+    }
+
+    fn constant(&mut self, value: f64) -> Register {
+        let dst = self.register();
+        self.emit(Instruction::Constant { dst, value });
+        dst
+    }
+
+    fn call(&mut self, name: &str, args: Vec<Register>) -> Result<Register, EvalError> {
+        let function = self
+            .ast
+            .functions
+            .get(name)
+            .expect("functions are checked when building the ast");
+
+        let ast = match function {
+            Function::Builtin(function) => {
+                let dst = self.register();
+                self.emit(Instruction::Call {
+                    dst,
+                    function,
+                    args,
+                });
+                return Ok(dst);
+            }
+            Function::User(_) => function
+                .expression()
+                .ok_or_else(|| EvalError::RustFunction {
+                    name: name.to_string(),
+                })?,
+        };
+
+        // inline the user defined function with its own parameters and functions in scope
+        let scope = ast
+            .parameters()
+            .iter()
+            .map(String::as_str)
+            .zip(args)
+            .collect();
+        let outer_scope = std::mem::replace(&mut self.scope, scope);
+        let outer_ast = std::mem::replace(&mut self.ast, ast);
+
+        let result = self.value(ast.root());
+
+        self.scope = outer_scope;
+        self.ast = outer_ast;
+
+        result
+    }
+
+    /// Emits
+    ///
+    /// ```text
+    ///     condition
+    ///     jump to else if no lane holds the condition
+    ///     body
+    /// else:
+    ///     jump to end if every lane holds the condition
+    ///     else branch
+    /// end:
+    ///     select body or else branch
+    /// ```
+    ///
+    /// The registers of a skipped part are not read by the select.
+    fn branch(
+        &mut self,
+        condition_branches: &'a [Branch],
+        else_branch: &'a AstNode,
+    ) -> Result<Register, EvalError> {
+        let (branch, remaining_branches) = match condition_branches.split_first() {
+            Some(split) => split,
+            None => return self.value(else_branch),
+        };
+
+        let condition = self.condition(branch.condition())?;
+
+        let skip_body = self.emit_jump(
+            |condition, target| Instruction::JumpIfNone { condition, target },
+            condition,
+        );
+        let if_true = self.value(branch.body())?;
+        self.patch(skip_body);
+
+        let skip_else = self.emit_jump(
+            |condition, target| Instruction::JumpIfAll { condition, target },
+            condition,
+        );
+        let if_false = self.branch(remaining_branches, else_branch)?;
+        self.patch(skip_else);
+
+        let dst = self.register();
+        self.emit(Instruction::Select {
+            dst,
+            condition,
+            if_true,
+            if_false,
+        });
+
+        Ok(dst)
+    }
+
+    fn condition(&mut self, expression: &'a BooleanExpression) -> Result<Register, EvalError> {
+        Ok(match expression.kind() {
+            BooleanExpressionKind::Constant(b) => self.constant(f64::from(u8::from(*b))),
+            BooleanExpressionKind::Not(operand) => {
+                let operand = self.condition(operand)?;
+                let dst = self.register();
+                self.emit(Instruction::Not { dst, operand });
+                dst
+            }
+            BooleanExpressionKind::Comparison { left, op, right } => {
+                let left = self.value(left)?;
+                let right = self.value(right)?;
+                let dst = self.register();
+                self.emit(Instruction::Compare {
+                    dst,
+                    op: op.clone(),
+                    left,
+                    right,
+                });
+                dst
+            }
+            BooleanExpressionKind::Operation { left, op, right } => {
+                let left = self.condition(left)?;
+                let right = self.condition(right)?;
+                let dst = self.register();
+                self.emit(Instruction::Logic {
+                    dst,
+                    op: op.clone(),
+                    left,
+                    right,
+                });
+                dst
+            }
+        })
+    }
+}
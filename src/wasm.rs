@@ -0,0 +1,437 @@
+use std::fmt;
+
+use wasmer::{Exports, FunctionType, ImportObject, Instance, Module, Store, Type, Value};
+
+use crate::data_type::DataType;
+use crate::eval::EvalError;
+use crate::functions::Function;
+use crate::{
+    Ast, AstNode, AstNodeKind, AstOperator, BooleanComparator, BooleanExpression,
+    BooleanExpressionKind, BooleanOperator,
+};
+
+/// Module of the host functions that the generated code imports
+const HOST_MODULE: &str = "env";
+
+impl Ast {
+    /// Generates a WebAssembly module in text format that exports the expression under its name.
+    ///
+    /// Builtin functions are imported from the `env` module, unless wasm has an instruction
+    /// with the same semantics, and user defined functions become functions of the module.
+    pub fn wat(&self) -> Result<String, WasmError> {
+        let mut module = vec!["(module".to_string()];
+
+        let mut definitions = Vec::new();
+        for name in self.imports() {
+            let function = self
+                .functions
+                .get(&name)
+                .expect("imports are checked when building the ast");
+
+            match function {
+                Function::Builtin(builtin) if builtin_instruction(builtin.name()).is_none() => {
+                    module.push(format!(
+                        "  (import \"{}\" \"{}\" (func ${} {} (result f64)))",
+                        HOST_MODULE,
+                        name,
+                        name,
+                        "(param f64) ".repeat(function.arity()).trim_end(),
+                    ));
+                }
+                Function::Builtin(_) => {}
+                Function::User(_) => {
+                    let ast = function
+                        .expression()
+                        .ok_or_else(|| WasmError::RustFunction {
+                            name: name.to_string(),
+                        })?;
+                    definitions.push(FunctionWriter::new(ast).function(None)?);
+                }
+            }
+        }
+
+        module.extend(definitions);
+        module.push(FunctionWriter::new(self).function(Some(self.name()))?);
+        module.push(")".to_string());
+
+        Ok(module.join("\n"))
+    }
+}
+
+/// Wasm instructions that compute builtin functions exactly like their Rust bodies.
+/// `min`, `max` and `round` are missing on purpose, since they treat NaN or ties differently.
+fn builtin_instruction(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "abs" => "f64.abs",
+        "sqrt" => "f64.sqrt",
+        "floor" => "f64.floor",
+        "ceil" => "f64.ceil",
+        _ => return None,
+    })
+}
+
+/// Emits the instructions of one wasm function
+struct FunctionWriter<'a> {
+    ast: &'a Ast,
+    lines: Vec<String>,
+    /// Locals for `let` assignments, unique per assignment so that shadowing works like in Rust
+    locals: Vec<String>,
+    /// Visible variables and their locals, later ones shadow earlier ones
+    scope: Vec<(&'a str, String)>,
+    depth: usize,
+}
+
+impl<'a> FunctionWriter<'a> {
+    fn new(ast: &'a Ast) -> Self {
+        Self {
+            ast,
+            lines: Vec::new(),
+            locals: Vec::new(),
+            scope: Vec::new(),
+            depth: 2,
+        }
+    }
+
+    /// Emits the function for the `Ast`, which is exported if it has an `export` name.
+    /// Only the exported function converts its parameters and result to their data types,
+    /// since calls from other expressions pass `f64` values.
+    fn function(mut self, export: Option<&str>) -> Result<String, WasmError> {
+        let ast = self.ast;
+
+        self.scope = ast
+            .parameters()
+            .iter()
+            .map(|parameter| (parameter.as_str(), format!("${}", parameter)))
+            .collect();
+
+        if export.is_some() {
+            for (parameter, data_type) in ast.parameters().iter().zip(ast.parameter_types()) {
+                if *data_type != DataType::F64 {
+                    self.emit(format!("local.get ${}", parameter));
+                    self.cast(*data_type);
+                    self.emit(format!("local.set ${}", parameter));
+                }
+            }
+        }
+
+        self.expression(ast.root())?;
+
+        if export.is_some() {
+            self.cast(ast.output_type());
+        }
+
+        let mut header = format!("  (func ${}", ast.name());
+        if let Some(export) = export {
+            header.push_str(&format!(" (export \"{}\")", export));
+        }
+        for parameter in ast.parameters() {
+            header.push_str(&format!(" (param ${} f64)", parameter));
+        }
+        header.push_str(" (result f64)");
+
+        let locals = self
+            .locals
+            .iter()
+            .map(|local| format!("    (local {} f64)", local));
+
+        Ok(std::iter::once(header)
+            .chain(locals)
+            .chain(self.lines)
+            .collect::<Vec<_>>()
+            .join("\n")
+            + ")")
+    }
+
+    fn emit(&mut self, instruction: impl Into<String>) {
+        self.lines
+            .push(format!("{}{}", "  ".repeat(self.depth), instruction.into()));
+    }
+
+    /// Converts the value on the stack to `data_type` and back like [`DataType::cast`]
+    fn cast(&mut self, data_type: DataType) {
+        match data_type {
+            DataType::F64 => {}
+            DataType::F32 => {
+                self.emit("f32.demote_f64");
+                self.emit("f64.promote_f32");
+            }
+            DataType::U8 | DataType::U16 | DataType::I16 => {
+                let (min, max) = data_type
+                    .integer_bounds()
+                    .expect("integer types have bounds");
+
+                // every integer type fits into `i32`, whose saturating conversion also turns `NaN` into `0`
+                self.emit("i32.trunc_sat_f64_s");
+                self.emit("f64.convert_i32_s");
+                self.emit(format!("f64.const {}", float_literal(min)));
+                self.emit("f64.max");
+                self.emit(format!("f64.const {}", float_literal(max)));
+                self.emit("f64.min");
+            }
+        }
+    }
+
+    fn expression(&mut self, node: &'a AstNode) -> Result<(), WasmError> {
+        match node.kind() {
+            AstNodeKind::Constant(n) => self.emit(format!("f64.const {}", float_literal(*n))),
+            AstNodeKind::Integer(n) => {
+                self.emit(format!("f64.const {}", float_literal(f64::from(*n))))
+            }
+            AstNodeKind::NamedConstant(constant) => {
+                self.emit(format!("f64.const {}", float_literal(constant.value())))
+            }
+            AstNodeKind::Variable(identifier) => {
+                let local = self
+                    .scope
+                    .iter()
+                    .rev()
+                    .find(|(name, _)| *name == identifier)
+                    .map(|(_, local)| local.clone())
+                    .expect("variables are checked when building the ast");
+                self.emit(format!("local.get {}", local));
+            }
+            AstNodeKind::Negation(operand) => {
+                self.expression(operand)?;
+                self.emit("f64.neg");
+            }
+            AstNodeKind::Operation { left, op, right } => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.emit(match op {
+                    AstOperator::Add => "f64.add",
+                    AstOperator::Subtract => "f64.sub",
+                    AstOperator::Multiply => "f64.mul",
+                    AstOperator::Divide => "f64.div",
+                    op => unreachable!("`{}` is replaced when the types are checked", op.symbol()),
+                });
+            }
+            AstNodeKind::Function { name, args } => {
+                let name = name.to_string();
+
+                match builtin_instruction(&name) {
+                    Some(instruction) => {
+                        for arg in args {
+                            self.expression(arg)?;
+                        }
+                        self.emit(instruction);
+                    }
+                    None => {
+                        for arg in args {
+                            self.expression(arg)?;
+                        }
+                        self.emit(format!("call ${}", name));
+                    }
+                }
+            }
+            AstNodeKind::Branch {
+                condition_branches,
+                else_branch,
+            } => {
+                for branch in condition_branches {
+                    self.boolean_expression(branch.condition())?;
+                    self.emit("if (result f64)");
+                    self.depth += 1;
+                    self.expression(branch.body())?;
+                    self.depth -= 1;
+                    self.emit("else");
+                    self.depth += 1;
+                }
+
+                self.expression(else_branch)?;
+
+                for _ in condition_branches {
+                    self.depth -= 1;
+                    self.emit("end");
+                }
+            }
+            AstNodeKind::AssignmentsAndExpression {
+                assignments,
+                expression,
+            } => {
+                let scope_start = self.scope.len();
+
+                for assignment in assignments {
+                    self.expression(assignment.expression())?;
+
+                    let local = format!("${}_{}", assignment.identifier(), self.locals.len());
+                    self.emit(format!("local.set {}", local));
+                    self.locals.push(local.clone());
+                    self.scope.push((assignment.identifier(), local));
+                }
+
+                self.expression(expression)?;
+
+                self.scope.truncate(scope_start);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn boolean_expression(&mut self, expression: &'a BooleanExpression) -> Result<(), WasmError> {
+        match expression.kind() {
+            BooleanExpressionKind::Constant(b) => self.emit(format!("i32.const {}", i32::from(*b))),
+            BooleanExpressionKind::Not(operand) => {
+                self.boolean_expression(operand)?;
+                self.emit("i32.eqz");
+            }
+            BooleanExpressionKind::Comparison { left, op, right } => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.emit(match op {
+                    BooleanComparator::Equal => "f64.eq",
+                    BooleanComparator::NotEqual => "f64.ne",
+                    BooleanComparator::LessThan => "f64.lt",
+                    BooleanComparator::LessThanOrEqual => "f64.le",
+                    BooleanComparator::GreaterThan => "f64.gt",
+                    BooleanComparator::GreaterThanOrEqual => "f64.ge",
+                });
+            }
+            BooleanExpressionKind::Operation { left, op, right } => {
+                // expressions have no side effects, so there is no need to short-circuit
+                self.boolean_expression(left)?;
+                self.boolean_expression(right)?;
+                self.emit(match op {
+                    BooleanOperator::And => "i32.and",
+                    BooleanOperator::Or => "i32.or",
+                    BooleanOperator::Xor => "i32.xor",
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats `value` as a wasm float literal that parses to exactly the same `f64`
+fn float_literal(value: f64) -> String {
+    if value.is_nan() {
+        "nan".to_string()
+    } else if value.is_infinite() && value.is_sign_positive() {
+        "inf".to_string()
+    } else if value.is_infinite() {
+        "-inf".to_string()
+    } else {
+        // the shortest representation that round-trips
+        format!("{:e}", value)
+    }
+}
+
+/// An expression that was compiled to WebAssembly and instantiated in a sandbox
+pub struct WasmExpression {
+    instance: Instance,
+    function: wasmer::Function,
+    arity: usize,
+}
+
+impl WasmExpression {
+    pub fn new(ast: &Ast) -> Result<Self, WasmError> {
+        let store = Store::default();
+        let module = Module::new(&store, ast.wat()?).map_err(WasmError::Compile)?;
+
+        let mut host_functions = Exports::new();
+        for name in ast.imports() {
+            let builtin = match ast.functions.get(&name) {
+                Some(Function::Builtin(builtin)) => builtin,
+                _ => continue,
+            };
+            if builtin_instruction(builtin.name()).is_some() {
+                continue;
+            }
+
+            let signature = FunctionType::new(vec![Type::F64; builtin.arity()], vec![Type::F64]);
+            let function = wasmer::Function::new(&store, signature, move |args| {
+                let args = args.iter().map(Value::unwrap_f64).collect::<Vec<_>>();
+                Ok(vec![Value::F64(builtin.evaluate(&args))])
+            });
+
+            host_functions.insert(builtin.name(), function);
+        }
+
+        let mut imports = ImportObject::new();
+        imports.register(HOST_MODULE, host_functions);
+
+        let instance = Instance::new(&module, &imports)
+            .map_err(|error| WasmError::Instantiation(Box::new(error)))?;
+        let function = instance
+            .exports
+            .get_function(ast.name())
+            .expect("the expression is exported under its name")
+            .clone();
+
+        Ok(Self {
+            instance,
+            function,
+            arity: ast.parameters().len(),
+        })
+    }
+
+    /// The wasmer instance, which exports the expression under the name of the `Ast`
+    pub fn instance(&self) -> &Instance {
+        &self.instance
+    }
+
+    /// Number of parameters of the expression
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Calls the expression with `parameters` in the order of the declared parameters
+    pub fn call(&self, parameters: &[f64]) -> Result<f64, EvalError> {
+        if parameters.len() != self.arity {
+            return Err(EvalError::WrongParameterCount {
+                expected: self.arity,
+                actual: parameters.len(),
+            });
+        }
+
+        let parameters = parameters
+            .iter()
+            .copied()
+            .map(Value::F64)
+            .collect::<Vec<_>>();
+
+        let result = self
+            .function
+            .call(&parameters)
+            .map_err(|error| EvalError::Trap {
+                message: error.message(),
+            })?;
+
+        Ok(result[0].unwrap_f64())
+    }
+}
+
+#[derive(Debug)]
+pub enum WasmError {
+    /// The expression calls a function with a Rust body, see [`UserFunction::rust`](crate::UserFunction::rust)
+    RustFunction {
+        name: String,
+    },
+    Compile(wasmer::CompileError),
+    Instantiation(Box<wasmer::InstantiationError>),
+}
+
+impl fmt::Display for WasmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RustFunction { name } => write!(
+                f,
+                "function `{}` has a Rust body and cannot be compiled to wasm",
+                name
+            ),
+            Self::Compile(error) => write!(f, "cannot compile wasm module: {}", error),
+            Self::Instantiation(error) => write!(f, "cannot instantiate wasm module: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for WasmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::RustFunction { .. } => None,
+            Self::Compile(error) => Some(error),
+            Self::Instantiation(error) => Some(error.as_ref()),
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use math_expr::{
+    Ast, AstBuilder, BytecodeExpression, CompiledExpression, DataType, FunctionRegistry,
+    JitExpression, OverflowMode, UserFunction, WasmExpression,
+};
+
+fn parameters(names: &[&str]) -> Vec<String> {
+    names.iter().map(ToString::to_string).collect()
+}
+
+/// Expressions that every backend must evaluate like [`Ast::evaluate`]
+const CORPUS: &[&str] = &[
+    // operators and named constants
+    "a + b * c - a / b",
+    "-a ** 2 + a // b - a % b",
+    "(a - b) / (a + b) * pi",
+    // builtins, some are host calls in wasm and calls through the trampoline of the JIT
+    "sqrt(a) + abs(b) + floor(c) + ceil(a) + round(b)",
+    "min(a, b) + max(b, c) + clamp(a, b, c)",
+    "sin(a) + cos(b) + tan(c) + atan2(a, b) + hypot(b, c)",
+    "exp(a) + ln(b) + log10(c) + log(a, b) + pow(a, c) + fmod(b, c)",
+    "sign(a) * tau() + e() + coalesce(a, b) + isnodata(c)",
+    // branches, whose conditions differ between elements
+    "if a > b { a } else { b }",
+    "if a < 0 { -1 } else if a == 0 { 0 } else if a < b && b < c { 1 } else { 2 }",
+    "if a > b xor !(b > c) { atan2(a, c) } else if true || a > 0 { hypot(a, b) } else { 0 }",
+    "if 0 <= a < b * 2 <= c { a } else { c }",
+    // nested branches and a branch that only some elements need
+    "if a > 0 { if b > 0 { a * b } else { sqrt(a) } } else { if c > 0 { c } else { nan } }",
+    // let bindings, shadowing and block scopes
+    "let x = a + b; let y = x * c; let x = y - a; x + y",
+    "let x = a * 2; let y = if x > b { let z = x - b; z * z } else { let z = b; -z }; y + x",
+    // user functions, also calling each other, with branches and bindings
+    "ndvi(a, b) + ndvi(b, c)",
+    "positivendvi(a, b) * scaled(c, 2)",
+    "if ndvi(a, b) > 0 { positivendvi(c, a) } else { scaled(ndvi(b, a), c) }",
+];
+
+/// Values of `a`, `b` and `c` for every element, including `NaN`, infinities and signed zeros
+fn samples() -> Vec<[f64; 3]> {
+    let values = [
+        f64::NAN,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        0.,
+        -0.,
+        0.5,
+        -2.5,
+        3.,
+        1e300,
+    ];
+
+    let mut samples = Vec::new();
+    for &a in &values {
+        for &b in &values {
+            for &c in &values {
+                samples.push([a, b, c]);
+            }
+        }
+    }
+    samples
+}
+
+fn functions() -> FunctionRegistry {
+    let mut functions = FunctionRegistry::new();
+    functions
+        .register(UserFunction::expression(
+            Ast::new(
+                "ndvi".to_string(),
+                &parameters(&["nir", "red"]),
+                "(nir - red) / (nir + red)",
+            )
+            .unwrap(),
+        ))
+        .unwrap();
+    functions
+        .register(UserFunction::expression(
+            Ast::builder("positivendvi".to_string(), &parameters(&["nir", "red"]))
+                .functions(functions.clone())
+                .build("let value = ndvi(nir, red); if value > 0 { value } else { 0 }")
+                .unwrap(),
+        ))
+        .unwrap();
+    functions
+        .register(UserFunction::expression(
+            Ast::new(
+                "scaled".to_string(),
+                &parameters(&["x", "factor"]),
+                "let y = x * factor; max(y, -y) + pow(x, 2)",
+            )
+            .unwrap(),
+        ))
+        .unwrap();
+    functions
+}
+
+fn same(expected: f64, actual: f64) -> bool {
+    expected.to_bits() == actual.to_bits() || (expected.is_nan() && actual.is_nan())
+}
+
+/// Evaluates a single element with a backend
+type Call<'a> = &'a dyn Fn(&[f64]) -> f64;
+
+fn check(ast: &Ast, source: &str) {
+    let samples = samples();
+    let expected = samples
+        .iter()
+        .map(|parameters| ast.evaluate(parameters).unwrap())
+        .collect::<Vec<_>>();
+
+    let compiled = CompiledExpression::new(ast).unwrap();
+    let wasm = WasmExpression::new(ast).unwrap();
+    let jit = JitExpression::new(ast).unwrap();
+    let vm = BytecodeExpression::new(ast).unwrap();
+
+    let calls: [(&str, Call); 4] = [
+        ("compiled", &|parameters| compiled.call(parameters).unwrap()),
+        ("wasm", &|parameters| wasm.call(parameters).unwrap()),
+        ("jit", &|parameters| jit.call(parameters).unwrap()),
+        ("vm", &|parameters| vm.call(parameters).unwrap()),
+    ];
+    for (backend, call) in calls {
+        for (parameters, &expected) in samples.iter().zip(&expected) {
+            let actual = call(parameters);
+            assert!(
+                same(expected, actual),
+                "{} evaluates `{}` with {:?} to {} instead of {}",
+                backend,
+                source,
+                parameters,
+                actual,
+                expected
+            );
+        }
+    }
+
+    // the lanes of a block take different branches
+    let inputs = (0..3)
+        .map(|i| samples.iter().map(|sample| sample[i]).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let inputs = inputs.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    let mut output = vec![0.; samples.len()];
+    vm.evaluate_slices(&inputs, &mut output).unwrap();
+
+    for ((parameters, &expected), actual) in samples.iter().zip(&expected).zip(output) {
+        assert!(
+            same(expected, actual),
+            "vm evaluates `{}` in a block with {:?} to {} instead of {}",
+            source,
+            parameters,
+            actual,
+            expected
+        );
+    }
+}
+
+fn builder() -> AstBuilder {
+    Ast::builder("expression".to_string(), &parameters(&["a", "b", "c"])).functions(functions())
+}
+
+#[test]
+fn backends_match_the_interpreter() {
+    for source in CORPUS {
+        check(&builder().build(source).unwrap(), source);
+    }
+}
+
+#[test]
+fn optimized_expressions_match_the_interpreter() {
+    for source in CORPUS {
+        let (optimized, _) = builder().build(source).unwrap().optimize();
+        check(&optimized, source);
+    }
+}
+
+#[test]
+fn data_types_and_nodata_match_the_interpreter() {
+    for source in CORPUS {
+        let ast = builder()
+            .parameter_type("a", DataType::I16)
+            .parameter_nodata("b", 3.)
+            .parameter_mask("c", "a")
+            .output_type(DataType::F32)
+            .output_nodata(-1.)
+            .build(source)
+            .unwrap();
+        check(&ast, source);
+    }
+}
+
+#[test]
+fn integer_arithmetic_matches_the_interpreter() {
+    for source in [
+        "a + b * 3 - a // b + a % b",
+        "(a & b) | (a ^ 7) << 2 >> 1",
+        "if a > b { a * 65536 * 65536 } else { -b }",
+    ] {
+        for overflow in [OverflowMode::Wrapping, OverflowMode::Checked] {
+            let ast = builder()
+                .parameter_type("a", DataType::I16)
+                .parameter_type("b", DataType::U8)
+                .integer_arithmetic(overflow)
+                .build(source)
+                .unwrap();
+            check(&ast, source);
+        }
+    }
+}
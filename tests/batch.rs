@@ -0,0 +1,136 @@
+use math_expr::{
+    Ast, BatchEvaluation, BytecodeExpression, CompiledExpression, EvalError, JitExpression,
+    WasmExpression,
+};
+
+fn parameters(names: &[&str]) -> Vec<String> {
+    names.iter().map(ToString::to_string).collect()
+}
+
+fn build() -> Ast {
+    Ast::new(
+        "expression".to_string(),
+        &parameters(&["a", "b", "c"]),
+        "let x = a - b; if x > 0 { x * c } else { max(a, b) / (c + 1) }",
+    )
+    .unwrap()
+}
+
+/// Every backend of the crate, the `Ast` itself is the interpreter
+fn backends(ast: &Ast) -> Vec<(&'static str, Box<dyn BatchEvaluation + '_>)> {
+    vec![
+        ("interpreter", Box::new(ast.clone())),
+        ("bytecode", Box::new(BytecodeExpression::new(ast).unwrap())),
+        ("jit", Box::new(JitExpression::new(ast).unwrap())),
+        ("wasm", Box::new(WasmExpression::new(ast).unwrap())),
+        ("compiled", Box::new(CompiledExpression::new(ast).unwrap())),
+    ]
+}
+
+/// Inputs of `length` elements, which include `NaN` and infinities
+fn inputs(length: usize) -> Vec<Vec<f64>> {
+    let special = [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 0., -0.];
+
+    (0..3)
+        .map(|parameter| {
+            (0..length)
+                .map(|i| match (i * 7 + parameter * 3) % 11 {
+                    n if n < special.len() => special[n],
+                    n => (n as f64 - 8.) * (parameter as f64 + 0.5),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn same(expected: f64, actual: f64) -> bool {
+    expected.to_bits() == actual.to_bits() || (expected.is_nan() && actual.is_nan())
+}
+
+#[test]
+fn chunks_match_the_interpreter() {
+    let ast = build();
+
+    for (name, backend) in backends(&ast) {
+        assert_eq!(backend.arity(), 3);
+
+        for length in [1, 7, 100, 1000] {
+            let inputs = inputs(length);
+            let inputs = inputs.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+            // the chunk sizes do not divide the lengths, except for a single element
+            for chunk_size in [1, 3, 64, 4096] {
+                let mut output = vec![0.; length];
+                backend
+                    .evaluate_batch_with_chunk_size(&inputs, &mut output, chunk_size)
+                    .unwrap();
+
+                for (i, actual) in output.into_iter().enumerate() {
+                    let parameters = [inputs[0][i], inputs[1][i], inputs[2][i]];
+                    let expected = ast.evaluate(&parameters).unwrap();
+                    assert!(
+                        same(expected, actual),
+                        "{} with {:?} in chunks of {}: {} != {}",
+                        name,
+                        parameters,
+                        chunk_size,
+                        expected,
+                        actual
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn empty_inputs() {
+    let ast = build();
+
+    for (name, backend) in backends(&ast) {
+        let mut output = [];
+        assert_eq!(
+            backend.evaluate_batch(&[&[], &[], &[]], &mut output),
+            Ok(()),
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn wrong_arity() {
+    let ast = build();
+
+    for (name, backend) in backends(&ast) {
+        let mut output = [0.; 2];
+        assert_eq!(
+            backend.evaluate_batch(&[&[1., 2.], &[3., 4.]], &mut output),
+            Err(EvalError::WrongParameterCount {
+                expected: 3,
+                actual: 2
+            }),
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn wrong_input_length() {
+    let ast = build();
+
+    for (name, backend) in backends(&ast) {
+        let mut output = [0.; 2];
+        assert_eq!(
+            backend.evaluate_batch(&[&[1., 2.], &[3.], &[5., 6.]], &mut output),
+            Err(EvalError::WrongInputLength {
+                expected: 2,
+                actual: 1
+            }),
+            "{}",
+            name
+        );
+        assert_eq!(output, [0.; 2], "{}", name);
+    }
+}
@@ -0,0 +1,174 @@
+use math_expr::{Ast, AstNodeKind, BooleanExpression, BooleanExpressionKind, BooleanOperator};
+
+/// Parses `if <condition> { 1 } else { 0 }` and returns the condition
+fn condition(condition: &str) -> BooleanExpression {
+    let ast = Ast::new(
+        "expression".to_string(),
+        &[],
+        &format!("if {} {{ 1 }} else {{ 0 }}", condition),
+    )
+    .unwrap();
+
+    let branch = match ast.root().kind() {
+        AstNodeKind::AssignmentsAndExpression { expression, .. } => expression,
+        kind => panic!("unexpected root {:?}", kind),
+    };
+
+    match branch.kind() {
+        AstNodeKind::Branch {
+            condition_branches, ..
+        } => condition_branches[0].condition().clone(),
+        kind => panic!("unexpected expression {:?}", kind),
+    }
+}
+
+/// Renders the tree as an s-expression, e.g. `(or true (and false true))`
+fn tree(expression: &BooleanExpression) -> String {
+    match expression.kind() {
+        BooleanExpressionKind::Constant(b) => b.to_string(),
+        BooleanExpressionKind::Not(operand) => format!("(not {})", tree(operand)),
+        BooleanExpressionKind::Operation { left, op, right } => {
+            let op = match op {
+                BooleanOperator::And => "and",
+                BooleanOperator::Or => "or",
+                BooleanOperator::Xor => "xor",
+            };
+            format!("({} {} {})", op, tree(left), tree(right))
+        }
+        BooleanExpressionKind::Comparison { .. } => "comparison".to_string(),
+    }
+}
+
+fn evaluate(expression: &BooleanExpression) -> bool {
+    match expression.kind() {
+        BooleanExpressionKind::Constant(b) => *b,
+        BooleanExpressionKind::Not(operand) => !evaluate(operand),
+        BooleanExpressionKind::Operation { left, op, right } => match op {
+            BooleanOperator::And => evaluate(left) && evaluate(right),
+            BooleanOperator::Or => evaluate(left) || evaluate(right),
+            BooleanOperator::Xor => evaluate(left) ^ evaluate(right),
+        },
+        BooleanExpressionKind::Comparison { .. } => panic!("only constants are supported"),
+    }
+}
+
+#[test]
+fn and_binds_stronger_than_or() {
+    assert_eq!(
+        tree(&condition("true || false && true")),
+        "(or true (and false true))"
+    );
+    assert_eq!(
+        tree(&condition("true && false || true")),
+        "(or (and true false) true)"
+    );
+    assert_eq!(
+        tree(&condition("true || false && true || false")),
+        "(or (or true (and false true)) false)"
+    );
+}
+
+#[test]
+fn xor_binds_between_or_and_and() {
+    assert_eq!(
+        tree(&condition("true || false xor true && false")),
+        "(or true (xor false (and true false)))"
+    );
+    assert_eq!(
+        tree(&condition("true && false xor true || false")),
+        "(or (xor (and true false) true) false)"
+    );
+}
+
+#[test]
+fn operators_are_left_associative() {
+    assert_eq!(
+        tree(&condition("true && false && true")),
+        "(and (and true false) true)"
+    );
+    assert_eq!(
+        tree(&condition("true || false || true")),
+        "(or (or true false) true)"
+    );
+    assert_eq!(
+        tree(&condition("true xor false xor true")),
+        "(xor (xor true false) true)"
+    );
+}
+
+#[test]
+fn parentheses_override_precedence() {
+    assert_eq!(
+        tree(&condition("(true || false) && true")),
+        "(and (or true false) true)"
+    );
+    assert_eq!(
+        tree(&condition("true && (false || true)")),
+        "(and true (or false true))"
+    );
+}
+
+#[test]
+fn negation_binds_strongest() {
+    assert_eq!(
+        tree(&condition("!true && false || !false")),
+        "(or (and (not true) false) (not false))"
+    );
+    assert_eq!(
+        tree(&condition("!(true || false)")),
+        "(not (or true false))"
+    );
+}
+
+#[test]
+fn mixed_expressions_evaluate_like_rust() {
+    type Case = (&'static str, fn(bool, bool, bool) -> bool);
+
+    let cases: [Case; 8] = [
+        ("A || B && C", |a, b, c| a || b && c),
+        ("A && B || C", |a, b, c| a && b || c),
+        ("(A || B) && C", |a, b, c| (a || b) && c),
+        ("A && B || !B && C", |a, b, c| a && b || !b && c),
+        ("A xor B && C", |a, b, c| a ^ (b && c)),
+        ("A || B xor C", |a, b, c| a || (b ^ c)),
+        ("!A || B && !C", |a, b, c| !a || b && !c),
+        ("A xor !B || C && B", |a, b, c| (a ^ !b) || c && b),
+    ];
+
+    for (pattern, expected) in cases {
+        for i in 0..8 {
+            let (a, b, c) = (i & 1 != 0, i & 2 != 0, i & 4 != 0);
+
+            let source = pattern
+                .replace('A', &a.to_string())
+                .replace('B', &b.to_string())
+                .replace('C', &c.to_string());
+
+            assert_eq!(
+                evaluate(&condition(&source)),
+                expected(a, b, c),
+                "{}",
+                source
+            );
+        }
+    }
+}
+
+#[test]
+fn xor_is_a_whole_word() {
+    let parameters = ["a", "xorval", "xor2"].map(ToString::to_string);
+    let build = |condition: &str| {
+        Ast::new(
+            "expression".to_string(),
+            &parameters,
+            &format!("if {} {{ 1 }} else {{ 0 }}", condition),
+        )
+    };
+
+    let ast = build("xorval > 0 xor xor2 > 0").unwrap();
+    assert_eq!(ast.evaluate(&[0., 1., 0.]).unwrap(), 1.);
+    assert_eq!(ast.evaluate(&[0., 1., 1.]).unwrap(), 0.);
+
+    // not `a > 0 xor 2 > 0`
+    assert!(build("a > 0 xor2 > 0").is_err());
+}
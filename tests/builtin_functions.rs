@@ -0,0 +1,294 @@
+use math_expr::{
+    builtin_functions, Ast, AstBuilder, AstNodeKind, CompiledExpression, DataType, ExpressionError,
+    NamedConstant, OverflowMode,
+};
+use quote::ToTokens;
+
+fn parse(expression: &str) -> Result<Ast, ExpressionError> {
+    let parameters = ["a", "b", "c"].map(ToString::to_string);
+    Ast::new("functions".to_string(), &parameters, expression)
+}
+
+/// A call of `name` with `count` arguments
+fn call(name: &str, count: usize) -> String {
+    let args = ["a", "b", "c", "a"][..count].join(", ");
+    format!("{}({})", name, args)
+}
+
+#[test]
+fn builtins_are_imported() {
+    for function in builtin_functions() {
+        let ast = parse(&call(function.name(), function.arity())).unwrap();
+
+        let code = ast.to_token_stream().to_string();
+        assert!(
+            code.contains(&format!("import_{} ", function.name())),
+            "`{}` is not imported",
+            function.name()
+        );
+    }
+}
+
+#[test]
+fn wrong_argument_counts() {
+    for function in builtin_functions() {
+        let mut counts = vec![function.arity() + 1];
+        if function.arity() > 1 {
+            counts.push(function.arity() - 1);
+        }
+
+        for count in counts {
+            let error = parse(&call(function.name(), count)).unwrap_err();
+            assert!(
+                matches!(
+                    &error,
+                    ExpressionError::WrongArgumentCount { name, expected, actual, .. }
+                        if name == function.name() && *expected == function.arity() && *actual == count
+                ),
+                "{:?}",
+                error
+            );
+        }
+    }
+}
+
+#[test]
+fn constants_can_be_called() {
+    for (source, constant) in [
+        ("pi()", NamedConstant::Pi),
+        ("e()", NamedConstant::E),
+        ("tau()", NamedConstant::Tau),
+        ("nodata()", NamedConstant::NoData),
+    ] {
+        let ast = parse(source).unwrap();
+        let expression = match ast.root().kind() {
+            AstNodeKind::AssignmentsAndExpression { expression, .. } => expression.kind(),
+            kind => kind,
+        };
+        assert!(
+            matches!(expression, AstNodeKind::NamedConstant(c) if *c == constant),
+            "{:?}",
+            expression
+        );
+    }
+
+    let error = parse("pi(a)").unwrap_err();
+    assert!(
+        matches!(
+            &error,
+            ExpressionError::WrongArgumentCount { name, expected: 0, actual: 1, .. } if name == "pi"
+        ),
+        "{:?}",
+        error
+    );
+}
+
+/// The functions that are reached with a configuration of the `Ast`, each with an expression that
+/// calls it, which selects by the parameter `f`
+struct Table {
+    configure: fn(AstBuilder) -> AstBuilder,
+    /// Assignments before the selection
+    prelude: &'static str,
+    cases: &'static [(&'static str, &'static str)],
+    samples: &'static [f64],
+}
+
+const FLOATS: &[f64] = &[
+    f64::NAN,
+    f64::INFINITY,
+    f64::NEG_INFINITY,
+    0.,
+    -0.,
+    0.5,
+    -2.5,
+    3.,
+    1e300,
+];
+
+/// `b` and `c` are `i16`, and `x` is `NaN` where `c` is zero
+const INTEGERS: &[f64] = &[-32768., -7., -1., 0., 2., 7., 31., 33., 32767.];
+
+const BUILTINS: Table = Table {
+    configure: |builder| builder,
+    prelude: "",
+    cases: &[
+        ("min", "min(a, b)"),
+        ("max", "max(a, b)"),
+        ("abs", "abs(a)"),
+        ("sqrt", "sqrt(a)"),
+        ("exp", "exp(a)"),
+        ("ln", "ln(a)"),
+        ("log10", "log10(a)"),
+        ("log", "log(a, b)"),
+        ("pow", "pow(a, b)"),
+        ("sin", "sin(a)"),
+        ("cos", "cos(a)"),
+        ("tan", "tan(a)"),
+        ("asin", "asin(a)"),
+        ("acos", "acos(a)"),
+        ("atan", "atan(a)"),
+        ("atan2", "atan2(a, b)"),
+        ("floor", "floor(a)"),
+        ("ceil", "ceil(a)"),
+        ("round", "round(a)"),
+        ("clamp", "clamp(a, b, c)"),
+        ("sign", "sign(a)"),
+        ("hypot", "hypot(a, b)"),
+        ("fmod", "fmod(a, b)"),
+        ("isnodata", "isnodata(a)"),
+        ("coalesce", "coalesce(a, b)"),
+        ("remainder_float", "a % b"),
+        ("floordivide_float", "a // b"),
+    ],
+    samples: FLOATS,
+};
+
+const INTEGER_CASES: &[(&str, &str)] = &[
+    ("add", "x + b"),
+    ("subtract", "x - b"),
+    ("multiply", "x * b * 65537"),
+    ("negate", "-x"),
+    ("floordivide", "x // b"),
+    ("remainder", "x % b"),
+    ("bitand", "x & b"),
+    ("bitor", "x | b"),
+    ("bitxor", "x ^ b"),
+    ("shiftleft", "x << b"),
+    ("shiftright", "x >> b"),
+];
+
+fn integers(builder: AstBuilder) -> AstBuilder {
+    builder
+        .parameter_type("b", DataType::I16)
+        .parameter_type("c", DataType::I16)
+}
+
+const WRAPPING: Table = Table {
+    configure: |builder| integers(builder).integer_arithmetic(OverflowMode::Wrapping),
+    prelude: "let x = b * 65536 // c;",
+    cases: INTEGER_CASES,
+    samples: INTEGERS,
+};
+
+const CHECKED: Table = Table {
+    configure: |builder| integers(builder).integer_arithmetic(OverflowMode::Checked),
+    prelude: "let x = b * 65536 // c;",
+    cases: INTEGER_CASES,
+    samples: INTEGERS,
+};
+
+/// `a` is no-data for `-9999` and `b` where `c` is zero or no-data
+const NODATA: Table = Table {
+    configure: |builder| {
+        builder
+            .parameter_nodata("a", -9999.)
+            .parameter_mask("b", "c")
+    },
+    prelude: "",
+    cases: &[
+        ("nodata_value", "a"),
+        ("nodata_mask", "b"),
+        ("min_nodata", "min(a, b)"),
+        ("max_nodata", "max(a, b)"),
+        ("clamp_nodata", "clamp(a, b, 2)"),
+        ("hypot_nodata", "hypot(a, b)"),
+        ("pow_nodata", "pow(a, b)"),
+    ],
+    samples: &[f64::NAN, f64::INFINITY, -9999., 0., 1., -2.5],
+};
+
+/// The names of the functions that the generated code of `table` imports
+fn function_names(table: &Table, overflow: Option<OverflowMode>) -> Vec<String> {
+    table
+        .cases
+        .iter()
+        .map(|(name, _)| match overflow {
+            Some(OverflowMode::Wrapping) if !cannot_overflow(name) => format!("{}_wrapping", name),
+            Some(OverflowMode::Checked) if !cannot_overflow(name) => format!("{}_checked", name),
+            Some(_) => format!("{}_integer", name),
+            None => name.to_string(),
+        })
+        .collect()
+}
+
+fn cannot_overflow(name: &str) -> bool {
+    matches!(name, "remainder" | "bitand" | "bitor" | "bitxor")
+}
+
+fn build(table: &Table) -> Ast {
+    let selection = table
+        .cases
+        .iter()
+        .enumerate()
+        .map(|(i, (_, expression))| format!("if f == {} {{ {} }}", i, expression))
+        .collect::<Vec<_>>()
+        .join(" else ");
+    let expression = format!("{} {} else {{ 0 }}", table.prelude, selection);
+
+    let parameters = ["f", "a", "b", "c"].map(ToString::to_string);
+    let builder = Ast::builder("functions".to_string(), &parameters);
+
+    (table.configure)(builder).build(&expression).unwrap()
+}
+
+/// Compares the interpreter, which calls the `evaluate` of the functions, with the Rust bodies
+fn check(table: &Table, overflow: Option<OverflowMode>) {
+    let ast = build(table);
+    let compiled = CompiledExpression::new(&ast).unwrap();
+
+    let code = ast.to_token_stream().to_string();
+    for name in function_names(table, overflow) {
+        assert!(
+            code.contains(&format!("import_{} ", name)),
+            "`{}` is not called",
+            name
+        );
+    }
+
+    for (i, (name, _)) in table.cases.iter().enumerate() {
+        for &a in table.samples {
+            for &b in table.samples {
+                for c in [1., 0., 2.] {
+                    let parameters = [i as f64, a, b, c];
+                    let expected = ast.evaluate(&parameters).unwrap();
+                    let actual = compiled.call(&parameters).unwrap();
+
+                    assert!(
+                        expected.to_bits() == actual.to_bits()
+                            || (expected.is_nan() && actual.is_nan()),
+                        "{} with {:?}: {} != {}",
+                        name,
+                        parameters,
+                        expected,
+                        actual
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn builtins_match_their_rust_bodies() {
+    let tested = function_names(&BUILTINS, None);
+    for function in builtin_functions() {
+        assert!(
+            tested.iter().any(|name| name == function.name()),
+            "`{}` is not tested",
+            function.name()
+        );
+    }
+
+    check(&BUILTINS, None);
+}
+
+#[test]
+fn integer_operators_match_their_rust_bodies() {
+    check(&WRAPPING, Some(OverflowMode::Wrapping));
+    check(&CHECKED, Some(OverflowMode::Checked));
+}
+
+#[test]
+fn nodata_functions_match_their_rust_bodies() {
+    check(&NODATA, None);
+}
@@ -0,0 +1,218 @@
+#![cfg(unix)]
+
+use std::fs::{self, File};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use math_expr::{Ast, CompilationCache, CompileError, Compiler};
+
+fn build(expression: &str) -> Ast {
+    Ast::new(
+        "expression".to_string(),
+        &["a".to_string(), "b".to_string()],
+        expression,
+    )
+    .unwrap()
+}
+
+/// A `rustc` that reports the version of the real one, so it finds its libraries in the cache,
+/// but fails to compile anything
+fn failing_rustc(directory: &Path) -> PathBuf {
+    let path = directory.join("rustc");
+    fs::write(
+        &path,
+        "#!/bin/sh\nif [ \"$1\" = \"-vV\" ]; then exec \"${RUSTC:-rustc}\" -vV; fi\nexit 1\n",
+    )
+    .unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+/// A `rustc` that appends a line to `log` whenever it is asked for its version
+fn counting_rustc(directory: &Path, log: &Path) -> PathBuf {
+    let path = directory.join("counting-rustc");
+    fs::write(
+        &path,
+        format!(
+            "#!/bin/sh\nif [ \"$1\" = \"-vV\" ]; then echo >> '{}'; fi\nexec \"${{RUSTC:-rustc}}\" \"$@\"\n",
+            log.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+/// The cached libraries, without the temporary directories of compilations
+fn libraries(cache: &CompilationCache) -> Vec<PathBuf> {
+    let mut libraries = fs::read_dir(cache.directory())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.to_string_lossy()
+                .ends_with(std::env::consts::DLL_SUFFIX)
+        })
+        .collect::<Vec<_>>();
+    libraries.sort();
+    libraries
+}
+
+fn set_age(path: &Path, seconds: u64) {
+    File::open(path)
+        .unwrap()
+        .set_modified(SystemTime::now() - Duration::from_secs(seconds))
+        .unwrap();
+}
+
+#[test]
+fn hits_skip_rustc() {
+    let directory = tempfile::tempdir().unwrap();
+    let cache = CompilationCache::new(directory.path().join("cache"));
+    let rustc = failing_rustc(directory.path());
+    let ast = build("a * b + 1");
+
+    let compiled = Compiler::new().cache(cache.clone()).compile(&ast).unwrap();
+    assert_eq!(compiled.call(&[2., 3.]).unwrap(), 7.);
+    assert_eq!(libraries(&cache).len(), 1);
+
+    let cached = Compiler::new()
+        .rustc(&rustc)
+        .cache(cache.clone())
+        .compile(&ast)
+        .unwrap();
+    assert_eq!(cached.call(&[2., 3.]).unwrap(), 7.);
+    assert_eq!(libraries(&cache).len(), 1);
+
+    // without the cache the same expression needs rustc
+    assert!(matches!(
+        Compiler::new().rustc(&rustc).compile(&ast),
+        Err(CompileError::Rustc { .. })
+    ));
+}
+
+#[test]
+fn the_version_of_rustc_is_queried_once() {
+    let directory = tempfile::tempdir().unwrap();
+    let cache = CompilationCache::new(directory.path().join("cache"));
+    let log = directory.path().join("versions");
+    let compiler = Compiler::new()
+        .rustc(counting_rustc(directory.path(), &log))
+        .cache(cache);
+
+    compiler.compile(&build("a * b + 1")).unwrap();
+    compiler.compile(&build("a * b + 1")).unwrap();
+    compiler.compile(&build("a * b + 2")).unwrap();
+
+    assert_eq!(fs::read_to_string(&log).unwrap().lines().count(), 1);
+}
+
+#[test]
+fn flags_and_sources_are_part_of_the_key() {
+    let directory = tempfile::tempdir().unwrap();
+    let cache = CompilationCache::new(directory.path().join("cache"));
+    let rustc = failing_rustc(directory.path());
+
+    Compiler::new()
+        .cache(cache.clone())
+        .compile(&build("a * b + 1"))
+        .unwrap();
+
+    let other_flags = Compiler::new()
+        .rustc(&rustc)
+        .flags(vec!["-C".to_string(), "opt-level=2".to_string()])
+        .cache(cache.clone())
+        .compile(&build("a * b + 1"));
+    assert!(matches!(other_flags, Err(CompileError::Rustc { .. })));
+
+    let other_source = Compiler::new()
+        .rustc(&rustc)
+        .cache(cache.clone())
+        .compile(&build("a * b + 2"));
+    assert!(matches!(other_source, Err(CompileError::Rustc { .. })));
+
+    // the failed compilations leave nothing behind
+    assert_eq!(fs::read_dir(cache.directory()).unwrap().count(), 1);
+}
+
+#[test]
+fn eviction_removes_the_least_recently_used_libraries() {
+    let directory = tempfile::tempdir().unwrap();
+    let cache = CompilationCache::new(directory.path().join("cache"));
+    let compiler = Compiler::new().cache(cache.clone());
+    fs::create_dir(cache.directory()).unwrap();
+
+    let library = |expression: &str| {
+        let before = libraries(&cache);
+        compiler.compile(&build(expression)).unwrap();
+        libraries(&cache)
+            .into_iter()
+            .find(|library| !before.contains(library))
+            .unwrap()
+    };
+    let size = |library: &Path| fs::metadata(library).unwrap().len();
+
+    let first = library("a + 1");
+    let second = library("a + 2");
+    let third = library("a + 3");
+    set_age(&first, 300);
+    set_age(&second, 200);
+    set_age(&third, 100);
+
+    // a hit makes the oldest library the most recently used one
+    compiler.compile(&build("a + 1")).unwrap();
+
+    // measure the next library in another cache, it is identical
+    let other = CompilationCache::new(directory.path().join("other"));
+    Compiler::new()
+        .cache(other.clone())
+        .compile(&build("a + 4"))
+        .unwrap();
+    let fourth_size = size(&libraries(&other)[0]);
+
+    // there is room for all but the second library
+    let max_size = size(&first) + size(&third) + fourth_size + size(&second) / 2;
+    let fourth = Compiler::new()
+        .cache(cache.clone().max_size(max_size))
+        .compile(&build("a + 4"))
+        .unwrap();
+    assert_eq!(fourth.call(&[1., 0.]).unwrap(), 5.);
+
+    let remaining = libraries(&cache);
+    assert_eq!(remaining.len(), 3, "{:?}", remaining);
+    assert!(remaining.contains(&first));
+    assert!(!remaining.contains(&second));
+    assert!(remaining.contains(&third));
+
+    // the library that was just compiled is kept even if it alone exceeds the limit
+    Compiler::new()
+        .cache(cache.clone().max_size(0))
+        .compile(&build("a + 5"))
+        .unwrap();
+    assert_eq!(libraries(&cache).len(), 1);
+}
+
+#[test]
+fn eviction_removes_stale_temporary_directories() {
+    let directory = tempfile::tempdir().unwrap();
+    let cache = CompilationCache::new(directory.path().join("cache"));
+
+    // left behind by a crashed compilation, and one of a compilation that is still running
+    let stale = cache.directory().join(".tmpStale");
+    let running = cache.directory().join(".tmpRunning");
+    for temporary in [&stale, &running] {
+        fs::create_dir_all(temporary).unwrap();
+        fs::write(temporary.join("expression.rs"), "").unwrap();
+    }
+    set_age(&stale, 2 * 60 * 60);
+
+    let compiled = Compiler::new()
+        .cache(cache.clone())
+        .compile(&build("a - b"))
+        .unwrap();
+    assert_eq!(compiled.call(&[3., 1.]).unwrap(), 2.);
+
+    assert!(!stale.exists());
+    assert!(running.exists());
+    assert_eq!(libraries(&cache).len(), 1);
+}
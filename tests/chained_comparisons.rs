@@ -0,0 +1,199 @@
+use math_expr::{
+    Ast, AstNode, AstNodeKind, BooleanComparator, BooleanExpression, BooleanExpressionKind,
+    BooleanOperator,
+};
+
+fn parameters(names: &[&str]) -> Vec<String> {
+    names.iter().map(ToString::to_string).collect()
+}
+
+/// Parses `if <condition> { 1 } else { 0 }` with the parameters `a`, `b` and `c`
+fn branch(condition: &str) -> Ast {
+    Ast::new(
+        "expression".to_string(),
+        &parameters(&["a", "b", "c"]),
+        &format!("if {} {{ 1 }} else {{ 0 }}", condition),
+    )
+    .unwrap()
+}
+
+fn evaluate(condition: &str, parameters: [f64; 3]) -> bool {
+    branch(condition).evaluate(&parameters).unwrap() == 1.
+}
+
+/// Renders an operand, which is a parameter, a variable or a constant
+fn operand(node: &AstNode) -> String {
+    match node.kind() {
+        AstNodeKind::Variable(identifier) => identifier.to_string(),
+        AstNodeKind::Integer(n) => n.to_string(),
+        kind => panic!("unexpected operand {:?}", kind),
+    }
+}
+
+/// Renders the condition as an s-expression, e.g. `(and (< a b) (< b c))`
+fn tree(expression: &BooleanExpression) -> String {
+    match expression.kind() {
+        BooleanExpressionKind::Constant(b) => b.to_string(),
+        BooleanExpressionKind::Not(operand) => format!("(not {})", tree(operand)),
+        BooleanExpressionKind::Operation { left, op, right } => {
+            let op = match op {
+                BooleanOperator::And => "and",
+                BooleanOperator::Or => "or",
+                BooleanOperator::Xor => "xor",
+            };
+            format!("({} {} {})", op, tree(left), tree(right))
+        }
+        BooleanExpressionKind::Comparison { left, op, right } => {
+            let op = match op {
+                BooleanComparator::Equal => "==",
+                BooleanComparator::NotEqual => "!=",
+                BooleanComparator::LessThan => "<",
+                BooleanComparator::LessThanOrEqual => "<=",
+                BooleanComparator::GreaterThan => ">",
+                BooleanComparator::GreaterThanOrEqual => ">=",
+            };
+            format!("({} {} {})", op, operand(left), operand(right))
+        }
+    }
+}
+
+/// The assignments before a branch and its first condition
+fn condition(node: &AstNode) -> (Vec<String>, BooleanExpression) {
+    let mut node = node;
+    let mut assignments = Vec::new();
+
+    loop {
+        match node.kind() {
+            AstNodeKind::AssignmentsAndExpression {
+                assignments: block,
+                expression,
+            } => {
+                assignments.extend(
+                    block
+                        .iter()
+                        .map(|assignment| assignment.identifier().to_string()),
+                );
+                node = expression;
+            }
+            AstNodeKind::Branch {
+                condition_branches, ..
+            } => return (assignments, condition_branches[0].condition().clone()),
+            kind => panic!("unexpected expression {:?}", kind),
+        }
+    }
+}
+
+#[test]
+fn chains_become_conjunctions() {
+    let (assignments, chain) = condition(branch("a < b < c").root());
+    assert!(assignments.is_empty());
+    assert_eq!(tree(&chain), "(and (< a b) (< b c))");
+
+    let (_, chain) = condition(branch("0 <= a < b == c").root());
+    assert_eq!(tree(&chain), "(and (and (<= 0 a) (< a b)) (== b c))");
+
+    for (parameters, expected) in [
+        ([1., 2., 3.], true),
+        ([1., 3., 2.], false),
+        ([2., 1., 3.], false),
+        ([1., 1., 3.], false),
+    ] {
+        assert_eq!(
+            evaluate("a < b < c", parameters),
+            expected,
+            "{:?}",
+            parameters
+        );
+    }
+}
+
+#[test]
+fn middle_operands_are_evaluated_once() {
+    let (assignments, chain) = condition(branch("a < b * 2 <= c").root());
+    assert_eq!(assignments, ["comparand_0"]);
+    assert_eq!(tree(&chain), "(and (< a comparand_0) (<= comparand_0 c))");
+
+    // only the middle operands
+    let (assignments, _) = condition(branch("a * 2 < b < c * 2").root());
+    assert!(assignments.is_empty());
+
+    assert!(evaluate("a < b * 2 <= c", [1., 1., 2.]));
+    assert!(!evaluate("a < b * 2 <= c", [1., 1., 1.]));
+}
+
+#[test]
+fn else_if_operands_are_evaluated_after_the_conditions_before() {
+    let ast = Ast::new(
+        "expression".to_string(),
+        &parameters(&["a", "b", "c"]),
+        "if a > 5 { 1 } else if 0 < a + b < 1 { 2 } else { 3 }",
+    )
+    .unwrap();
+
+    let (assignments, first) = condition(ast.root());
+    assert!(assignments.is_empty());
+    assert_eq!(tree(&first), "(> a 5)");
+
+    let else_branch = match ast.root().kind() {
+        AstNodeKind::AssignmentsAndExpression { expression, .. } => match expression.kind() {
+            AstNodeKind::Branch {
+                condition_branches,
+                else_branch,
+            } => {
+                assert_eq!(condition_branches.len(), 1);
+                else_branch
+            }
+            kind => panic!("expected a branch, got {:?}", kind),
+        },
+        kind => panic!("unexpected root {:?}", kind),
+    };
+    let (assignments, second) = condition(else_branch);
+    assert_eq!(assignments, ["comparand_0"]);
+    assert_eq!(tree(&second), "(and (< 0 comparand_0) (< comparand_0 1))");
+
+    for (parameters, expected) in [
+        ([6., 0., 0.], 1.),
+        ([0.25, 0.25, 0.], 2.),
+        ([0.75, 0.75, 0.], 3.),
+    ] {
+        assert_eq!(
+            ast.evaluate(&parameters).unwrap(),
+            expected,
+            "{:?}",
+            parameters
+        );
+    }
+}
+
+#[test]
+fn negation_and_xor() {
+    assert_eq!(tree(&condition(branch("!a < b").root()).1), "(not (< a b))");
+    assert_eq!(
+        tree(&condition(branch("!true xor true").root()).1),
+        "(xor (not true) true)"
+    );
+    assert_eq!(
+        tree(&condition(branch("!(true xor true)").root()).1),
+        "(not (xor true true))"
+    );
+    assert_eq!(
+        tree(&condition(branch("a < b xor b < c").root()).1),
+        "(xor (< a b) (< b c))"
+    );
+
+    for (parameters, expected) in [
+        ([1., 2., 3.], false),
+        ([1., 2., 1.], true),
+        ([2., 1., 3.], true),
+        ([2., 1., 0.], false),
+    ] {
+        assert_eq!(
+            evaluate("a < b xor b < c", parameters),
+            expected,
+            "{:?}",
+            parameters
+        );
+    }
+    assert!(evaluate("!true xor true", [0.; 3]));
+    assert!(!evaluate("!(true xor false)", [0.; 3]));
+}
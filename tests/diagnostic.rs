@@ -0,0 +1,102 @@
+use math_expr::{Ast, Diagnostic, ExpressionError, Span};
+
+fn parameters(names: &[&str]) -> Vec<String> {
+    names.iter().map(ToString::to_string).collect()
+}
+
+fn error(expression: &str) -> ExpressionError {
+    Ast::new(
+        "expression".to_string(),
+        &parameters(&["a", "b"]),
+        expression,
+    )
+    .unwrap_err()
+}
+
+fn render(expression: &str) -> String {
+    error(expression).diagnostic().render(expression)
+}
+
+#[test]
+fn single_line_with_hint() {
+    assert_eq!(
+        render("mx(a) + b"),
+        "error: unknown function `mx`\n \
+         --> 1:1\n  \
+         |\n\
+         1 | mx(a) + b\n  \
+         | ^^ did you mean `max`?\n"
+    );
+}
+
+#[test]
+fn multi_line_source() {
+    assert_eq!(
+        render("let x = a;\nlet y = x + c;\ny"),
+        "error: unknown variable `c`\n \
+         --> 2:13\n  \
+         |\n\
+         2 | let y = x + c;\n  \
+         |             ^ did you mean `a`?\n"
+    );
+}
+
+#[test]
+fn end_of_input() {
+    let expression = "a +";
+    assert_eq!(error(expression).span(), Span::new(3, 3));
+    assert_eq!(
+        render(expression),
+        "error: expected number, identifier, unary or branch\n \
+         --> 1:4\n  \
+         |\n\
+         1 | a +\n  \
+         |    ^\n"
+    );
+}
+
+#[test]
+fn non_ascii_column() {
+    // `ö` and `ß` take two bytes each, but one column
+    let source = "größe + c";
+    let diagnostic = Diagnostic::new("unknown variable `c`".to_string(), Span::new(10, 11));
+    assert_eq!(
+        diagnostic.render(source),
+        "error: unknown variable `c`\n \
+         --> 1:9\n  \
+         |\n\
+         1 | größe + c\n  \
+         |         ^\n"
+    );
+
+    let diagnostic = Diagnostic::new("unknown variable `größe`".to_string(), Span::new(0, 7));
+    assert!(diagnostic.render(source).ends_with("  | ^^^^^\n"));
+}
+
+fn suggestion(expression: &str) -> Option<String> {
+    match error(expression) {
+        ExpressionError::UnknownVariable { suggestion, .. }
+        | ExpressionError::UnknownFunction { suggestion, .. } => suggestion,
+        error => panic!("expected an unknown name, got {:?}", error),
+    }
+}
+
+#[test]
+fn closest_match() {
+    assert_eq!(suggestion("let value = 1; valu").as_deref(), Some("value"));
+    assert_eq!(suggestion("mx(a)").as_deref(), Some("max"));
+    assert_eq!(
+        suggestion("let longer = 1; lnger").as_deref(),
+        Some("longer")
+    );
+
+    // the closest candidate wins over an earlier one
+    assert_eq!(
+        suggestion("let total = 1; let totals = 2; totalss").as_deref(),
+        Some("totals")
+    );
+
+    // too far away to be a typo
+    assert_eq!(suggestion("let value = 1; other"), None);
+    assert_eq!(suggestion("xyz(a)"), None);
+}
@@ -0,0 +1,174 @@
+use math_expr::{Ast, DataType, EvalError, FunctionRegistry, UserFunction};
+
+fn parameters(names: &[&str]) -> Vec<String> {
+    names.iter().map(ToString::to_string).collect()
+}
+
+fn build(expression: &str) -> Ast {
+    Ast::new(
+        "expression".to_string(),
+        &parameters(&["a", "b", "c"]),
+        expression,
+    )
+    .unwrap()
+}
+
+fn evaluate(expression: &str, parameters: [f64; 3]) -> f64 {
+    build(expression).evaluate(&parameters).unwrap()
+}
+
+#[test]
+fn arithmetic() {
+    assert_eq!(evaluate("a + b * c", [1., 2., 3.]), 7.);
+    assert_eq!(evaluate("(a + b) * c", [1., 2., 3.]), 9.);
+    assert_eq!(evaluate("a - b - c", [10., 2., 3.]), 5.);
+    assert_eq!(evaluate("a / b / c", [12., 2., 3.]), 2.);
+    assert_eq!(evaluate("a ** b ** c", [2., 3., 2.]), 512.);
+    assert_eq!(evaluate("a // b", [7., 2., 0.]), 3.);
+    assert_eq!(evaluate("a // b", [-7., 2., 0.]), -4.);
+    assert_eq!(evaluate("a % b", [-7., 2., 0.]), 1.);
+    assert_eq!(evaluate("-a + 1.5e1", [5., 0., 0.]), 10.);
+
+    assert_eq!(evaluate("a / b", [1., 0., 0.]), f64::INFINITY);
+    assert!(evaluate("a / b", [0., 0., 0.]).is_nan());
+    assert!(evaluate("a + b", [f64::NAN, 1., 0.]).is_nan());
+    assert!(evaluate("a - b", [f64::INFINITY, f64::INFINITY, 0.]).is_nan());
+}
+
+#[test]
+fn builtins_and_constants() {
+    assert_eq!(evaluate("sqrt(a)", [16., 0., 0.]), 4.);
+    assert_eq!(evaluate("min(a, b) + max(a, c)", [1., 2., 3.]), 4.);
+    assert_eq!(evaluate("clamp(a, b, c)", [5., 0., 2.]), 2.);
+    assert_eq!(evaluate("hypot(a, b)", [3., 4., 0.]), 5.);
+    assert_eq!(evaluate("sign(a)", [-0.5, 0., 0.]), -1.);
+    assert_eq!(evaluate("pi", [0., 0., 0.]), std::f64::consts::PI);
+    assert_eq!(
+        evaluate("tau() * a", [2., 0., 0.]),
+        2. * std::f64::consts::TAU
+    );
+    assert_eq!(evaluate("coalesce(a, b)", [f64::NAN, 2., 0.]), 2.);
+    assert_eq!(evaluate("isnodata(a)", [f64::NAN, 0., 0.]), 1.);
+    assert!(evaluate("ln(a)", [-1., 0., 0.]).is_nan());
+}
+
+#[test]
+fn assignments() {
+    assert_eq!(evaluate("let x = a + b; x * x", [1., 2., 0.]), 9.);
+    assert_eq!(
+        evaluate("let x = a; let y = x + b; y * c", [1., 2., 3.]),
+        9.
+    );
+    // later assignments shadow earlier ones
+    assert_eq!(evaluate("let x = a; let x = x * 2; x", [3., 0., 0.]), 6.);
+    // variables of a branch are only visible inside of it
+    assert_eq!(
+        evaluate(
+            "let x = a; let y = if b > 0 { let x = 10; x } else { x }; x + y",
+            [1., 1., 0.]
+        ),
+        11.
+    );
+}
+
+#[test]
+fn branches() {
+    let source = "if a < 0 { -1 } else if a == 0 { 0 } else if a < b && !(c > 5) { 1 } else { 2 }";
+
+    for (parameters, expected) in [
+        ([-3., 0., 0.], -1.),
+        ([0., 0., 0.], 0.),
+        ([1., 2., 0.], 1.),
+        ([1., 2., 6.], 2.),
+        ([3., 2., 0.], 2.),
+        // comparisons with NaN are false
+        ([f64::NAN, 2., 0.], 2.),
+    ] {
+        assert_eq!(evaluate(source, parameters), expected, "{:?}", parameters);
+    }
+
+    assert_eq!(
+        evaluate("if true || a > 0 { 1 } else { 0 }", [-1., 0., 0.]),
+        1.
+    );
+    assert_eq!(evaluate("if 0 <= a < b { 1 } else { 0 }", [1., 2., 0.]), 1.);
+}
+
+#[test]
+fn data_types() {
+    let ast = Ast::builder("expression".to_string(), &parameters(&["a", "b"]))
+        .parameter_type("a", DataType::U8)
+        .output_type(DataType::I16)
+        .build("a * b")
+        .unwrap();
+
+    // the parameters are converted first, `300` saturates to `255`
+    assert_eq!(ast.evaluate(&[300., 0.5]).unwrap(), 127.);
+    assert_eq!(ast.evaluate(&[2.7, -1.]).unwrap(), -2.);
+    assert_eq!(ast.evaluate(&[200., 1000.]).unwrap(), f64::from(i16::MAX));
+}
+
+#[test]
+fn user_functions() {
+    let mut functions = FunctionRegistry::new();
+    functions
+        .register(UserFunction::expression(
+            Ast::new(
+                "ndvi".to_string(),
+                &parameters(&["nir", "red"]),
+                "(nir - red) / (nir + red)",
+            )
+            .unwrap(),
+        ))
+        .unwrap();
+    functions
+        .register(UserFunction::rust(
+            "double".to_string(),
+            &parameters(&["x"]),
+            "x * 2.".to_string(),
+        ))
+        .unwrap();
+
+    let ast = Ast::builder("expression".to_string(), &parameters(&["a", "b"]))
+        .functions(functions.clone())
+        .build("ndvi(a, b) + 1")
+        .unwrap();
+    assert_eq!(ast.evaluate(&[3., 1.]).unwrap(), 1.5);
+
+    let ast = Ast::builder("expression".to_string(), &parameters(&["a"]))
+        .functions(functions)
+        .build("double(a)")
+        .unwrap();
+    assert_eq!(
+        ast.evaluate(&[1.]),
+        Err(EvalError::RustFunction {
+            name: "double".to_string()
+        })
+    );
+}
+
+#[test]
+fn wrong_parameter_count() {
+    let ast = build("a + b + c");
+
+    assert_eq!(
+        ast.evaluate(&[1., 2.]),
+        Err(EvalError::WrongParameterCount {
+            expected: 3,
+            actual: 2
+        })
+    );
+    assert_eq!(
+        ast.evaluate(&[1., 2., 3., 4.]),
+        Err(EvalError::WrongParameterCount {
+            expected: 3,
+            actual: 4
+        })
+    );
+    assert_eq!(
+        Ast::new("expression".to_string(), &[], "1")
+            .unwrap()
+            .evaluate(&[]),
+        Ok(1.)
+    );
+}
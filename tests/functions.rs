@@ -0,0 +1,99 @@
+use math_expr::{Ast, ExpressionError, FunctionRegistry, FunctionRegistryError, UserFunction};
+
+fn parameters(names: &[&str]) -> Vec<String> {
+    names.iter().map(ToString::to_string).collect()
+}
+
+fn register(name: &str, parameter: &str) -> Result<(), FunctionRegistryError> {
+    FunctionRegistry::new().register(UserFunction::rust(
+        name.to_string(),
+        &parameters(&[parameter]),
+        "1.".to_string(),
+    ))
+}
+
+fn invalid_name(name: &str) -> Result<(), FunctionRegistryError> {
+    Err(FunctionRegistryError::InvalidName {
+        name: name.to_string(),
+    })
+}
+
+#[test]
+fn keywords_are_not_function_names() {
+    for keyword in [
+        "if", "else", "let", "true", "TRUE", "False", "xor", "nan", "inf", "fn", "type", "match",
+        "loop", "Self",
+    ] {
+        assert_eq!(register(keyword, "a"), invalid_name(keyword));
+        assert_eq!(register("f", keyword), invalid_name(keyword));
+    }
+
+    for name in ["ndvi", "If", "nan2", "matches", "typed"] {
+        assert_eq!(register(name, "a"), Ok(()), "{}", name);
+    }
+
+    assert_eq!(register("f", "a_b"), invalid_name("a_b"));
+    assert_eq!(register("2f", "a"), invalid_name("2f"));
+}
+
+#[test]
+fn keywords_are_not_parameter_names() {
+    for keyword in ["let", "inf", "fn", "match"] {
+        let error =
+            Ast::new("expression".to_string(), &parameters(&["a", keyword]), "a").unwrap_err();
+
+        assert!(
+            matches!(&error, ExpressionError::InvalidParameter { name, .. } if name == keyword),
+            "{:?}",
+            error
+        );
+    }
+}
+
+#[test]
+fn expression_names_are_identifiers() {
+    for name in ["my-expr", "fn", "let", "2x", ""] {
+        let error = Ast::new(name.to_string(), &parameters(&["a"]), "a").unwrap_err();
+
+        assert!(
+            matches!(&error, ExpressionError::InvalidName { name: invalid, .. } if invalid == name),
+            "{:?}",
+            error
+        );
+    }
+}
+
+#[test]
+fn functions_are_not_named_like_the_expression() {
+    let mut functions = FunctionRegistry::new();
+    functions
+        .register(UserFunction::rust(
+            "ndvi".to_string(),
+            &parameters(&["nir", "red"]),
+            "(nir - red) / (nir + red)".to_string(),
+        ))
+        .unwrap();
+
+    let error = Ast::builder("ndvi".to_string(), &parameters(&["nir", "red"]))
+        .functions(functions.clone())
+        .build("ndvi(nir, red)")
+        .unwrap_err();
+    assert!(
+        matches!(&error, ExpressionError::FunctionNamedLikeExpression { name, .. } if name == "ndvi"),
+        "{:?}",
+        error
+    );
+
+    // the generated code also calls the builtin functions by their names
+    assert!(matches!(
+        Ast::new("sqrt".to_string(), &parameters(&["a"]), "a"),
+        Err(ExpressionError::FunctionNamedLikeExpression { .. })
+    ));
+
+    assert!(
+        Ast::builder("positivendvi".to_string(), &parameters(&["nir", "red"]))
+            .functions(functions)
+            .build("max(ndvi(nir, red), 0)")
+            .is_ok()
+    );
+}
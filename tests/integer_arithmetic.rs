@@ -0,0 +1,102 @@
+use math_expr::{Ast, DataType, ExpressionError, OverflowMode};
+
+fn parameters(names: &[&str]) -> Vec<String> {
+    names.iter().map(ToString::to_string).collect()
+}
+
+fn evaluate(overflow: Option<OverflowMode>, expression: &str) -> f64 {
+    let mut builder = Ast::builder("expression".to_string(), &[]);
+    if let Some(overflow) = overflow {
+        builder = builder.integer_arithmetic(overflow);
+    }
+    builder.build(expression).unwrap().evaluate(&[]).unwrap()
+}
+
+#[test]
+fn floor_division_and_remainder() {
+    for overflow in [
+        None,
+        Some(OverflowMode::Wrapping),
+        Some(OverflowMode::Checked),
+    ] {
+        assert_eq!(evaluate(overflow, "7 // 2"), 3.);
+        assert_eq!(evaluate(overflow, "-7 // 2"), -4.);
+        assert_eq!(evaluate(overflow, "7 % -2"), -1.);
+        assert_eq!(evaluate(overflow, "-7 % 2"), 1.);
+        assert!(evaluate(overflow, "5 % 0").is_nan());
+    }
+
+    // integer division by zero has no result, float division follows IEEE 754
+    assert!(evaluate(Some(OverflowMode::Wrapping), "5 // 0").is_nan());
+    assert_eq!(evaluate(None, "5 // 0"), f64::INFINITY);
+
+    assert_eq!(evaluate(None, "7.5 // 2"), 3.);
+    assert_eq!(evaluate(None, "-7.5 % 2"), 0.5);
+}
+
+#[test]
+fn division_is_float() {
+    assert_eq!(evaluate(Some(OverflowMode::Wrapping), "7 / 2"), 3.5);
+}
+
+#[test]
+fn overflow() {
+    let max = "2147483647 + 1";
+    assert_eq!(
+        evaluate(Some(OverflowMode::Wrapping), max),
+        f64::from(i32::MIN)
+    );
+    assert!(evaluate(Some(OverflowMode::Checked), max).is_nan());
+    assert_eq!(evaluate(None, max), 2147483648.);
+
+    assert_eq!(
+        evaluate(Some(OverflowMode::Wrapping), "(-2147483647 - 1) // -1"),
+        f64::from(i32::MIN)
+    );
+    assert!(evaluate(Some(OverflowMode::Checked), "(-2147483647 - 1) // -1").is_nan());
+}
+
+#[test]
+fn bitwise_operators() {
+    let overflow = Some(OverflowMode::Wrapping);
+    assert_eq!(evaluate(overflow, "6 & 3"), 2.);
+    assert_eq!(evaluate(overflow, "6 | 3"), 7.);
+    assert_eq!(evaluate(overflow, "6 ^ 3"), 5.);
+    assert_eq!(evaluate(overflow, "1 << 4"), 16.);
+    assert_eq!(evaluate(overflow, "-16 >> 2"), -4.);
+    // like in Rust, shifts bind stronger than `&`, which binds stronger than `^` and `|`
+    assert_eq!(evaluate(overflow, "1 | 2 ^ 6 & 3 << 1"), 5.);
+}
+
+#[test]
+fn integer_parameters() {
+    let ast = Ast::builder("expression".to_string(), &parameters(&["a", "b"]))
+        .parameter_type("a", DataType::I16)
+        .integer_arithmetic(OverflowMode::Checked)
+        .build("a & 255")
+        .unwrap();
+    assert_eq!(ast.evaluate(&[1000., 0.]).unwrap(), 232.);
+
+    let error = Ast::builder("expression".to_string(), &parameters(&["a", "b"]))
+        .parameter_type("a", DataType::I16)
+        .integer_arithmetic(OverflowMode::Checked)
+        .build("a & b");
+    assert!(matches!(error, Err(ExpressionError::FloatOperand { .. })));
+}
+
+#[test]
+fn float_operands() {
+    for expression in ["1 & 2", "1.5 << 1", "1 | 2.0"] {
+        let error = Ast::new("expression".to_string(), &[], expression);
+        assert!(
+            matches!(error, Err(ExpressionError::FloatOperand { .. })),
+            "{}",
+            expression
+        );
+    }
+
+    let error = Ast::builder("expression".to_string(), &[])
+        .integer_arithmetic(OverflowMode::Wrapping)
+        .build("1.5 << 1");
+    assert!(matches!(error, Err(ExpressionError::FloatOperand { .. })));
+}
@@ -0,0 +1,91 @@
+use math_expr::{Ast, AstNode, AstNodeKind};
+use quote::ToTokens;
+
+fn build(source: &str) -> Ast {
+    Ast::new("expression".to_string(), &[], source).unwrap()
+}
+
+/// The literal, which is the expression of the root block
+fn literal(ast: &Ast) -> &AstNode {
+    match ast.root().kind() {
+        AstNodeKind::AssignmentsAndExpression { expression, .. } => expression,
+        kind => panic!("unexpected root {:?}", kind),
+    }
+}
+
+/// Reads the value back from the emitted Rust tokens, e.g. `(- 5e-324f64)` or `f64 :: NAN`
+fn emitted(node: &AstNode) -> f64 {
+    let code = node.to_token_stream().to_string().replace(' ', "");
+
+    let (negative, code) = match code.strip_prefix("(-") {
+        Some(code) => (true, code.trim_end_matches(')')),
+        None => (false, code.as_str()),
+    };
+
+    let value = match code {
+        "f64::NAN" => f64::NAN,
+        "f64::INFINITY" => f64::INFINITY,
+        "f64::NEG_INFINITY" => f64::NEG_INFINITY,
+        code => code
+            .strip_suffix("f64")
+            .unwrap_or_else(|| panic!("expected a f64 literal, got `{}`", code))
+            .parse()
+            .unwrap(),
+    };
+
+    if negative {
+        -value
+    } else {
+        value
+    }
+}
+
+#[test]
+fn literals_round_trip() {
+    for (source, expected) in [
+        ("5e-324", 5e-324),
+        ("1.7976931348623157e308", f64::MAX),
+        ("0.1", 0.1),
+        ("6.02E23", 6.02e23),
+        (".5", 0.5),
+        ("5.", 5.),
+        ("1e-3", 0.001),
+        ("-5e-324", -5e-324),
+        ("-0.1", -0.1),
+        ("nan", f64::NAN),
+        ("inf", f64::INFINITY),
+        ("-inf", f64::NEG_INFINITY),
+    ] {
+        let ast = build(source);
+        let node = literal(&ast);
+
+        let value = match node.kind() {
+            AstNodeKind::Constant(value) => *value,
+            kind => panic!("`{}` is not a float constant: {:?}", source, kind),
+        };
+        assert_eq!(value.to_bits(), expected.to_bits(), "{}", source);
+        assert_eq!(emitted(node).to_bits(), expected.to_bits(), "{}", source);
+    }
+}
+
+#[test]
+fn integers_have_no_fraction_or_exponent() {
+    for (source, expected) in [("1000", 1000), ("-3", -3)] {
+        let ast = build(source);
+        assert!(
+            matches!(literal(&ast).kind(), AstNodeKind::Integer(n) if *n == expected),
+            "{}",
+            source
+        );
+    }
+
+    // with a fraction or an exponent, or too large for `i32`
+    for source in ["1e-3", "1e3", "5.", "3000000000"] {
+        let ast = build(source);
+        assert!(
+            matches!(literal(&ast).kind(), AstNodeKind::Constant(_)),
+            "{}",
+            source
+        );
+    }
+}
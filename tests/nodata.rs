@@ -0,0 +1,113 @@
+use math_expr::{Ast, DataType};
+use quote::ToTokens;
+
+fn parameters(names: &[&str]) -> Vec<String> {
+    names.iter().map(ToString::to_string).collect()
+}
+
+/// `a` has the no-data value `-9999` and `b` is valid where `m` is not zero
+fn build(expression: &str) -> Ast {
+    Ast::builder("expression".to_string(), &parameters(&["a", "b", "m"]))
+        .parameter_nodata("a", -9999.)
+        .parameter_mask("b", "m")
+        .build(expression)
+        .unwrap()
+}
+
+#[test]
+fn operations_pass_nodata_on() {
+    for expression in [
+        "(a - b) / (a + b)",
+        "max(a, b)",
+        "clamp(a, b, 10)",
+        "pow(b, a) + pow(1, b)",
+    ] {
+        let ast = build(expression);
+        assert!(
+            ast.evaluate(&[-9999., 1., 1.]).unwrap().is_nan(),
+            "{}",
+            expression
+        );
+        assert!(
+            ast.evaluate(&[1., 2., 0.]).unwrap().is_nan(),
+            "{}",
+            expression
+        );
+        assert!(
+            !ast.evaluate(&[1., 2., 1.]).unwrap().is_nan(),
+            "{}",
+            expression
+        );
+    }
+}
+
+#[test]
+fn replaced_calls_are_not_imported() {
+    let code = build("max(a, b)").to_token_stream().to_string();
+    assert!(code.contains("import_max_nodata "), "{}", code);
+    assert!(!code.contains("import_max "), "{}", code);
+}
+
+#[test]
+fn conditions() {
+    let ast = build("if a > 0 { 1 } else { 2 }");
+    assert_eq!(ast.evaluate(&[1., 0., 0.]).unwrap(), 1.);
+    assert_eq!(ast.evaluate(&[-1., 0., 0.]).unwrap(), 2.);
+    assert!(ast.evaluate(&[-9999., 0., 0.]).unwrap().is_nan());
+
+    // conditions are only checked if the branches before them are not taken
+    let ast = build("if isnodata(a) == 1 { 0 } else if a > b { 1 } else { 2 }");
+    assert_eq!(ast.evaluate(&[-9999., 1., 0.]).unwrap(), 0.);
+    assert!(ast.evaluate(&[5., 1., 0.]).unwrap().is_nan());
+    assert_eq!(ast.evaluate(&[5., 1., 1.]).unwrap(), 1.);
+}
+
+#[test]
+fn explicit_handling() {
+    let ast = build("coalesce(a, 0) + coalesce(b, 10)");
+    assert_eq!(ast.evaluate(&[-9999., 1., 0.]).unwrap(), 10.);
+    assert_eq!(ast.evaluate(&[2., 1., 1.]).unwrap(), 3.);
+
+    let ast = build("isnodata(a) + isnodata(b)");
+    assert_eq!(ast.evaluate(&[-9999., 1., 0.]).unwrap(), 2.);
+    assert_eq!(ast.evaluate(&[-9998., 1., f64::NAN]).unwrap(), 1.);
+}
+
+#[test]
+fn output_nodata() {
+    let ast = Ast::builder("expression".to_string(), &parameters(&["a", "b"]))
+        .parameter_type("a", DataType::U8)
+        .parameter_nodata("a", 0.)
+        .output_type(DataType::U8)
+        .output_nodata(255.)
+        .build("a / b")
+        .unwrap();
+
+    assert_eq!(ast.evaluate(&[0., 1.]).unwrap(), 255.);
+    assert_eq!(ast.evaluate(&[10., 2.]).unwrap(), 5.);
+    // invalid results are no-data too
+    assert_eq!(ast.evaluate(&[10., f64::NAN]).unwrap(), 255.);
+}
+
+#[test]
+fn masks_with_nodata() {
+    // `m` comes after `a` and is read with its own no-data value
+    let ast = Ast::builder("expression".to_string(), &parameters(&["a", "m"]))
+        .parameter_mask("a", "m")
+        .parameter_nodata("m", -9999.)
+        .build("a")
+        .unwrap();
+    assert_eq!(ast.evaluate(&[5., 1.]).unwrap(), 5.);
+    assert!(ast.evaluate(&[5., 0.]).unwrap().is_nan());
+    assert!(ast.evaluate(&[5., -9999.]).unwrap().is_nan());
+
+    // `b` is no-data where `m` is zero, so `a` is too
+    let ast = Ast::builder("expression".to_string(), &parameters(&["a", "b", "m"]))
+        .parameter_mask("a", "b")
+        .parameter_mask("b", "m")
+        .build("a")
+        .unwrap();
+    assert_eq!(ast.evaluate(&[5., 1., 1.]).unwrap(), 5.);
+    assert!(ast.evaluate(&[5., 0., 1.]).unwrap().is_nan());
+    assert!(ast.evaluate(&[5., 1., 0.]).unwrap().is_nan());
+}
@@ -0,0 +1,253 @@
+use math_expr::{Ast, DataType, FunctionRegistry, OpenClError, UserFunction};
+
+fn parameters(names: &[&str]) -> Vec<String> {
+    names.iter().map(ToString::to_string).collect()
+}
+
+fn kernel(name: &str, names: &[&str], expression: &str) -> String {
+    Ast::new(name.to_string(), &parameters(names), expression)
+        .unwrap()
+        .opencl()
+        .unwrap()
+}
+
+#[test]
+fn ndvi() {
+    assert_eq!(
+        kernel("ndvi", &["a", "b"], "(a - b) / (a + b)"),
+        r#"#pragma OPENCL EXTENSION cl_khr_fp64 : enable
+
+__kernel void ndvi(__global double* output_values, __global const double* input_a, __global const double* input_b) {
+    const size_t global_id = get_global_id(0);
+    output_values[global_id] = ((input_a[global_id] - input_b[global_id]) / (input_a[global_id] + input_b[global_id]));
+}
+"#
+    );
+}
+
+#[test]
+fn assignments_and_branches() {
+    assert_eq!(
+        kernel(
+            "expression",
+            &["a"],
+            "let x = a * 2; if x > 10 && !(x == 12) { x } else if x < 0 { -x } else { 0.5 }"
+        ),
+        r#"#pragma OPENCL EXTENSION cl_khr_fp64 : enable
+
+__kernel void expression(__global double* output_values, __global const double* input_a) {
+    const size_t global_id = get_global_id(0);
+    const double x_0 = (input_a[global_id] * 2.0);
+    double branch_1;
+    if ((x_0 > 10.0) && (!(x_0 == 12.0))) {
+        branch_1 = x_0;
+    } else {
+        if (x_0 < 0.0) {
+            branch_1 = (-x_0);
+        } else {
+            branch_1 = 0.5;
+        }
+    }
+    output_values[global_id] = branch_1;
+}
+"#
+    );
+}
+
+#[test]
+fn block_scoped_variables() {
+    assert_eq!(
+        kernel(
+            "expression",
+            &["a"],
+            "if a > 0 { let x = a * 2; x + 1 } else { let x = -a; x }"
+        ),
+        r#"#pragma OPENCL EXTENSION cl_khr_fp64 : enable
+
+__kernel void expression(__global double* output_values, __global const double* input_a) {
+    const size_t global_id = get_global_id(0);
+    double branch_0;
+    if (input_a[global_id] > 0.0) {
+        const double x_1 = (input_a[global_id] * 2.0);
+        branch_0 = (x_1 + 1.0);
+    } else {
+        const double x_2 = (-input_a[global_id]);
+        branch_0 = x_2;
+    }
+    output_values[global_id] = branch_0;
+}
+"#
+    );
+}
+
+#[test]
+fn builtin_functions() {
+    assert_eq!(
+        kernel("expression", &["a", "b"], "max(a, b) * sign(a) + tau()"),
+        r#"#pragma OPENCL EXTENSION cl_khr_fp64 : enable
+
+double import_max(double a, double b) {
+    return fmax(a, b);
+}
+
+double import_sign(double a) {
+    return a > 0.0 ? 1.0 : a < 0.0 ? -1.0 : a;
+}
+
+__kernel void expression(__global double* output_values, __global const double* input_a, __global const double* input_b) {
+    const size_t global_id = get_global_id(0);
+    output_values[global_id] = ((import_max(input_a[global_id], input_b[global_id]) * import_sign(input_a[global_id])) + 6.283185307179586);
+}
+"#
+    );
+}
+
+#[test]
+fn data_types() {
+    let ast = Ast::builder("scale".to_string(), &parameters(&["a", "b"]))
+        .parameter_type("a", DataType::U8)
+        .parameter_type("b", DataType::F32)
+        .output_type(DataType::U16)
+        .build("a * b")
+        .unwrap();
+
+    assert_eq!(
+        ast.opencl().unwrap(),
+        r#"#pragma OPENCL EXTENSION cl_khr_fp64 : enable
+
+__kernel void scale(__global ushort* output_values, __global const uchar* input_a, __global const float* input_b) {
+    const size_t global_id = get_global_id(0);
+    output_values[global_id] = convert_ushort_sat_rtz((((double)input_a[global_id]) * ((double)input_b[global_id])));
+}
+"#
+    );
+}
+
+#[test]
+fn user_functions() {
+    let mut functions = FunctionRegistry::new();
+    functions
+        .register(UserFunction::expression(
+            Ast::new(
+                "ndvi".to_string(),
+                &parameters(&["nir", "red"]),
+                "(nir - red) / (nir + red)",
+            )
+            .unwrap(),
+        ))
+        .unwrap();
+    functions
+        .register(UserFunction::expression(
+            Ast::builder("positivendvi".to_string(), &parameters(&["nir", "red"]))
+                .functions(functions.clone())
+                .build("let value = ndvi(nir, red); max(value, 0)")
+                .unwrap(),
+        ))
+        .unwrap();
+
+    let ast = Ast::builder("expression".to_string(), &parameters(&["a", "b"]))
+        .functions(functions)
+        .build("positivendvi(a, b)")
+        .unwrap();
+
+    assert_eq!(
+        ast.opencl().unwrap(),
+        r#"#pragma OPENCL EXTENSION cl_khr_fp64 : enable
+
+double import_ndvi(double input_nir, double input_red) {
+    return ((input_nir - input_red) / (input_nir + input_red));
+}
+
+double import_max(double a, double b) {
+    return fmax(a, b);
+}
+
+double import_positivendvi(double input_nir, double input_red) {
+    const double value_0 = import_ndvi(input_nir, input_red);
+    return import_max(value_0, 0.0);
+}
+
+__kernel void expression(__global double* output_values, __global const double* input_a, __global const double* input_b) {
+    const size_t global_id = get_global_id(0);
+    output_values[global_id] = import_positivendvi(input_a[global_id], input_b[global_id]);
+}
+"#
+    );
+}
+
+#[test]
+fn rust_functions_are_not_supported() {
+    let mut functions = FunctionRegistry::new();
+    functions
+        .register(UserFunction::rust(
+            "double".to_string(),
+            &parameters(&["x"]),
+            "x * 2.".to_string(),
+        ))
+        .unwrap();
+
+    let ast = Ast::builder("expression".to_string(), &parameters(&["a"]))
+        .functions(functions)
+        .build("double(a)")
+        .unwrap();
+
+    assert_eq!(
+        ast.opencl(),
+        Err(OpenClError::RustFunction {
+            name: "double".to_string()
+        })
+    );
+}
+
+#[test]
+fn keywords_of_opencl_are_prefixed() {
+    let mut functions = FunctionRegistry::new();
+    functions
+        .register(UserFunction::expression(
+            Ast::new(
+                "scale".to_string(),
+                &parameters(&["global", "double"]),
+                "global * double",
+            )
+            .unwrap(),
+        ))
+        .unwrap();
+
+    let ast = Ast::builder("expression".to_string(), &parameters(&["int", "kernel"]))
+        .functions(functions)
+        .build("let local = scale(int, kernel); local")
+        .unwrap();
+
+    assert_eq!(
+        ast.opencl().unwrap(),
+        r#"#pragma OPENCL EXTENSION cl_khr_fp64 : enable
+
+double import_scale(double input_global, double input_double) {
+    return (input_global * input_double);
+}
+
+__kernel void expression(__global double* output_values, __global const double* input_int, __global const double* input_kernel) {
+    const size_t global_id = get_global_id(0);
+    const double local_0 = import_scale(input_int[global_id], input_kernel[global_id]);
+    output_values[global_id] = local_0;
+}
+"#
+    );
+}
+
+#[test]
+fn keywords_of_opencl_are_not_kernel_names() {
+    for name in ["kernel", "int", "double4", "uchar16"] {
+        assert_eq!(
+            Ast::new(name.to_string(), &[], "1").unwrap().opencl(),
+            Err(OpenClError::InvalidKernelName {
+                name: name.to_string()
+            })
+        );
+    }
+
+    assert!(Ast::new("double5".to_string(), &[], "1")
+        .unwrap()
+        .opencl()
+        .is_ok());
+}
@@ -0,0 +1,174 @@
+use math_expr::{Ast, AstNodeKind, Optimization, Optimizer};
+use quote::ToTokens;
+
+fn parameters(names: &[&str]) -> Vec<String> {
+    names.iter().map(ToString::to_string).collect()
+}
+
+fn build(expression: &str) -> Ast {
+    Ast::new(
+        "expression".to_string(),
+        &parameters(&["a", "b"]),
+        expression,
+    )
+    .unwrap()
+}
+
+fn constant(ast: &Ast) -> Option<f64> {
+    match ast.root().kind() {
+        AstNodeKind::Constant(n) => Some(*n),
+        AstNodeKind::Integer(n) => Some(f64::from(*n)),
+        _ => None,
+    }
+}
+
+#[test]
+fn same_results() {
+    for expression in [
+        "1 + 2 / 3 + a",
+        "a * 1 + 1 * b - 0",
+        "-(-a) / 1 + b * -1",
+        "pow(a, 2) + pow(a + b, 2) + pow(b, 1) + pow(a, 0)",
+        "let c = 2; let d = c * 3; a * d + c",
+        "if 1 < 2 && a > b { a } else if false { b } else { max(a, 2 ** 3) }",
+        "if !(true xor a > 0) { 1 } else { 2 }",
+    ] {
+        let ast = build(expression);
+        let (optimized, report) = ast.optimize();
+        assert!(!report.is_empty(), "{}", expression);
+
+        for parameters in [
+            [1., 2.],
+            [-3., 0.5],
+            [0., -0.],
+            [f64::NAN, 1.],
+            [f64::INFINITY, 2.],
+        ] {
+            let expected = ast.evaluate(&parameters).unwrap();
+            let actual = optimized.evaluate(&parameters).unwrap();
+            assert!(
+                expected.to_bits() == actual.to_bits() || (expected.is_nan() && actual.is_nan()),
+                "{} with {:?}: {} != {}",
+                expression,
+                parameters,
+                expected,
+                actual
+            );
+        }
+    }
+}
+
+#[test]
+fn constants() {
+    let (optimized, report) = build("1 + 2 / 4").optimize();
+    assert_eq!(constant(&optimized), Some(1.5));
+    // nested folds are reported once
+    assert_eq!(report.changes().len(), 1);
+
+    let (optimized, report) = build("let c = 1.2; let d = 2; c + d + 1").optimize();
+    assert_eq!(constant(&optimized), Some(4.2));
+    assert!(report.changes().iter().any(
+        |change| matches!(change, Optimization::ConstantPropagated { name, .. } if name == "d")
+    ));
+
+    let (optimized, _) = build("sqrt(16) + floor(pi)").optimize();
+    assert_eq!(constant(&optimized), Some(7.));
+    // the folded calls are not imported anymore
+    let code = optimized.to_token_stream().to_string();
+    assert!(!code.contains("import_sqrt"), "{}", code);
+}
+
+#[test]
+fn branches() {
+    let (optimized, report) = build("if true { 1 } else { 2 }").optimize();
+    assert_eq!(constant(&optimized), Some(1.));
+    assert!(matches!(
+        report.changes()[0],
+        Optimization::BranchRemoved {
+            condition: true,
+            ..
+        }
+    ));
+
+    let (optimized, _) = build("if false || 1 > 2 { a } else if a < 0 { 1 } else { 2 }").optimize();
+    match optimized.root().kind() {
+        AstNodeKind::Branch {
+            condition_branches, ..
+        } => assert_eq!(condition_branches.len(), 1),
+        kind => panic!("expected a branch, got {:?}", kind),
+    }
+}
+
+#[test]
+fn fast_math() {
+    let ast = build("a * 0 + (b + 0)");
+
+    let (optimized, _) = ast.optimize();
+    assert!(optimized.evaluate(&[f64::NAN, 1.]).unwrap().is_nan());
+    assert!(optimized.evaluate(&[1., -0.]).unwrap().is_sign_positive());
+
+    let (optimized, report) = Optimizer::new().fast_math(true).optimize(&ast);
+    assert!(matches!(optimized.root().kind(), AstNodeKind::Variable(b) if b == "b"));
+    assert_eq!(report.changes().len(), 3);
+    assert_eq!(optimized.evaluate(&[f64::NAN, 1.]).unwrap(), 1.);
+}
+
+fn eliminated(ast: &Ast) -> Vec<usize> {
+    ast.optimize()
+        .1
+        .changes()
+        .iter()
+        .filter_map(|change| match change {
+            Optimization::SubexpressionEliminated { occurrences, .. } => Some(*occurrences),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn common_subexpressions() {
+    let ast = build("(a - b) / (a + b) * (a - b)");
+    assert_eq!(eliminated(&ast), vec![2]);
+    assert_eq!(
+        ast.optimize().0.evaluate(&[3., 1.]).unwrap(),
+        ast.evaluate(&[3., 1.]).unwrap()
+    );
+
+    // the call is computed once before the branch
+    let ast = build("if a > 0 { sqrt(a*a + b*b) } else if b > 0 { 1 } else { -sqrt(a*a + b*b) }");
+    assert_eq!(eliminated(&ast), vec![2]);
+    let (optimized, _) = ast.optimize();
+    match optimized.root().kind() {
+        AstNodeKind::AssignmentsAndExpression {
+            assignments,
+            expression,
+        } => {
+            assert_eq!(assignments[0].identifier(), "common_0");
+            assert!(matches!(expression.kind(), AstNodeKind::Branch { .. }));
+        }
+        kind => panic!("expected a block, got {:?}", kind),
+    }
+    for parameters in [[3., 4.], [-3., 4.], [-3., -4.]] {
+        assert_eq!(
+            optimized.evaluate(&parameters).unwrap(),
+            ast.evaluate(&parameters).unwrap()
+        );
+    }
+
+    // nested subexpressions are found in the binding of the larger one
+    let ast = build("(a + b) * 2 + (a + b) * 2 + (a + b)");
+    assert_eq!(eliminated(&ast), vec![2, 2]);
+
+    // the same names that refer to different variables
+    let ast = build("let c = a + 1; let d = c * 2; let c = b; c * 2 + d");
+    assert!(eliminated(&ast).is_empty());
+
+    let (optimized, report) = Optimizer::new()
+        .common_subexpressions(false)
+        .optimize(&build("(a - b) * (a - b)"));
+    assert!(report.is_empty());
+    assert!(matches!(
+        optimized.root().kind(),
+        AstNodeKind::Operation { .. }
+    ));
+}
@@ -0,0 +1,126 @@
+use math_expr::{Ast, BytecodeExpression, ExpressionError, JitExpression, Warning};
+
+fn parameters(names: &[&str]) -> Vec<String> {
+    names.iter().map(ToString::to_string).collect()
+}
+
+fn build(expression: &str) -> Result<Ast, ExpressionError> {
+    Ast::new("expression".to_string(), &parameters(&["a"]), expression)
+}
+
+fn evaluate(expression: &str) -> f64 {
+    build(expression).unwrap().evaluate(&[10.]).unwrap()
+}
+
+#[test]
+fn use_before_assignment() {
+    let source = "let x = y; let y = 1; x";
+    match build(source) {
+        Err(ExpressionError::UseBeforeAssignment {
+            name,
+            span,
+            assignment,
+        }) => {
+            assert_eq!(name, "y");
+            assert_eq!(&source[span.start()..span.end()], "y");
+            assert_eq!(&source[assignment.start()..assignment.end()], "let y = 1;");
+        }
+        result => panic!("expected use before assignment, got {:?}", result),
+    }
+
+    // a variable is not visible in its own value
+    assert!(matches!(
+        build("let x = x + 1; x"),
+        Err(ExpressionError::UseBeforeAssignment { name, .. }) if name == "x"
+    ));
+
+    // the hint points to the assignment
+    let error = build(source).unwrap_err();
+    assert_eq!(
+        error.diagnostic().hint(),
+        Some("move `let y = ...;` before this use")
+    );
+}
+
+#[test]
+fn shadowing() {
+    // the value of an assignment reads the previous variable of the same name
+    assert_eq!(evaluate("let x = a; let x = x + 1; x * 2"), 22.);
+    assert_eq!(evaluate("let x = 1; let y = x; let x = 5; x + y"), 6.);
+
+    // variables shadow named constants
+    assert_eq!(evaluate("let pi = 3; pi"), 3.);
+    // a later variable makes the name ambiguous before its assignment
+    assert!(matches!(
+        build("let x = pi; let pi = 3; x"),
+        Err(ExpressionError::UseBeforeAssignment { .. })
+    ));
+
+    // but not parameters
+    assert!(matches!(
+        build("let a = 1; a"),
+        Err(ExpressionError::AssignmentToParameter { .. })
+    ));
+}
+
+#[test]
+fn unknown_variables() {
+    match build("let value = 1; valu") {
+        Err(ExpressionError::UnknownVariable { suggestion, .. }) => {
+            assert_eq!(suggestion.as_deref(), Some("value"));
+        }
+        result => panic!("expected an unknown variable, got {:?}", result),
+    }
+}
+
+#[test]
+fn branch_bodies() {
+    let expression = "if a > 0 { let x = a * 2; x + 1 } else { let x = -a; x }";
+    let ast = build(expression).unwrap();
+    let bytecode = BytecodeExpression::new(&ast).unwrap();
+    let jit = JitExpression::new(&ast).unwrap();
+
+    for (parameter, expected) in [(10., 21.), (-3., 3.)] {
+        assert_eq!(ast.evaluate(&[parameter]).unwrap(), expected);
+        assert_eq!(bytecode.call(&[parameter]).unwrap(), expected);
+        assert_eq!(jit.call(&[parameter]).unwrap(), expected);
+    }
+
+    // every body has its own locals
+    let wat = ast.wat().unwrap();
+    assert!(wat.contains("local.set $x_0"), "{}", wat);
+    assert!(wat.contains("local.set $x_1"), "{}", wat);
+
+    // a body can shadow the variables around the branch
+    assert_eq!(
+        evaluate("let x = 1; let y = if a > 0 { let x = x + a; x } else { x }; x + y"),
+        12.
+    );
+    assert_eq!(
+        evaluate("if a > 0 { let y = a; if y > 5 { let z = y * 2; z } else { y } } else { 0 }"),
+        20.
+    );
+}
+
+#[test]
+fn block_scopes() {
+    // the variables of a body are not visible after the branch
+    assert!(matches!(
+        build("if a > 0 { let x = 1; x } else { 2 } + x"),
+        Err(ExpressionError::UnknownVariable { name, .. }) if name == "x"
+    ));
+    assert!(matches!(
+        build("if a > 0 { let x = 1; x } else { x }"),
+        Err(ExpressionError::UnknownVariable { name, .. }) if name == "x"
+    ));
+
+    assert!(matches!(
+        build("if a > 0 { let x = y; let y = 1; x } else { 0 }"),
+        Err(ExpressionError::UseBeforeAssignment { name, .. }) if name == "y"
+    ));
+
+    assert!(matches!(
+        build("if a > 0 { let b = 1; a } else { 0 }").unwrap().warnings(),
+        [Warning::UnusedVariable { name, .. }] if name == "b"
+    ));
+}
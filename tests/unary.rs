@@ -0,0 +1,119 @@
+use math_expr::{Ast, AstNode, AstNodeKind};
+
+fn build(expression: &str) -> Ast {
+    Ast::new(
+        "expression".to_string(),
+        &["a".to_string(), "b".to_string()],
+        expression,
+    )
+    .unwrap()
+}
+
+fn evaluate(expression: &str, a: f64) -> f64 {
+    build(expression).evaluate(&[a, 0.]).unwrap()
+}
+
+/// The expression of the root block
+fn expression(ast: &Ast) -> &AstNodeKind {
+    match ast.root().kind() {
+        AstNodeKind::AssignmentsAndExpression { expression, .. } => expression.kind(),
+        kind => panic!("unexpected root {:?}", kind),
+    }
+}
+
+fn negated(node: &AstNode) -> &AstNodeKind {
+    match node.kind() {
+        AstNodeKind::Negation(operand) => operand.kind(),
+        kind => panic!("expected a negation, got {:?}", kind),
+    }
+}
+
+fn is_variable(kind: &AstNodeKind, variable: &str) -> bool {
+    matches!(kind, AstNodeKind::Variable(name) if name == variable)
+}
+
+#[test]
+fn minus_binds_weaker_than_power() {
+    match expression(&build("-a**b")) {
+        AstNodeKind::Negation(operand) => {
+            assert!(matches!(operand.kind(), AstNodeKind::Function { name, .. } if name == "pow"))
+        }
+        kind => panic!("expected a negation, got {:?}", kind),
+    }
+
+    match expression(&build("(-a)**b")) {
+        AstNodeKind::Function { name, args } if name == "pow" => {
+            assert!(is_variable(negated(&args[0]), "a"));
+            assert!(is_variable(args[1].kind(), "b"));
+        }
+        kind => panic!("expected a power, got {:?}", kind),
+    }
+}
+
+#[test]
+fn negative_exponents() {
+    match expression(&build("a**-b")) {
+        AstNodeKind::Function { name, args } if name == "pow" => {
+            assert!(is_variable(args[0].kind(), "a"));
+            assert!(is_variable(negated(&args[1]), "b"));
+        }
+        kind => panic!("expected a power, got {:?}", kind),
+    }
+}
+
+#[test]
+fn negative_literals() {
+    // the sign is part of the literal
+    for source in ["-2", "-2.5", "-(2)"] {
+        assert!(
+            !matches!(expression(&build(source)), AstNodeKind::Negation(_)),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn repeated_signs() {
+    match expression(&build("--a")) {
+        AstNodeKind::Negation(operand) => {
+            assert!(matches!(operand.kind(), AstNodeKind::Negation(_)))
+        }
+        kind => panic!("expected a negation, got {:?}", kind),
+    }
+
+    match expression(&build("-+-a")) {
+        AstNodeKind::Negation(operand) => assert!(is_variable(negated(operand), "a")),
+        kind => panic!("expected a negation, got {:?}", kind),
+    }
+
+    assert!(is_variable(expression(&build("+a")), "a"));
+
+    match expression(&build("b * -a")) {
+        AstNodeKind::Operation { left, right, .. } => {
+            assert!(is_variable(left.kind(), "b"));
+            assert!(is_variable(negated(right), "a"));
+        }
+        kind => panic!("expected an operation, got {:?}", kind),
+    }
+}
+
+#[test]
+fn evaluation() {
+    assert_eq!(evaluate("-2**2", 0.), -4.);
+    assert_eq!(evaluate("if -2**2 == -4 { 1 } else { 0 }", 0.), 1.);
+    assert_eq!(evaluate("(-2)**2", 0.), 4.);
+    assert_eq!(evaluate("-a**2", 3.), -9.);
+
+    assert_eq!(evaluate("2**-1", 0.), 0.5);
+    assert_eq!(evaluate("2**-a", 2.), 0.25);
+    // the exponent of `**` is right associative and includes the sign
+    assert_eq!(evaluate("2**-1**2", 0.), 0.5);
+
+    assert_eq!(evaluate("--a", 3.), 3.);
+    assert_eq!(evaluate("-+-a", 3.), 3.);
+    assert_eq!(evaluate("+a", 3.), 3.);
+    assert_eq!(evaluate("2 * -a", 3.), -6.);
+    assert_eq!(evaluate("1 - -a", 3.), 4.);
+    assert_eq!(evaluate("-(a + 1)", 3.), -4.);
+}
@@ -0,0 +1,92 @@
+use math_expr::{Ast, Optimization, Severity, Span, Warning};
+
+fn parameters(names: &[&str]) -> Vec<String> {
+    names.iter().map(ToString::to_string).collect()
+}
+
+fn build(expression: &str) -> Ast {
+    Ast::new(
+        "expression".to_string(),
+        &parameters(&["a", "b", "c"]),
+        expression,
+    )
+    .unwrap()
+}
+
+#[test]
+fn warnings() {
+    let source = "let x = a; let y = b; let x = 2; x + y";
+    let ast = build(source);
+
+    assert_eq!(
+        ast.warnings(),
+        &[
+            Warning::UnusedParameter {
+                name: "c".to_string(),
+                span: ast.root().span(),
+            },
+            Warning::OverwrittenVariable {
+                name: "x".to_string(),
+                span: Span::new(0, 10),
+            },
+        ]
+    );
+
+    let diagnostic = ast.warnings()[1].diagnostic();
+    assert_eq!(diagnostic.severity(), Severity::Warning);
+    assert!(diagnostic.render(source).starts_with("warning: "));
+
+    let ast = build("let x = a + b; let y = x * 2; c");
+    assert_eq!(
+        ast.warnings()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>(),
+        vec!["unused variable `y`"]
+    );
+
+    assert!(build("a + b + c").warnings().is_empty());
+}
+
+#[test]
+fn used_parameters() {
+    // `a` and `b` are only read by the unused `y`
+    let ast = build("let x = a + b; let y = x * 2; c");
+    assert_eq!(ast.used_parameters(), vec!["c"]);
+
+    assert_eq!(
+        build("if a > 0 { b } else { 1 }").used_parameters(),
+        vec!["a", "b"]
+    );
+
+    // the mask is needed to know where `a` is valid
+    let ast = Ast::builder("expression".to_string(), &parameters(&["a", "b", "m"]))
+        .parameter_nodata("b", 0.)
+        .parameter_mask("a", "m")
+        .build("a * 2")
+        .unwrap();
+    assert!(matches!(
+        ast.warnings(),
+        [Warning::UnusedParameter { name, .. }] if name == "b"
+    ));
+    assert_eq!(ast.used_parameters(), vec!["a", "m"]);
+}
+
+#[test]
+fn dead_assignments() {
+    let ast = build("let x = a + b; let y = x * 2; c");
+    let (optimized, report) = ast.optimize();
+
+    let removed = report
+        .changes()
+        .iter()
+        .filter_map(|change| match change {
+            Optimization::DeadAssignmentRemoved { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(removed, vec!["y", "x"]);
+
+    assert_eq!(optimized.evaluate(&[1., 2., 3.]).unwrap(), 3.);
+    assert_eq!(optimized.used_parameters(), vec!["c"]);
+}